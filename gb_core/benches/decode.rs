@@ -0,0 +1,48 @@
+// instructions per second of the cpu on a flat bus, run with `cargo bench -p gb_core`
+use gb_core::core::{Bus, FlatBus, CPU};
+use std::time::Instant;
+
+const INSTRUCTIONS: u32 = 10_000_000;
+
+// a loop mixing loads, ALU, CB prefixed and control flow instructions
+const PROGRAM: [u8; 24] = [
+    0x21, 0x00, 0xC0, // LD HL, 0xC000
+    0x3E, 0x12, // LD A, 0x12
+    0x80, // ADD A, B
+    0x0C, // INC C
+    0x77, // LD (HL), A
+    0x23, // INC HL
+    0xAF, // XOR A
+    0xCB, 0x37, // SWAP A
+    0xCB, 0x7C, // BIT 7, H
+    0x1B, // DEC DE
+    0xC5, // PUSH BC
+    0xC1, // POP BC
+    0x86, // ADD A, (HL)
+    0xFE, 0x10, // CP 0x10
+    0x18, 0xEA, // JR -22
+    0x00, 0x00,
+];
+
+fn main() {
+    let mut cpu = CPU::with_bus(FlatBus::new());
+    for (i, byte) in PROGRAM.iter().enumerate() {
+        cpu.memory_bus.write_byte(0x0100 + i as u16, *byte);
+    }
+    cpu.pc = 0x0100;
+    cpu.sp = 0xFFFE;
+
+    let start = Instant::now();
+    let mut cycles = 0u64;
+    for _ in 0..INSTRUCTIONS {
+        cycles += cpu.tick().unwrap() as u64;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "{} instructions in {:.3}s: {:.1} million instructions per second, {:.1}x real time",
+        INSTRUCTIONS,
+        elapsed,
+        INSTRUCTIONS as f64 / elapsed / 1_000_000.0,
+        cycles as f64 / 4_194_304.0 / elapsed
+    );
+}
@@ -0,0 +1,77 @@
+// generate one test per mooneye test rom, see src/tests/mooneye_tests.rs
+// the roms are searched in MOONEYE_ROMS, or ../mooneye next to this crate
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_roms(&path, roms)?;
+        } else if path.extension().is_some_and(|extension| extension == "gb") {
+            roms.push(path);
+        }
+    }
+    Ok(())
+}
+
+// acceptance/timer/tima_reload.gb -> acceptance_timer_tima_reload
+fn test_name(dir: &Path, rom: &Path) -> String {
+    let relative = rom.strip_prefix(dir).unwrap().with_extension("");
+    let name: String = relative
+        .to_string_lossy()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("mooneye_{}", name)
+}
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=MOONEYE_ROMS");
+    let dir = match env::var("MOONEYE_ROMS") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("../mooneye"),
+    };
+    // watching a missing path would rerun the build script every time
+    if dir.exists() {
+        println!("cargo:rerun-if-changed={}", dir.display());
+    }
+
+    let mut roms = Vec::new();
+    // no tests are generated when the directory does not exist
+    if dir.is_dir() {
+        find_roms(&dir, &mut roms).unwrap();
+    }
+    roms.sort();
+
+    let mut tests = String::new();
+    let mut names = HashSet::new();
+    for rom in &roms {
+        // foo-bar.gb and foo_bar.gb, or A.gb and a.gb, give the same name. the roms are sorted,
+        // the later ones get a number
+        let name = test_name(&dir, rom);
+        let mut unique = name.clone();
+        let mut count = 1;
+        while !names.insert(unique.clone()) {
+            count += 1;
+            unique = format!("{}_{}", name, count);
+        }
+        tests += &format!(
+            "#[test]\nfn {}() {{\n    assert_mooneye_passed({:?});\n}}\n\n",
+            unique,
+            rom.to_string_lossy()
+        );
+    }
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("mooneye_tests.rs");
+    fs::write(out, tests).unwrap();
+}
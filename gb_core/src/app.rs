@@ -1,12 +1,23 @@
-use crate::cartridge::load_cartridge_from_file;
-use crate::core::{Error, CPU, CYCLES_PER_FRAME, DEFAULT_FPS};
+use crate::audio::WavWriter;
+use crate::cartridge::{load_cartridge_from_file, read_file};
+use crate::core::{Error, FaultPolicy, Model, CPU, CYCLES_PER_FRAME, DEFAULT_FPS};
+use crate::graphics::RenderMode;
+pub use crate::io_registers::{Button, Disconnected, SerialCapture, SerialDevice};
+use log::error;
+use std::path::Path;
 use std::result::Result;
 use std::thread::sleep;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 // TODO: cpu updates controlled by frame rates.
 pub struct GameBoyApp {
     cpu: CPU,
+    // clock cycles executed since the cartridge was loaded
+    cycles: u64,
+    // 1.0 is real time, run and the audio output follow it
+    speed: f64,
+    // cartridge clocks catch up with the host time between sessions
+    rtc_wall_clock: bool,
 }
 
 impl GameBoyApp {
@@ -15,12 +26,18 @@ impl GameBoyApp {
         // load cartridge file
         cpu.memory_bus
             .load_cartridge(load_cartridge_from_file(path)?);
-        Ok(Self { cpu })
+        Ok(Self {
+            cpu,
+            cycles: 0,
+            speed: 1.0,
+            rtc_wall_clock: false,
+        })
     }
 
     pub fn load_new_cartridge(&mut self, path: &str) -> Result<(), Error> {
         // reset cpu and memory
         self.cpu.reset();
+        self.cycles = 0;
         self.cpu
             .memory_bus
             .load_cartridge(load_cartridge_from_file(path)?);
@@ -28,37 +45,225 @@ impl GameBoyApp {
         Ok(())
     }
 
+    // map a boot rom over the cartridge, boot then runs it instead of skipping it
+    pub fn load_boot_rom(&mut self, path: &str) -> Result<(), Error> {
+        self.cpu.memory_bus.load_boot_rom(read_file(path)?)
+    }
+
+    // load the battery backed ram and clock of the cartridge from a save file
+    pub fn load_save(&mut self, path: &str) -> Result<(), Error> {
+        let bytes = read_file(path)?;
+        let cartridge = self.cpu.memory_bus.cartridge_mut()?;
+        cartridge.load_save_data(&bytes)?;
+        if self.rtc_wall_clock {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs());
+            cartridge.advance_clock_to(now);
+        }
+        Ok(())
+    }
+
+    // off by default, the cartridge clock then only advances with emulated time
+    pub fn set_rtc_wall_clock(&mut self, enabled: bool) {
+        self.rtc_wall_clock = enabled;
+    }
+
+    // write the battery backed ram to a save file, nothing is written without a battery
+    pub fn write_save(&self, path: &str) -> Result<(), Error> {
+        let cartridge = self.cpu.memory_bus.cartridge()?;
+        if cartridge.has_battery() {
+            std::fs::write(path, cartridge.save_data())?;
+        }
+        Ok(())
+    }
+
+    // the console to emulate, DMG by default
+    pub fn set_model(&mut self, model: Model) {
+        self.cpu.memory_bus.set_model(model);
+    }
+
     pub fn boot(&mut self) {
-        // check cartridge is valid
+        if self.cpu.memory_bus.boot_rom_mapped() {
+            // the boot rom scrolls the logo, plays the di-ding sound and unmaps itself by writing 0xFF50
+            self.cpu.pc = 0x0000;
+        } else {
+            // start at 0x0100 with the registers the boot rom leaves behind
+            self.cpu.skip_boot();
+        }
+    }
+
+    // 160 x 144 shades of the last frame, 0 is white and 3 is black
+    pub fn frame_buffer(&self) -> &[u8] {
+        self.cpu.memory_bus.ppu().frame_buffer()
+    }
+
+    // move the stereo samples produced so far into interleaved left, right pairs,
+    // return the pairs written. the oldest are dropped when not drained within a quarter second
+    pub fn drain_audio(&mut self, output: &mut [i16]) -> usize {
+        self.cpu.memory_bus.apu_mut().samples().drain_into(output)
+    }
+
+    // 48 kHz by default, the samples not drained yet are dropped
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.cpu.memory_bus.apu_mut().set_sample_rate(sample_rate);
+    }
+
+    // fast-forward above 1.0, the audio keeps its real time rate and plays faster.
+    // zero, negative and non-finite speeds are rejected
+    pub fn set_speed(&mut self, speed: f64) -> Result<(), Error> {
+        if !speed.is_finite() || speed <= 0.0 {
+            return Err(Error::SpeedError);
+        }
+        self.speed = speed;
+        self.cpu.memory_bus.apu_mut().set_speed(speed);
+        Ok(())
+    }
+
+    // line based rendering is the default, the pixel FIFO shows mid-line register changes
+    pub fn set_render_mode(&mut self, render_mode: RenderMode) {
+        self.cpu.memory_bus.ppu_mut().set_render_mode(render_mode);
+    }
+
+    // press or release a button, frontends call this from their input handling
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.cpu.memory_bus.set_button(button, pressed);
+    }
+
+    // connect a device to the serial port, it is disconnected by default
+    pub fn set_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.cpu.memory_bus.set_serial_device(device);
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // faults are returned by default, OpenBus reads 0xFF and drops writes instead
+    pub fn set_fault_policy(&mut self, fault_policy: FaultPolicy) {
+        self.cpu.memory_bus.set_fault_policy(fault_policy);
+    }
 
-        // play di-ding sound
+    // execute one instruction, the cpu advances the peripherals, return clock cycles taken
+    fn step(&mut self) -> Result<u32, Error> {
+        let cycles_executed = self.cpu.tick()?;
+        self.cycles += cycles_executed as u64;
+        Ok(cycles_executed)
+    }
 
-        // set boot rom register to disable boot rom
+    // run as fast as possible for at least the given clock cycles, return the cycles executed
+    pub fn run_for_cycles(&mut self, cycles: u64) -> Result<u64, Error> {
+        let start = self.cycles;
+        while self.cycles - start < cycles {
+            self.step()?;
+        }
+        Ok(self.cycles - start)
+    }
 
-        // set pc
-        self.cpu.pc = 0x0100;
+    // run until the predicate is true after an instruction, give up after max_cycles
+    // return true when the predicate was met
+    pub fn run_until<F>(&mut self, max_cycles: u64, mut predicate: F) -> Result<bool, Error>
+    where
+        F: FnMut(&GameBoyApp) -> bool,
+    {
+        let start = self.cycles;
+        while self.cycles - start < max_cycles {
+            self.step()?;
+            if predicate(self) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
     }
 
-    pub fn run(&mut self) {
-        // compute time per frame
-        const FRAME_DURATION: Duration = Duration::from_micros((1_000_000.0 / DEFAULT_FPS) as u64);
+    // run for the given frames as fast as possible and write the audio to a WAV file at the output rate,
+    // with channel_tracks also every channel alone to <name>.ch1.wav - <name>.ch4.wav next to it.
+    // the audio is recorded at real time speed, samples not drained before are dropped
+    pub fn record_audio<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        frames: u32,
+        channel_tracks: bool,
+    ) -> Result<(), Error> {
+        let apu = self.cpu.memory_bus.apu_mut();
+        let sample_rate = apu.sample_rate();
+        apu.set_speed(1.0);
+        apu.set_channel_tracks(channel_tracks);
+        apu.samples().clear();
 
+        let result = self.write_audio(path.as_ref(), frames, channel_tracks, sample_rate);
+
+        // back to the speed of the app without the tracks, also when the recording failed
+        let apu = self.cpu.memory_bus.apu_mut();
+        apu.set_channel_tracks(false);
+        apu.set_speed(self.speed);
+        result
+    }
+
+    fn write_audio(
+        &mut self,
+        path: &Path,
+        frames: u32,
+        channel_tracks: bool,
+        sample_rate: u32,
+    ) -> Result<(), Error> {
+        let mut wav = WavWriter::create(path, sample_rate)?;
+        let mut track_wavs = Vec::new();
+        if channel_tracks {
+            for channel in 1..=4 {
+                let track_path = path.with_extension(format!("ch{}.wav", channel));
+                track_wavs.push(WavWriter::create(track_path, sample_rate)?);
+            }
+        }
+
+        // a frame of samples fits in the buffer, drain after every frame
+        let mut output = vec![0; 2 * sample_rate as usize / 4];
+        let start = self.cycles;
+        for frame in 1..=frames as u64 {
+            let end = start + frame * CYCLES_PER_FRAME as u64;
+            self.run_for_cycles(end.saturating_sub(self.cycles))?;
+
+            let apu = self.cpu.memory_bus.apu_mut();
+            let count = apu.samples().drain_into(&mut output);
+            wav.write_samples(&output[..count * 2])?;
+            for (channel, track_wav) in track_wavs.iter_mut().enumerate() {
+                let samples = apu.channel_samples(channel).unwrap();
+                let count = samples.drain_into(&mut output);
+                track_wav.write_samples(&output[..count * 2])?;
+            }
+        }
+
+        wav.finish()?;
+        for track_wav in track_wavs {
+            track_wav.finish()?;
+        }
+        Ok(())
+    }
+
+    // run in real time until a bus fault, which is logged with its pc and address and returned
+    pub fn run(&mut self) -> Result<(), Error> {
         // call cpu update
         loop {
+            // compute time per frame, shorter when fast-forwarding
+            let frame_duration = Duration::from_secs_f64(1.0 / (DEFAULT_FPS * self.speed));
             let frame_start_time = Instant::now();
             let mut cycles_this_frame = 0;
             while cycles_this_frame < CYCLES_PER_FRAME {
-                let cycles_executed = self.cpu.tick();
-                cycles_this_frame += cycles_executed
-
-                // self.ppu.step(cycles_executed);
-                // self.timer.step(cycles_executed);
+                cycles_this_frame += self.step().inspect_err(|fault| error!("{}", fault))?;
             }
 
-            // update screen, draw screen
+            // update screen, draw screen from frame_buffer
 
             let time_taken = frame_start_time.elapsed();
-            if let Some(sleep_duration) = FRAME_DURATION.checked_sub(time_taken) {
+            if let Some(sleep_duration) = frame_duration.checked_sub(time_taken) {
                 sleep(sleep_duration);
             }
         }
@@ -68,114 +273,279 @@ impl GameBoyApp {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::{Bus, DEFAULT_TIMER_FREQUENCY};
     use log::debug;
 
+    // blargg's roms print the result to the serial port and to the screen
+    #[derive(Debug, PartialEq)]
+    enum BlarggResult {
+        Passed,
+        Failed(String),
+        Timeout(String),
+    }
+
+    // blargg's font uses the ascii code as the tile index
+    fn screen_text(app: &GameBoyApp) -> String {
+        let vram = app.cpu.memory_bus.ppu().vram();
+        let mut text = String::new();
+        for y in 0..18 {
+            for x in 0..20 {
+                let index = vram.tile_map_index(0x9800, x, y);
+                text.push(if index.is_ascii_graphic() {
+                    index as char
+                } else {
+                    ' '
+                });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    fn blargg_result(serial: &[u8], screen: &str) -> Option<BlarggResult> {
+        let serial = String::from_utf8_lossy(serial);
+        for text in [serial.as_ref(), screen] {
+            if text.contains("Passed") {
+                return Some(BlarggResult::Passed);
+            }
+            if text.contains("Failed") {
+                return Some(BlarggResult::Failed(text.to_string()));
+            }
+        }
+        None
+    }
+
+    // run a blargg test rom for at most the given emulated seconds
+    fn run_blargg(path: &str, seconds: u64) -> BlarggResult {
+        assert!(
+            std::path::Path::new(path).exists(),
+            "the blargg test rom {} is missing, check out the roms next to the crates",
+            path
+        );
+        let mut app = GameBoyApp::new(path)
+            .unwrap_or_else(|error| panic!("can't load the test rom {}: {}", path, error));
+        let capture = SerialCapture::new();
+        let output = capture.output();
+        app.set_serial_device(Box::new(capture));
+        app.boot();
+
+        // checking the screen is slow, only look once per frame
+        let mut next_check = 0;
+        let mut result = None;
+        app.run_until(seconds * DEFAULT_TIMER_FREQUENCY, |app| {
+            if app.cycles() < next_check {
+                return false;
+            }
+            next_check = app.cycles() + CYCLES_PER_FRAME as u64;
+            result = blargg_result(&output.borrow(), &screen_text(app));
+            result.is_some()
+        })
+        .unwrap();
+
+        let serial = String::from_utf8_lossy(&output.borrow()).to_string();
+        debug!("serial output of {}:\n{}", path, serial);
+        result.unwrap_or(BlarggResult::Timeout(serial))
+    }
+
+    fn assert_blargg_passed(path: &str, seconds: u64) {
+        assert_eq!(run_blargg(path, seconds), BlarggResult::Passed, "{}", path);
+    }
+
     #[test]
     #[test_log::test]
     fn test_06() {
-        let path = "../cpu_instrs/individual/06-ld r,r.gb";
-        let mut app = GameBoyApp::new(path).unwrap();
-        app.boot();
-        app.run();
+        assert_blargg_passed("../cpu_instrs/individual/06-ld r,r.gb", 30);
     }
 
     #[test]
     #[test_log::test]
     fn test_05() {
-        let path = "../cpu_instrs/individual/05-op rp.gb";
-        let mut app = GameBoyApp::new(path).unwrap();
-        app.boot();
-        app.run();
+        assert_blargg_passed("../cpu_instrs/individual/05-op rp.gb", 30);
     }
 
     #[test]
     #[test_log::test]
     fn test_04() {
-        let path = "../cpu_instrs/individual/04-op r,imm.gb";
-        let mut app = GameBoyApp::new(path).unwrap();
-        app.boot();
-        app.run();
+        assert_blargg_passed("../cpu_instrs/individual/04-op r,imm.gb", 30);
     }
 
     #[test]
     #[test_log::test]
     fn test_03() {
-        let path = "../cpu_instrs/individual/03-op sp,hl.gb";
-        let mut app = GameBoyApp::new(path).unwrap();
-        app.boot();
-        app.run();
+        assert_blargg_passed("../cpu_instrs/individual/03-op sp,hl.gb", 30);
     }
 
     #[test]
     #[test_log::test]
     fn test_02() {
-        let path = "../cpu_instrs/individual/02-interrupts.gb";
-        let mut app = GameBoyApp::new(path).unwrap();
-        app.boot();
-        app.run();
+        assert_blargg_passed("../cpu_instrs/individual/02-interrupts.gb", 30);
     }
 
     #[test]
     #[test_log::test]
     fn test_01() {
-        let path = "../cpu_instrs/individual/01-special.gb";
-        let mut app = GameBoyApp::new(path).unwrap();
-        app.boot();
-        app.run();
+        assert_blargg_passed("../cpu_instrs/individual/01-special.gb", 30);
     }
 
     #[test]
     #[test_log::test]
     fn test_07() {
-        let path = "../cpu_instrs/individual/07-jr,jp,call,ret,rst.gb";
-        let mut app = GameBoyApp::new(path).unwrap();
-        app.boot();
-        app.run();
+        assert_blargg_passed("../cpu_instrs/individual/07-jr,jp,call,ret,rst.gb", 30);
     }
 
     #[test]
     #[test_log::test]
     fn test_08() {
-        let path = "../cpu_instrs/individual/08-misc instrs.gb";
-        let mut app = GameBoyApp::new(path).unwrap();
-        app.boot();
-        app.run();
+        assert_blargg_passed("../cpu_instrs/individual/08-misc instrs.gb", 30);
     }
 
     #[test]
     #[test_log::test]
     fn test_09() {
-        let path = "../cpu_instrs/individual/09-op r,r.gb";
-        let mut app = GameBoyApp::new(path).unwrap();
-        app.boot();
-        app.run();
+        assert_blargg_passed("../cpu_instrs/individual/09-op r,r.gb", 30);
     }
 
     #[test]
     #[test_log::test]
     fn test_10() {
-        let path = "../cpu_instrs/individual/10-bit ops.gb";
-        let mut app = GameBoyApp::new(path).unwrap();
-        app.boot();
-        app.run();
+        assert_blargg_passed("../cpu_instrs/individual/10-bit ops.gb", 30);
     }
 
     #[test]
     #[test_log::test]
     fn test_11() {
-        let path = "../cpu_instrs/individual/11-op a,(hl).gb";
-        let mut app = GameBoyApp::new(path).unwrap();
-        app.boot();
-        app.run();
+        assert_blargg_passed("../cpu_instrs/individual/11-op a,(hl).gb", 30);
     }
 
     #[test]
     #[test_log::test]
     fn test_all_gb() {
-        let path = "../cpu_instrs/cpu_instrs.gb";
-        let mut app = GameBoyApp::new(path).unwrap();
+        assert_blargg_passed("../cpu_instrs/cpu_instrs.gb", 120);
+    }
+
+    #[test]
+    #[test_log::test]
+    fn test_mem_timing() {
+        assert_blargg_passed("../mem_timing/mem_timing.gb", 30);
+    }
+
+    #[test]
+    #[test_log::test]
+    fn test_mem_timing_2() {
+        assert_blargg_passed("../mem_timing-2/mem_timing.gb", 30);
+    }
+
+    #[test]
+    fn test_blargg_result() {
+        assert_eq!(
+            blargg_result(b"01-special\n\n\nPassed\n", ""),
+            Some(BlarggResult::Passed)
+        );
+        assert_eq!(blargg_result(b"", "   Passed"), Some(BlarggResult::Passed));
+        assert!(matches!(
+            blargg_result(b"01-special\n\nFailed #2\n", ""),
+            Some(BlarggResult::Failed(_))
+        ));
+        assert_eq!(blargg_result(b"01-special\n", ""), None);
+    }
+
+    // a directory in the temp dir, removed when dropped so failed tests clean up too
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_record_audio() {
+        let mut rom = crate::cartridge::tests::test_rom(0x00, 0x00, 0x00);
+        // JP 0x0150
+        rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        // only channel 2 on both sides at full volume, then JR -2
+        rom[0x150..0x166].copy_from_slice(&[
+            0x3E, 0x80, 0xE0, 0x26, // NR52
+            0x3E, 0x77, 0xE0, 0x24, // NR50
+            0x3E, 0x22, 0xE0, 0x25, // NR51
+            0x3E, 0xF0, 0xE0, 0x17, // NR22
+            0x3E, 0x87, 0xE0, 0x19, // NR24
+            0x18, 0xFE,
+        ]);
+        let temp_dir = TempDir::new("gb_record_audio");
+        let dir = &temp_dir.0;
+        let rom_path = dir.join("sound.gb");
+        std::fs::write(&rom_path, &rom).unwrap();
+
+        let mut app = GameBoyApp::new(rom_path.to_str().unwrap()).unwrap();
         app.boot();
-        app.run();
+        // channel 1 is left on by the boot rom, at volume 0. its DAC offset would
+        // show up in the track, turn the DAC off
+        assert_eq!(app.cpu.memory_bus.read_byte(0xFF26), 0xF1);
+        app.cpu.memory_bus.write_byte(0xFF12, 0x00);
+        let wav_path = dir.join("sound.wav");
+        app.record_audio(&wav_path, 10, true).unwrap();
+
+        // 10 frames are 8036 samples at 48 kHz
+        let wav = std::fs::read(&wav_path).unwrap();
+        let data_size = u32::from_le_bytes(wav[40..44].try_into().unwrap());
+        assert!((8035 * 4..=8037 * 4).contains(&data_size));
+        assert_eq!(wav.len(), 44 + data_size as usize);
+
+        for channel in 1..=4 {
+            let track = std::fs::read(dir.join(format!("sound.ch{}.wav", channel))).unwrap();
+            assert_eq!(track.len(), wav.len());
+            let silent = track[44..].iter().all(|&byte| byte == 0);
+            assert_eq!(silent, channel != 2, "channel {}", channel);
+        }
+    }
+
+    #[test]
+    fn test_record_audio_restores_apu() {
+        let temp_dir = TempDir::new("gb_record_audio_error");
+        let rom_path = temp_dir.0.join("silence.gb");
+        std::fs::write(
+            &rom_path,
+            crate::cartridge::tests::test_rom(0x00, 0x00, 0x00),
+        )
+        .unwrap();
+        let mut app = GameBoyApp::new(rom_path.to_str().unwrap()).unwrap();
+        app.set_speed(2.0).unwrap();
+
+        // the WAV can't be created in a missing directory
+        let wav_path = temp_dir.0.join("missing").join("silence.wav");
+        assert!(app.record_audio(&wav_path, 1, true).is_err());
+        let apu = app.cpu.memory_bus.apu_mut();
+        assert_eq!(apu.speed(), 2.0);
+        assert!(apu.channel_samples(0).is_none());
+    }
+
+    #[test]
+    fn test_set_speed() {
+        let rom_path = std::env::temp_dir().join(format!("gb_set_speed_{}.gb", std::process::id()));
+        std::fs::write(
+            &rom_path,
+            crate::cartridge::tests::test_rom(0x00, 0x00, 0x00),
+        )
+        .unwrap();
+        let app = GameBoyApp::new(rom_path.to_str().unwrap());
+        std::fs::remove_file(&rom_path).unwrap();
+        let mut app = app.unwrap();
+
+        app.set_speed(2.0).unwrap();
+        for speed in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(app.set_speed(speed), Err(Error::SpeedError)));
+        }
+        // the last valid speed is kept
+        assert_eq!(app.speed, 2.0);
     }
 
     #[test]
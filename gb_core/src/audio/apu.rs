@@ -0,0 +1,461 @@
+/*
+APU:
+0xFF10 - 0xFF14 channel 1, square with sweep: NR10 sweep, NR11 duty and length, NR12 envelope, NR13 - NR14 period
+0xFF16 - 0xFF19 channel 2, square: NR21 duty and length, NR22 envelope, NR23 - NR24 period
+0xFF1A - 0xFF1E channel 3, wave: NR30 DAC, NR31 length, NR32 output level, NR33 - NR34 period
+0xFF20 - 0xFF23 channel 4, noise: NR41 length, NR42 envelope, NR43 LFSR, NR44 control
+bit 7 of NRx4 triggers the channel, bit 6 enables its length.
+0xFF24 NR50: bit 4-6 left volume, bit 0-2 right volume
+0xFF25 NR51: bit 4-7 channel 4 - 1 on the left, bit 0-3 channel 4 - 1 on the right
+0xFF26 NR52: bit 7 power, bit 0-3 channel 4 - 1 on, read only
+0xFF30 - 0xFF3F wave RAM
+
+The frame sequencer runs at 512 Hz and clocks
+step:     0  1  2  3  4  5  6  7
+length:   x     x     x     x
+sweep:          x           x
+envelope:                      x
+
+Turning the power off clears 0xFF10 - 0xFF25 and ignores writes to them until it is turned on,
+wave RAM stays accessible.
+
+The stereo amplitude is mixed every machine cycle and resampled to the output rate.
+Channel tracks optionally resample every channel on its own as well, with its panning and the master volume.
+*/
+
+use super::buffer::SampleBuffer;
+use super::channels::{Noise, Square, Sweep, Wave};
+use super::resampler::{Resampler, DEFAULT_SAMPLE_RATE};
+use crate::core::Error;
+use std::result::Result;
+
+pub const NR10: u16 = 0xFF10;
+pub const NR50: u16 = 0xFF24;
+pub const NR51: u16 = 0xFF25;
+pub const NR52: u16 = 0xFF26;
+pub const WAVE_RAM: u16 = 0xFF30;
+
+const FRAME_SEQUENCER_PERIOD: u32 = 8192;
+
+// the bits that read back as 1 for 0xFF10 - 0xFF25, write only and unused bits
+const READ_MASKS: [u8; 0x16] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10 - NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // unused, NR21 - NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30 - NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // unused, NR41 - NR44
+    0x00, 0x00, // NR50, NR51
+];
+
+pub struct Apu {
+    powered: bool,
+    registers: [u8; 0x16],
+    square1: Square,
+    sweep: Sweep,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    frame_sequencer_step: u8,
+    frame_sequencer_timer: u32,
+    // clock cycles left over from the last machine cycle
+    cycles: u32,
+    resampler: Resampler,
+    // one per channel when enabled
+    channel_tracks: Vec<Resampler>,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            powered: false,
+            registers: [0; 0x16],
+            square1: Square::new(),
+            sweep: Sweep::new(),
+            square2: Square::new(),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            frame_sequencer_step: 0,
+            frame_sequencer_timer: FRAME_SEQUENCER_PERIOD,
+            cycles: 0,
+            resampler: Resampler::new(DEFAULT_SAMPLE_RATE),
+            channel_tracks: Vec::new(),
+        }
+    }
+
+    // the output rate, speed and channel tracks are kept
+    pub fn reset(&mut self) {
+        let sample_rate = self.resampler.sample_rate();
+        let speed = self.resampler.speed();
+        let channel_tracks = !self.channel_tracks.is_empty();
+        *self = Self::new();
+        self.set_sample_rate(sample_rate);
+        self.set_speed(speed);
+        self.set_channel_tracks(channel_tracks);
+    }
+
+    fn resamplers_mut(&mut self) -> impl Iterator<Item = &mut Resampler> {
+        std::iter::once(&mut self.resampler).chain(self.channel_tracks.iter_mut())
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.resampler.sample_rate()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.resamplers_mut()
+            .for_each(|resampler| resampler.set_sample_rate(sample_rate));
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.resampler.speed()
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.resamplers_mut()
+            .for_each(|resampler| resampler.set_speed(speed));
+    }
+
+    // stereo samples at the output rate
+    pub fn samples(&mut self) -> &mut SampleBuffer {
+        self.resampler.samples()
+    }
+
+    // resample every channel on its own too, costs a resampler per channel
+    pub fn set_channel_tracks(&mut self, enabled: bool) {
+        self.channel_tracks.clear();
+        if enabled {
+            for _ in 0..4 {
+                let mut track = Resampler::new(self.resampler.sample_rate());
+                track.set_speed(self.resampler.speed());
+                self.channel_tracks.push(track);
+            }
+        }
+    }
+
+    // stereo samples of channel 0 - 3 alone, None without channel tracks
+    pub fn channel_samples(&mut self, channel: usize) -> Option<&mut SampleBuffer> {
+        self.channel_tracks.get_mut(channel).map(Resampler::samples)
+    }
+
+    // the digital output 0 - 15 of each channel, 1 to 4
+    pub fn channel_outputs(&self) -> [u8; 4] {
+        [
+            self.square1.output(),
+            self.square2.output(),
+            self.wave.output(),
+            self.noise.output(),
+        ]
+    }
+
+    fn dac_enabled(&self) -> [bool; 4] {
+        [
+            self.square1.envelope.dac_enabled(),
+            self.square2.envelope.dac_enabled(),
+            self.wave.dac_enabled,
+            self.noise.envelope.dac_enabled(),
+        ]
+    }
+
+    fn register(&self, address: u16) -> u8 {
+        self.registers[(address - NR10) as usize]
+    }
+
+    pub fn read_byte(&self, address: u16) -> Result<u8, Error> {
+        match address {
+            NR52 => {
+                let status = self.square1.enabled as u8
+                    | (self.square2.enabled as u8) << 1
+                    | (self.wave.enabled as u8) << 2
+                    | (self.noise.enabled as u8) << 3;
+                Ok((self.powered as u8) << 7 | 0x70 | status)
+            }
+            0xFF10..=0xFF25 => {
+                let index = (address - NR10) as usize;
+                Ok(self.registers[index] | READ_MASKS[index])
+            }
+            0xFF27..=0xFF2F => Ok(0xFF),
+            0xFF30..=0xFF3F => Ok(self.wave.ram[(address - WAVE_RAM) as usize]),
+            _ => Err(Error::IORegisterAddressError),
+        }
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) -> Result<(), Error> {
+        match address {
+            NR52 => {
+                let powered = value & 0x80 != 0;
+                if self.powered && !powered {
+                    self.power_off();
+                } else if !self.powered && powered {
+                    // the frame sequencer starts over at step 0
+                    self.frame_sequencer_step = 0;
+                    self.frame_sequencer_timer = FRAME_SEQUENCER_PERIOD;
+                }
+                self.powered = powered;
+            }
+            0xFF10..=0xFF25 if !self.powered => (),
+            0xFF10..=0xFF25 => self.write_register(address, value),
+            0xFF27..=0xFF2F => (),
+            0xFF30..=0xFF3F => self.wave.ram[(address - WAVE_RAM) as usize] = value,
+            _ => return Err(Error::IORegisterAddressError),
+        }
+        Ok(())
+    }
+
+    fn power_off(&mut self) {
+        let ram = self.wave.ram;
+        self.registers = [0; 0x16];
+        self.square1 = Square::new();
+        self.sweep = Sweep::new();
+        self.square2 = Square::new();
+        self.wave = Wave::new();
+        self.wave.ram = ram;
+        self.noise = Noise::new();
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        self.registers[(address - NR10) as usize] = value;
+        match address {
+            0xFF10 => self.sweep.register = value,
+            0xFF11 => {
+                self.square1.duty = value >> 6;
+                self.square1.length.load((value & 0x3F) as u16);
+            }
+            0xFF12 => {
+                self.square1.envelope.register = value;
+                self.square1.enabled &= self.square1.envelope.dac_enabled();
+            }
+            0xFF13 => self.square1.period = (self.square1.period & 0x700) | value as u16,
+            0xFF14 => {
+                self.square1.period = (self.square1.period & 0xFF) | ((value & 0x07) as u16) << 8;
+                self.square1.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.square1.trigger();
+                    self.sweep.trigger(&mut self.square1);
+                }
+            }
+            0xFF16 => {
+                self.square2.duty = value >> 6;
+                self.square2.length.load((value & 0x3F) as u16);
+            }
+            0xFF17 => {
+                self.square2.envelope.register = value;
+                self.square2.enabled &= self.square2.envelope.dac_enabled();
+            }
+            0xFF18 => self.square2.period = (self.square2.period & 0x700) | value as u16,
+            0xFF19 => {
+                self.square2.period = (self.square2.period & 0xFF) | ((value & 0x07) as u16) << 8;
+                self.square2.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.square2.trigger();
+                }
+            }
+            0xFF1A => {
+                self.wave.dac_enabled = value & 0x80 != 0;
+                self.wave.enabled &= self.wave.dac_enabled;
+            }
+            0xFF1B => self.wave.length.load(value as u16),
+            0xFF1C => self.wave.output_level = (value >> 5) & 0x03,
+            0xFF1D => self.wave.period = (self.wave.period & 0x700) | value as u16,
+            0xFF1E => {
+                self.wave.period = (self.wave.period & 0xFF) | ((value & 0x07) as u16) << 8;
+                self.wave.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.wave.trigger();
+                }
+            }
+            0xFF20 => self.noise.length.load((value & 0x3F) as u16),
+            0xFF21 => {
+                self.noise.envelope.register = value;
+                self.noise.enabled &= self.noise.envelope.dac_enabled();
+            }
+            0xFF22 => self.noise.register = value,
+            0xFF23 => {
+                self.noise.length.enabled = value & 0x40 != 0;
+                if value & 0x80 != 0 {
+                    self.noise.trigger();
+                }
+            }
+            // NR50, NR51 and the unused registers are only stored
+            _ => (),
+        }
+    }
+
+    // advance by clock cycles, these do not speed up in double speed
+    pub fn step(&mut self, cycles: u32) {
+        self.cycles += cycles;
+        while self.cycles >= 4 {
+            self.cycles -= 4;
+            if self.powered {
+                self.square1.step(4);
+                self.square2.step(4);
+                self.wave.step(4);
+                self.noise.step(4);
+
+                self.frame_sequencer_timer -= 4;
+                if self.frame_sequencer_timer == 0 {
+                    self.frame_sequencer_timer = FRAME_SEQUENCER_PERIOD;
+                    self.clock_frame_sequencer();
+                }
+            }
+            let levels = self.mix();
+            let left = levels.iter().map(|level| level[0]).sum();
+            let right = levels.iter().map(|level| level[1]).sum();
+            self.resampler.set_levels([left, right]);
+            self.resampler.advance(4);
+            for (track, level) in self.channel_tracks.iter_mut().zip(levels) {
+                track.set_levels(level);
+                track.advance(4);
+            }
+        }
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        if self.frame_sequencer_step & 1 == 0 {
+            self.square1.enabled &= self.square1.length.clock();
+            self.square2.enabled &= self.square2.length.clock();
+            self.wave.enabled &= self.wave.length.clock();
+            self.noise.enabled &= self.noise.length.clock();
+        }
+        if self.frame_sequencer_step == 2 || self.frame_sequencer_step == 6 {
+            self.sweep.clock(&mut self.square1);
+        }
+        if self.frame_sequencer_step == 7 {
+            self.square1.envelope.clock();
+            self.square2.envelope.clock();
+            self.noise.envelope.clock();
+        }
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) & 7;
+    }
+
+    // the analog output of each channel on both sides, before the high-pass filter
+    fn mix(&self) -> [[f32; 2]; 4] {
+        let outputs = self.channel_outputs();
+        let dacs = self.dac_enabled();
+        let panning = self.register(NR51);
+        let volume = self.register(NR50);
+        let left = (((volume >> 4) & 0x07) + 1) as f32;
+        let right = ((volume & 0x07) + 1) as f32;
+
+        let mut levels = [[0.0; 2]; 4];
+        for (channel, level) in levels.iter_mut().enumerate() {
+            if !dacs[channel] {
+                continue;
+            }
+            // a DAC maps 0 - 15 to 1.0 - -1.0, kept here as 15 - -15
+            let analog = 15.0 - outputs[channel] as f32 * 2.0;
+            if panning & (0x10 << channel) != 0 {
+                level[0] = analog * left;
+            }
+            if panning & (0x01 << channel) != 0 {
+                level[1] = analog * right;
+            }
+        }
+        levels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::DEFAULT_TIMER_FREQUENCY;
+
+    #[test]
+    fn test_read_masks() {
+        let mut apu = Apu::new();
+        apu.write_byte(NR52, 0x80).unwrap();
+        assert_eq!(apu.read_byte(NR52).unwrap(), 0xF0);
+        for address in 0xFF10..=0xFF25 {
+            apu.write_byte(address, 0x00).unwrap();
+        }
+        assert_eq!(apu.read_byte(0xFF10).unwrap(), 0x80);
+        assert_eq!(apu.read_byte(0xFF11).unwrap(), 0x3F);
+        assert_eq!(apu.read_byte(0xFF13).unwrap(), 0xFF);
+        assert_eq!(apu.read_byte(0xFF14).unwrap(), 0xBF);
+        assert_eq!(apu.read_byte(0xFF15).unwrap(), 0xFF);
+        assert_eq!(apu.read_byte(0xFF1C).unwrap(), 0x9F);
+        assert_eq!(apu.read_byte(0xFF27).unwrap(), 0xFF);
+        assert!(apu.read_byte(0xFF40).is_err());
+    }
+
+    #[test]
+    fn test_power_off() {
+        let mut apu = Apu::new();
+        // writes are ignored while off, wave RAM is not
+        apu.write_byte(0xFF12, 0xF0).unwrap();
+        apu.write_byte(WAVE_RAM, 0x12).unwrap();
+        assert_eq!(apu.read_byte(0xFF12).unwrap(), 0x00);
+        assert_eq!(apu.read_byte(WAVE_RAM).unwrap(), 0x12);
+
+        apu.write_byte(NR52, 0x80).unwrap();
+        apu.write_byte(0xFF12, 0xF0).unwrap();
+        apu.write_byte(0xFF14, 0x80).unwrap();
+        assert_eq!(apu.read_byte(NR52).unwrap(), 0xF1);
+
+        apu.write_byte(NR52, 0x00).unwrap();
+        assert_eq!(apu.read_byte(NR52).unwrap(), 0x70);
+        assert_eq!(apu.read_byte(0xFF12).unwrap(), 0x00);
+        assert_eq!(apu.read_byte(WAVE_RAM).unwrap(), 0x12);
+    }
+
+    #[test]
+    fn test_length_stops_channel() {
+        let mut apu = Apu::new();
+        apu.write_byte(NR52, 0x80).unwrap();
+        apu.write_byte(0xFF17, 0xF0).unwrap();
+        // length 63, one clock left
+        apu.write_byte(0xFF16, 0x3F).unwrap();
+        apu.write_byte(0xFF19, 0xC0).unwrap();
+        assert_eq!(apu.read_byte(NR52).unwrap(), 0xF2);
+        apu.step(FRAME_SEQUENCER_PERIOD);
+        assert_eq!(apu.read_byte(NR52).unwrap(), 0xF0);
+    }
+
+    #[test]
+    fn test_samples() {
+        let mut apu = Apu::new();
+        apu.write_byte(NR52, 0x80).unwrap();
+        apu.write_byte(NR50, 0x77).unwrap();
+        // channel 2 only on the left
+        apu.write_byte(NR51, 0x20).unwrap();
+        apu.write_byte(0xFF17, 0xF0).unwrap();
+        apu.write_byte(0xFF16, 0x80).unwrap();
+        apu.write_byte(0xFF19, 0x87).unwrap();
+        apu.step(DEFAULT_TIMER_FREQUENCY as u32 / 100);
+
+        let samples = apu.samples();
+        assert!((479..=480).contains(&samples.len()));
+        let mut output = vec![0; 960];
+        samples.drain_into(&mut output);
+        assert!(output.chunks(2).any(|sample| sample[0] != 0));
+        assert!(output.chunks(2).all(|sample| sample[1] == 0));
+    }
+
+    #[test]
+    fn test_channel_tracks() {
+        let mut apu = Apu::new();
+        assert!(apu.channel_samples(0).is_none());
+        apu.set_channel_tracks(true);
+        apu.write_byte(NR52, 0x80).unwrap();
+        apu.write_byte(NR51, 0xFF).unwrap();
+        // channel 2 and 4 play
+        apu.write_byte(0xFF17, 0xF0).unwrap();
+        apu.write_byte(0xFF19, 0x87).unwrap();
+        apu.write_byte(0xFF21, 0xF0).unwrap();
+        apu.write_byte(0xFF23, 0x80).unwrap();
+        apu.step(DEFAULT_TIMER_FREQUENCY as u32 / 100);
+
+        let mut output = vec![0; 960];
+        for channel in 0..4 {
+            let samples = apu.channel_samples(channel).unwrap();
+            assert!((479..=480).contains(&samples.len()));
+            samples.drain_into(&mut output);
+            let silent = output.iter().all(|&sample| sample == 0);
+            assert_eq!(silent, channel == 0 || channel == 2);
+        }
+    }
+}
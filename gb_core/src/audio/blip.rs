@@ -0,0 +1,137 @@
+/*
+Blip buffer:
+A band-limited resampler from the clock rate to the output rate, in the style of blip_buf.
+The APU output only changes when a channel does, so instead of filtering every clock cycle
+each change of amplitude (a delta) adds a band-limited step to the output samples around its time.
+The buffer keeps the differences between samples, reading a sample sums them up.
+
+The step is a windowed sinc, stored as KERNEL_PHASES kernels of KERNEL_WIDTH taps for the
+fraction of a sample the delta falls on. A sample is complete once the time has passed it,
+the kernel delays the output by KERNEL_WIDTH / 2 samples.
+*/
+
+use std::f64::consts::PI;
+
+const KERNEL_WIDTH: usize = 16;
+const KERNEL_PHASES: usize = 64;
+
+// a bit below the nyquist frequency of the output rate, the window needs room to roll off
+const CUTOFF: f64 = 0.9;
+
+// ring of sample differences, a delta reaches at most 2 + KERNEL_WIDTH samples ahead
+const BUFFER_SIZE: usize = 32;
+
+pub struct BlipBuffer {
+    // output samples per clock cycle
+    ratio: f64,
+    // the current time in samples after the next sample to read
+    position: f64,
+    kernels: Vec<[f32; KERNEL_WIDTH]>,
+    deltas: [f32; BUFFER_SIZE],
+    read: usize,
+    sum: f32,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+        Self {
+            ratio: sample_rate / clock_rate,
+            position: 0.0,
+            kernels: (0..KERNEL_PHASES).map(kernel).collect(),
+            deltas: [0.0; BUFFER_SIZE],
+            read: 0,
+            sum: 0.0,
+        }
+    }
+
+    // changing the rates keeps the samples that are not complete yet
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.ratio = sample_rate / clock_rate;
+    }
+
+    // change the amplitude by delta at the current time
+    pub fn add_delta(&mut self, delta: f32) {
+        let phase = (self.position.fract() * KERNEL_PHASES as f64) as usize;
+        let start = self.read + self.position as usize;
+        for (tap, weight) in self.kernels[phase].iter().enumerate() {
+            self.deltas[(start + tap) % BUFFER_SIZE] += delta * weight;
+        }
+    }
+
+    // advance the time by clock cycles
+    pub fn advance(&mut self, cycles: u32) {
+        self.position += cycles as f64 * self.ratio;
+    }
+
+    // the next complete sample
+    pub fn read_sample(&mut self) -> Option<f32> {
+        if self.position < 1.0 {
+            return None;
+        }
+        self.position -= 1.0;
+        self.sum += self.deltas[self.read];
+        self.deltas[self.read] = 0.0;
+        self.read = (self.read + 1) % BUFFER_SIZE;
+        Some(self.sum)
+    }
+}
+
+// the differences of a band-limited step that starts phase / KERNEL_PHASES into a sample
+fn kernel(phase: usize) -> [f32; KERNEL_WIDTH] {
+    let fraction = phase as f64 / KERNEL_PHASES as f64;
+    let center = (KERNEL_WIDTH / 2) as f64;
+    let mut taps = [0.0f64; KERNEL_WIDTH];
+    for (tap, value) in taps.iter_mut().enumerate() {
+        // the impulse in the middle of the sample is the difference of the step over it
+        let x = tap as f64 + 0.5 - fraction - center;
+        let sinc = if x == 0.0 {
+            1.0
+        } else {
+            (PI * CUTOFF * x).sin() / (PI * CUTOFF * x)
+        };
+        // blackman window over the kernel
+        let w = (x + center) / KERNEL_WIDTH as f64;
+        let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+        *value = sinc * window;
+    }
+    // every step settles at exactly delta
+    let total: f64 = taps.iter().sum();
+    taps.map(|value| (value / total) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_settles() {
+        let mut blip = BlipBuffer::new(4.0, 1.0);
+        blip.advance(2);
+        blip.add_delta(100.0);
+        let mut samples = Vec::new();
+        for _ in 0..KERNEL_WIDTH + 2 {
+            blip.advance(4);
+            samples.push(blip.read_sample().unwrap());
+            assert!(blip.read_sample().is_none());
+        }
+        // nothing before the step, delta once the kernel has passed
+        assert!(samples[0].abs() < 1.0);
+        assert!((samples[KERNEL_WIDTH + 1] - 100.0).abs() < 0.001);
+        // the step is centered half a kernel later
+        assert!(samples[KERNEL_WIDTH / 2 - 1] < 50.0);
+        assert!(samples[KERNEL_WIDTH / 2 + 1] > 50.0);
+    }
+
+    #[test]
+    fn test_sample_count() {
+        let mut blip = BlipBuffer::new(4_194_304.0, 48_000.0);
+        let mut count = 0;
+        for _ in 0..4_194_304 / 4 {
+            blip.advance(4);
+            while blip.read_sample().is_some() {
+                count += 1;
+            }
+        }
+        assert!((47_999..=48_000).contains(&count));
+    }
+}
@@ -0,0 +1,75 @@
+/*
+Sample buffer:
+A ring buffer of stereo samples, the apu pushes and the frontend drains.
+When the frontend falls behind, the oldest samples are dropped.
+*/
+
+use std::collections::VecDeque;
+
+pub struct SampleBuffer {
+    samples: VecDeque<[i16; 2]>,
+    capacity: usize,
+}
+
+impl SampleBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn push(&mut self, left: i16, right: i16) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back([left, right]);
+    }
+
+    // oldest sample, left and right
+    pub fn pop(&mut self) -> Option<[i16; 2]> {
+        self.samples.pop_front()
+    }
+
+    // move the oldest samples into interleaved left, right pairs, return the pairs written
+    pub fn drain_into(&mut self, output: &mut [i16]) -> usize {
+        let count = self.samples.len().min(output.len() / 2);
+        for (pair, sample) in output.chunks_exact_mut(2).zip(self.samples.drain(..count)) {
+            pair.copy_from_slice(&sample);
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_buffer() {
+        let mut buffer = SampleBuffer::new(3);
+        for i in 0..5 {
+            buffer.push(i, -i);
+        }
+        // the two oldest were dropped
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.pop(), Some([2, -2]));
+
+        let mut output = [0; 6];
+        assert_eq!(buffer.drain_into(&mut output), 2);
+        assert_eq!(output, [3, -3, 4, -4, 0, 0]);
+        assert!(buffer.is_empty());
+    }
+}
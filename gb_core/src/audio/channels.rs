@@ -0,0 +1,438 @@
+/*
+Channels:
+Every channel produces a digital value 0 - 15, its DAC turns it into an analog value.
+A channel is turned off when its length runs out, when its DAC is turned off, or by the sweep overflowing,
+triggering it (bit 7 of NRx4) turns it on again if the DAC is on.
+
+Square (channel 1 and 2):
+the duty step advances every (2048 - period) * 4 clock cycles, the 8 steps of the duty cycle are
+12.5%: 00000001, 25%: 10000001, 50%: 10000111, 75%: 01111110
+
+Wave (channel 3):
+32 4-bit samples in wave RAM 0xFF30 - 0xFF3F, upper nibble first.
+the position advances every (2048 - period) * 2 clock cycles, NR32 shifts the sample right.
+
+Noise (channel 4):
+a 15-bit LFSR clocked every divisor << shift clock cycles, divisor 8 for code 0 and code * 16 otherwise.
+bit 0 XNOR bit 1 is shifted in at bit 14, and also at bit 6 in 7-bit mode. The output is bit 0.
+*/
+
+const DUTY_CYCLES: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+// counts down at 256 Hz, turns the channel off at 0 when enabled
+pub struct Length {
+    pub enabled: bool,
+    counter: u16,
+    max: u16,
+}
+
+impl Length {
+    pub fn new(max: u16) -> Self {
+        Self {
+            enabled: false,
+            counter: 0,
+            max,
+        }
+    }
+
+    // the length register holds max - counter
+    pub fn load(&mut self, value: u16) {
+        self.counter = self.max - value;
+    }
+
+    // return false when the channel is turned off
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter > 0;
+        }
+        true
+    }
+
+    pub fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+}
+
+// NRx2: bit 4-7 initial volume, bit 3 increases, bit 0-2 pace at 64 Hz, 0 keeps the volume
+pub struct Envelope {
+    pub register: u8,
+    pub volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Self {
+            register: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    // the DAC is on when the volume or the direction bits are set
+    pub fn dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+
+    fn pace(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    pub fn clock(&mut self) {
+        if self.pace() == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.pace();
+            if self.register & 0x08 != 0 && self.volume < 15 {
+                self.volume += 1;
+            } else if self.register & 0x08 == 0 && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.pace();
+    }
+}
+
+pub struct Square {
+    pub enabled: bool,
+    pub duty: u8,
+    pub period: u16,
+    pub length: Length,
+    pub envelope: Envelope,
+    duty_step: u8,
+    timer: i32,
+}
+
+impl Square {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            duty: 0,
+            period: 0,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            duty_step: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn step(&mut self, cycles: i32) {
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += (2048 - self.period as i32) * 4;
+            self.duty_step = (self.duty_step + 1) & 7;
+        }
+    }
+
+    pub fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.envelope.trigger();
+        self.timer = (2048 - self.period as i32) * 4;
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let high = DUTY_CYCLES[self.duty as usize] >> (7 - self.duty_step) & 1;
+        high * self.envelope.volume
+    }
+}
+
+// NR10: bit 4-6 pace at 128 Hz, bit 3 decreases, bit 0-2 shift
+pub struct Sweep {
+    pub register: u8,
+    enabled: bool,
+    shadow: u16,
+    timer: u8,
+}
+
+impl Sweep {
+    pub fn new() -> Self {
+        Self {
+            register: 0,
+            enabled: false,
+            shadow: 0,
+            timer: 0,
+        }
+    }
+
+    fn pace(&self) -> u8 {
+        (self.register >> 4) & 0x07
+    }
+
+    fn shift(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    // the next period, None when it overflows and turns the channel off
+    fn next_period(&self) -> Option<u16> {
+        let delta = self.shadow >> self.shift();
+        let period = if self.register & 0x08 != 0 {
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        };
+        (period <= 0x7FF).then_some(period)
+    }
+
+    // the timer reloads with 8 when the pace is 0
+    fn reload_timer(&mut self) {
+        self.timer = if self.pace() == 0 { 8 } else { self.pace() };
+    }
+
+    pub fn trigger(&mut self, channel: &mut Square) {
+        self.shadow = channel.period;
+        self.reload_timer();
+        self.enabled = self.pace() != 0 || self.shift() != 0;
+        if self.shift() != 0 && self.next_period().is_none() {
+            channel.enabled = false;
+        }
+    }
+
+    pub fn clock(&mut self, channel: &mut Square) {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+        self.reload_timer();
+        if !self.enabled || self.pace() == 0 {
+            return;
+        }
+        match self.next_period() {
+            Some(period) if self.shift() != 0 => {
+                self.shadow = period;
+                channel.period = period;
+                // the new period is checked for overflow again
+                if self.next_period().is_none() {
+                    channel.enabled = false;
+                }
+            }
+            Some(_) => (),
+            None => channel.enabled = false,
+        }
+    }
+}
+
+pub struct Wave {
+    pub enabled: bool,
+    pub dac_enabled: bool,
+    // NR32 bit 5-6: 0 mute, 1 100%, 2 50%, 3 25%
+    pub output_level: u8,
+    pub period: u16,
+    pub length: Length,
+    pub ram: [u8; 16],
+    position: u8,
+    timer: i32,
+}
+
+impl Wave {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            dac_enabled: false,
+            output_level: 0,
+            period: 0,
+            length: Length::new(256),
+            ram: [0; 16],
+            position: 0,
+            timer: 0,
+        }
+    }
+
+    pub fn step(&mut self, cycles: i32) {
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += (2048 - self.period as i32) * 2;
+            self.position = (self.position + 1) & 31;
+        }
+    }
+
+    pub fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger();
+        self.position = 0;
+        self.timer = (2048 - self.period as i32) * 2;
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.output_level == 0 {
+            return 0;
+        }
+        let byte = self.ram[self.position as usize / 2];
+        let sample = if self.position & 1 == 0 {
+            byte >> 4
+        } else {
+            byte & 0x0F
+        };
+        sample >> (self.output_level - 1)
+    }
+}
+
+pub struct Noise {
+    pub enabled: bool,
+    // NR43: bit 4-7 shift, bit 3 7-bit mode, bit 0-2 divisor code
+    pub register: u8,
+    pub length: Length,
+    pub envelope: Envelope,
+    lfsr: u16,
+    timer: i32,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            register: 0,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            lfsr: 0,
+            timer: 0,
+        }
+    }
+
+    fn timer_period(&self) -> i32 {
+        let divisor = match self.register & 0x07 {
+            0 => 8,
+            code => code as i32 * 16,
+        };
+        divisor << (self.register >> 4)
+    }
+
+    pub fn step(&mut self, cycles: i32) {
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += self.timer_period();
+            let bit = !(self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr & !(1 << 15)) | (bit << 15);
+            if self.register & 0x08 != 0 {
+                self.lfsr = (self.lfsr & !(1 << 7)) | (bit << 7);
+            }
+            self.lfsr >>= 1;
+        }
+    }
+
+    pub fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.envelope.trigger();
+        self.lfsr = 0;
+        self.timer = self.timer_period();
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        (self.lfsr & 1) as u8 * self.envelope.volume
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_square_duty() {
+        let mut square = Square::new();
+        square.envelope.register = 0xF0;
+        square.duty = 2;
+        square.period = 2047;
+        square.trigger();
+
+        // 50% duty, each step lasts 4 clock cycles at period 2047
+        let mut output = Vec::new();
+        for _ in 0..8 {
+            square.step(4);
+            output.push(square.output());
+        }
+        assert_eq!(output, [0, 0, 0, 0, 15, 15, 15, 15]);
+    }
+
+    #[test]
+    fn test_length_turns_off() {
+        let mut square = Square::new();
+        square.envelope.register = 0xF0;
+        square.length.load(62);
+        square.length.enabled = true;
+        square.trigger();
+        assert!(square.length.clock());
+        assert!(!square.length.clock());
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mut envelope = Envelope::new();
+        // volume 2, decreasing every clock
+        envelope.register = 0x21;
+        envelope.trigger();
+        envelope.clock();
+        assert_eq!(envelope.volume, 1);
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.volume, 0);
+        assert!(envelope.dac_enabled());
+    }
+
+    #[test]
+    fn test_sweep_overflow() {
+        let mut square = Square::new();
+        let mut sweep = Sweep::new();
+        square.envelope.register = 0xF0;
+        square.period = 0x700;
+        square.trigger();
+        // pace 1, increasing, shift 1: 0x700 + 0x380 overflows right away
+        sweep.register = 0x11;
+        sweep.trigger(&mut square);
+        assert!(!square.enabled);
+
+        square.period = 0x100;
+        square.trigger();
+        sweep.trigger(&mut square);
+        sweep.clock(&mut square);
+        assert_eq!(square.period, 0x180);
+        assert!(square.enabled);
+    }
+
+    #[test]
+    fn test_wave_output_level() {
+        let mut wave = Wave::new();
+        wave.dac_enabled = true;
+        wave.ram[0] = 0xF8;
+        wave.output_level = 1;
+        wave.period = 2047;
+        wave.trigger();
+        assert_eq!(wave.output(), 0x0F);
+        wave.output_level = 3;
+        assert_eq!(wave.output(), 0x03);
+        wave.step(2);
+        assert_eq!(wave.output(), 0x02);
+    }
+
+    #[test]
+    fn test_noise_lfsr() {
+        let mut noise = Noise::new();
+        noise.envelope.register = 0xF0;
+        noise.trigger();
+        // 0 XNOR 0 shifts ones in from the top
+        noise.step(8);
+        assert_eq!(noise.lfsr, 0x4000);
+        assert_eq!(noise.output(), 0);
+
+        // 7-bit mode also sets bit 6
+        noise.register = 0x08;
+        noise.trigger();
+        noise.step(8);
+        assert_eq!(noise.lfsr, 0x4040);
+    }
+}
@@ -0,0 +1,11 @@
+mod apu;
+mod blip;
+mod buffer;
+mod channels;
+mod resampler;
+mod wav;
+
+pub use apu::Apu;
+pub use buffer::SampleBuffer;
+pub use resampler::DEFAULT_SAMPLE_RATE;
+pub use wav::WavWriter;
@@ -0,0 +1,142 @@
+/*
+Resampler:
+Turns the stereo amplitude of the APU into samples at the output rate, 48 kHz by default.
+Time is counted in emulated clock cycles, so skipped frames produce the same audio as rendered ones.
+When fast-forwarding by a speed factor the clock rate is scaled by it, the output keeps its real time
+rate and plays faster and higher instead of piling up.
+
+The high-pass filter of the hardware removes the DC offset of the DACs on the output samples.
+Samples wait in a ring buffer of a quarter second, the oldest are dropped when not drained in time.
+*/
+
+use super::blip::BlipBuffer;
+use super::buffer::SampleBuffer;
+use crate::core::DEFAULT_TIMER_FREQUENCY;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+// the high-pass filter capacitor keeps this much of its charge every clock cycle
+const CAPACITOR_CHARGE: f64 = 0.999_958;
+
+// the amplitude is at most 4 channels * 15 * volume 8, scaled to the i16 range
+const SCALE: f32 = 64.0;
+
+pub struct Resampler {
+    sample_rate: u32,
+    speed: f64,
+    blips: [BlipBuffer; 2],
+    levels: [f32; 2],
+    capacitors: [f32; 2],
+    // capacitor charge kept for every output sample
+    charge: f32,
+    samples: SampleBuffer,
+}
+
+impl Resampler {
+    pub fn new(sample_rate: u32) -> Self {
+        let clock_rate = DEFAULT_TIMER_FREQUENCY as f64;
+        Self {
+            sample_rate,
+            speed: 1.0,
+            blips: [
+                BlipBuffer::new(clock_rate, sample_rate as f64),
+                BlipBuffer::new(clock_rate, sample_rate as f64),
+            ],
+            levels: [0.0; 2],
+            capacitors: [0.0; 2],
+            charge: capacitor_charge(sample_rate),
+            samples: SampleBuffer::new(sample_rate as usize / 4),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // the buffered samples are dropped, they were made for the old rate
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let speed = self.speed;
+        *self = Self::new(sample_rate);
+        self.set_speed(speed);
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    // 2.0 runs twice as fast as real time, the speed must be positive and finite
+    pub fn set_speed(&mut self, speed: f64) {
+        assert!(speed.is_finite() && speed > 0.0, "invalid speed {}", speed);
+        self.speed = speed;
+        let clock_rate = DEFAULT_TIMER_FREQUENCY as f64 * speed;
+        for blip in self.blips.iter_mut() {
+            blip.set_rates(clock_rate, self.sample_rate as f64);
+        }
+    }
+
+    pub fn samples(&mut self) -> &mut SampleBuffer {
+        &mut self.samples
+    }
+
+    // set the amplitude of both sides from now on
+    pub fn set_levels(&mut self, levels: [f32; 2]) {
+        for ((blip, level), new_level) in self.blips.iter_mut().zip(&mut self.levels).zip(levels) {
+            if new_level != *level {
+                blip.add_delta(new_level - *level);
+                *level = new_level;
+            }
+        }
+    }
+
+    // advance by clock cycles, push the samples completed
+    pub fn advance(&mut self, cycles: u32) {
+        for blip in self.blips.iter_mut() {
+            blip.advance(cycles);
+        }
+        // both sides advance the same way, they complete their samples together
+        while let (Some(left), Some(right)) =
+            (self.blips[0].read_sample(), self.blips[1].read_sample())
+        {
+            let left = self.high_pass(0, left);
+            let right = self.high_pass(1, right);
+            self.samples.push(left, right);
+        }
+    }
+
+    fn high_pass(&mut self, side: usize, input: f32) -> i16 {
+        let output = input - self.capacitors[side];
+        self.capacitors[side] = input - output * self.charge;
+        (output * SCALE).clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    }
+}
+
+fn capacitor_charge(sample_rate: u32) -> f32 {
+    CAPACITOR_CHARGE.powf(DEFAULT_TIMER_FREQUENCY as f64 / sample_rate as f64) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_rate() {
+        let mut resampler = Resampler::new(44_100);
+        resampler.set_levels([10.0, -10.0]);
+        resampler.advance(DEFAULT_TIMER_FREQUENCY as u32 / 10);
+        assert!((4409..=4410).contains(&resampler.samples().len()));
+
+        // the step passes the high-pass filter, with the sides apart
+        let mut output = vec![0; 8820];
+        resampler.samples().drain_into(&mut output);
+        assert!(output[100] > 500 && output[101] < -500);
+    }
+
+    #[test]
+    fn test_speed() {
+        let mut resampler = Resampler::new(DEFAULT_SAMPLE_RATE);
+        resampler.set_speed(2.0);
+        resampler.advance(DEFAULT_TIMER_FREQUENCY as u32 / 2);
+        // half an emulated second at double speed plays for a quarter second
+        assert!((11_999..=12_000).contains(&resampler.samples().len()));
+    }
+}
@@ -0,0 +1,101 @@
+/*
+WAV writer:
+16-bit PCM stereo, the RIFF and data chunk sizes are patched in when the writer is finished.
+
+0x00 "RIFF", file size - 8
+0x08 "WAVE"
+0x0C "fmt ", 16, format 1 (PCM), 2 channels, sample rate, byte rate, block align 4, 16 bits
+0x24 "data", data size, the samples as interleaved little endian left, right pairs
+*/
+
+use crate::core::Error;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::result::Result;
+
+const HEADER_SIZE: u32 = 44;
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+const BLOCK_ALIGN: u16 = CHANNELS * BITS_PER_SAMPLE / 8;
+
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    data_size: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> Result<Self, Error> {
+        Self::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> Result<Self, Error> {
+        let byte_rate = sample_rate * BLOCK_ALIGN as u32;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(HEADER_SIZE - 8).to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&CHANNELS.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&byte_rate.to_le_bytes())?;
+        writer.write_all(&BLOCK_ALIGN.to_le_bytes())?;
+        writer.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(Self {
+            writer,
+            data_size: 0,
+        })
+    }
+
+    // interleaved left, right pairs
+    pub fn write_samples(&mut self, samples: &[i16]) -> Result<(), Error> {
+        for sample in samples {
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_size += samples.len() as u32 * 2;
+        Ok(())
+    }
+
+    // write the chunk sizes, return the writer
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer
+            .write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_wav_header() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 48_000).unwrap();
+        wav.write_samples(&[1, -1, 0x1234, 0]).unwrap();
+        let bytes = wav.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 8);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(bytes[4..8], 44u32.to_le_bytes());
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(bytes[22..24], 2u16.to_le_bytes());
+        assert_eq!(bytes[24..28], 48_000u32.to_le_bytes());
+        assert_eq!(bytes[28..32], 192_000u32.to_le_bytes());
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(bytes[40..44], 8u32.to_le_bytes());
+        assert_eq!(
+            bytes[44..],
+            [0x01, 0x00, 0xFF, 0xFF, 0x34, 0x12, 0x00, 0x00]
+        );
+    }
+}
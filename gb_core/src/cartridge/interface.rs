@@ -22,6 +22,26 @@ pub trait Cartridge {
     where
         Self: Cartridge + Sized;
 
+    // advance by clock cycles, for cartridges with a clock
+    fn step(&mut self, _cycles: u32) {}
+
+    // battery backed cartridges keep their save data between sessions
+    fn has_battery(&self) -> bool {
+        false
+    }
+
+    // the ram, and any other state kept by the battery, as written to a save file
+    fn save_data(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_save_data(&mut self, _bytes: &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // advance a clock by the host time since its save data was written, timestamp in UNIX seconds
+    fn advance_clock_to(&mut self, _timestamp: u64) {}
+
     fn get_rom(&self) -> &Vec<Vec<u8>>;
     fn get_ram(&mut self) -> &mut Vec<Vec<u8>>;
     fn get_header(&self) -> &CartridgeHeader;
@@ -63,8 +83,8 @@ impl CartridgeHeader {
         // debug!("header checksum: {:04X}", header_checksum);
         let mut checksum = 0i32;
         // debug!("bytes: {:?}", &bytes[0x134..0x14d]);
-        for i in 0x134..0x14d {
-            checksum = checksum - (bytes[i] as i32) - 1;
+        for byte in &bytes[0x134..0x14d] {
+            checksum = checksum - (*byte as i32) - 1;
         }
         // debug!("checksum: {}", checksum);
         // if the lower 8 bit of checksum doesn't match, throw error
@@ -0,0 +1,185 @@
+use super::*;
+use crate::{core::Error, implement_cartridge_getters};
+
+// 512 half bytes of RAM are built into the MBC2
+const RAM_SIZE: usize = 0x200;
+
+#[derive(Debug)]
+pub struct MBC2Cartridge {
+    /*
+    supports up to 256 kb rom, which equals to 16 rom banks
+    has 512 x 4 bits of built-in ram, the header ram size is 0
+    bank switch behaviors, bit 8 of the address selects the register
+    0000 - 3FFF, bit 8 clear: write 0x_A will enable the RAM, any other values will disable it.
+    0000 - 3FFF, bit 8 set: lower 4 bits select the rom bank at 4000 - 7FFF, 0x00 is set as 0x01.
+    A000 - A1FF: the lower 4 bits of each byte are stored, the upper 4 bits read as 1.
+    A200 - BFFF: echoes A000 - A1FF.
+    type 0x06 has a battery to keep the ram.
+    */
+    rom: Vec<Vec<u8>>,
+    ram: Vec<Vec<u8>>,
+    header: CartridgeHeader,
+    rom_idx: u8,
+    ram_enabled: bool,
+}
+
+impl Cartridge for MBC2Cartridge {
+    fn read_byte(&self, address: u16) -> Result<u8, Error> {
+        let val = match address {
+            0x0000..=0x3FFF => self.rom[0][address as usize],
+            0x4000..=0x7FFF => self.rom[self.rom_idx as usize][address as usize - 0x4000],
+            0xA000..=0xBFFF => {
+                if self.ram_enabled {
+                    self.ram[0][address as usize & (RAM_SIZE - 1)] | 0xF0
+                } else {
+                    OPENBUS
+                }
+            }
+            _ => return Err(Error::CartridgeAddressError),
+        };
+        Ok(val)
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) -> Result<(), Error> {
+        match address {
+            0x0000..=0x3FFF if address & 0x0100 == 0 => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+            }
+            0x0000..=0x3FFF => {
+                let masked_index = (value & 0x0F) as usize % self.rom.len();
+                self.rom_idx = if value & 0x0F == 0 {
+                    1
+                } else {
+                    masked_index as u8
+                };
+            }
+            // no registers here
+            0x4000..=0x7FFF => {}
+            0xA000..=0xBFFF => {
+                if self.ram_enabled {
+                    self.ram[0][address as usize & (RAM_SIZE - 1)] = value & 0x0F;
+                }
+            }
+            _ => return Err(Error::CartridgeAddressError),
+        }
+        Ok(())
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Result<Self, Error> {
+        let header = CartridgeHeader::from_bytes(&bytes)?;
+        let rom_bank_numbers = (header.rom_size as usize / 0x4000).clamp(2, 16);
+        let rom: Vec<Vec<u8>> = bytes
+            .chunks(0x4000)
+            .take(rom_bank_numbers)
+            .map(|bank| bank.to_vec())
+            .collect();
+        if rom.len() < 2 || rom[rom.len() - 1].len() < 0x4000 {
+            return Err(Error::CartridgeFileHeaderError);
+        }
+
+        Ok(Self {
+            rom,
+            ram: vec![vec![0; RAM_SIZE]],
+            header,
+            rom_idx: 1,
+            ram_enabled: false,
+        })
+    }
+
+    fn has_battery(&self) -> bool {
+        self.header.cartridge_type == 0x06
+    }
+
+    // one byte for every half byte of ram
+    fn save_data(&self) -> Vec<u8> {
+        self.ram[0].clone()
+    }
+
+    fn load_save_data(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if bytes.len() != RAM_SIZE {
+            return Err(Error::SaveDataSizeError);
+        }
+        for (byte, saved) in self.ram[0].iter_mut().zip(bytes) {
+            *byte = saved & 0x0F;
+        }
+        Ok(())
+    }
+
+    implement_cartridge_getters!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::{test_rom, test_rom_byte};
+
+    fn test_cartridge() -> MBC2Cartridge {
+        // 256 kb rom, with battery
+        MBC2Cartridge::from_bytes(test_rom(0x06, 0x03, 0x00)).unwrap()
+    }
+
+    #[test]
+    fn test_mbc2_rom_banks() {
+        let mut cartridge = test_cartridge();
+        assert_eq!(cartridge.get_rom().len(), 16);
+        assert_eq!(cartridge.read_byte(0x4000).unwrap(), test_rom_byte(0x4000));
+
+        // bit 8 set selects the rom bank
+        cartridge.write_byte(0x2100, 0x05).unwrap();
+        assert_eq!(cartridge.read_byte(0x4123).unwrap(), test_rom_byte(0x14123));
+        // only the lower 4 bits are used, 0 selects bank 1
+        cartridge.write_byte(0x0100, 0xFF).unwrap();
+        assert_eq!(cartridge.read_byte(0x4000).unwrap(), test_rom_byte(0x3C000));
+        cartridge.write_byte(0x3FFF, 0x10).unwrap();
+        assert_eq!(cartridge.read_byte(0x4000).unwrap(), test_rom_byte(0x4000));
+
+        // bit 8 clear is the ram enable, the bank stays
+        cartridge.write_byte(0x2000, 0x05).unwrap();
+        assert_eq!(cartridge.read_byte(0x4000).unwrap(), test_rom_byte(0x4000));
+        // 4000 - 7FFF has no registers
+        cartridge.write_byte(0x4100, 0x05).unwrap();
+        assert_eq!(cartridge.read_byte(0x4000).unwrap(), test_rom_byte(0x4000));
+    }
+
+    #[test]
+    fn test_mbc2_ram() {
+        let mut cartridge = test_cartridge();
+        // disabled by default
+        cartridge.write_byte(0xA000, 0x05).unwrap();
+        assert_eq!(cartridge.read_byte(0xA000).unwrap(), 0xFF);
+
+        // bit 8 set selects the rom bank instead
+        cartridge.write_byte(0x0100, 0x0A).unwrap();
+        assert_eq!(cartridge.read_byte(0xA000).unwrap(), 0xFF);
+
+        cartridge.write_byte(0x0000, 0x1A).unwrap();
+        cartridge.write_byte(0xA000, 0xA5).unwrap();
+        assert_eq!(cartridge.read_byte(0xA000).unwrap(), 0xF5);
+        // the 512 half bytes echo up to BFFF
+        assert_eq!(cartridge.read_byte(0xA200).unwrap(), 0xF5);
+        cartridge.write_byte(0xBFFF, 0x03).unwrap();
+        assert_eq!(cartridge.read_byte(0xA1FF).unwrap(), 0xF3);
+
+        cartridge.write_byte(0x0000, 0x00).unwrap();
+        assert_eq!(cartridge.read_byte(0xA000).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn test_mbc2_save_data() {
+        let mut cartridge = test_cartridge();
+        assert!(cartridge.has_battery());
+        cartridge.write_byte(0x0000, 0x0A).unwrap();
+        cartridge.write_byte(0xA010, 0x07).unwrap();
+        let save = cartridge.save_data();
+        assert_eq!(save.len(), 512);
+
+        let mut loaded = test_cartridge();
+        assert!(loaded.load_save_data(&save[..256]).is_err());
+        loaded.load_save_data(&save).unwrap();
+        loaded.write_byte(0x0000, 0x0A).unwrap();
+        assert_eq!(loaded.read_byte(0xA010).unwrap(), 0xF7);
+
+        let cartridge = MBC2Cartridge::from_bytes(test_rom(0x05, 0x00, 0x00)).unwrap();
+        assert!(!cartridge.has_battery());
+    }
+}
@@ -0,0 +1,283 @@
+use super::rtc::{Rtc, RTC_SAVE_SIZE, RTC_SAVE_SIZE_32};
+use super::*;
+use crate::{core::Error, implement_cartridge_getters};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug)]
+pub struct MBC3Cartridge {
+    /*
+    supports up to 2 mb rom, which equals to 128 rom banks
+    supports up to 32 kb ram, which equals to 4 ram banks
+    types 0x0F and 0x10 have a real time clock, 0x0F, 0x10 and 0x13 have a battery.
+    bank switch behaviors
+    0000 - 1FFF: write 0x0A will enable the RAM and the clock registers, any other values will disable them.
+    2000 - 3FFF: lower 7 bits select the rom bank, if set to 0x00, it will be set as 0x01.
+    4000 - 5FFF: 0x00 - 0x03 select the ram bank, 0x08 - 0x0C select a clock register at A000 - BFFF.
+    6000 - 7FFF: writing 0x00 then 0x01 latches the clock registers.
+    */
+    rom: Vec<Vec<u8>>,
+    ram: Vec<Vec<u8>>,
+    header: CartridgeHeader,
+    rom_idx: u8,
+    // ram bank, or clock register from 0x08
+    ram_idx: u8,
+    ram_enabled: bool,
+    rtc: Option<Rtc>,
+    // the UNIX time the loaded save data was written
+    saved_at: Option<u64>,
+}
+
+impl MBC3Cartridge {
+    fn ram_bytes(&self) -> usize {
+        self.header.ram_size as usize
+    }
+}
+
+impl Cartridge for MBC3Cartridge {
+    fn read_byte(&self, address: u16) -> Result<u8, Error> {
+        let val = match address {
+            0x0000..=0x3FFF => self.rom[0][address as usize],
+            0x4000..=0x7FFF => self.rom[self.rom_idx as usize][address as usize - 0x4000],
+            0xA000..=0xBFFF => match (self.ram_idx, &self.rtc) {
+                _ if !self.ram_enabled => OPENBUS,
+                (0x00..=0x03, _) if !self.ram.is_empty() => {
+                    let bank = self.ram_idx as usize % self.ram.len();
+                    self.ram[bank][(address as usize - 0xA000) % self.ram[bank].len()]
+                }
+                (0x08..=0x0C, Some(rtc)) => rtc.read(self.ram_idx),
+                _ => OPENBUS,
+            },
+            _ => return Err(Error::CartridgeAddressError),
+        };
+        Ok(val)
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) -> Result<(), Error> {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                let masked_index = (value & 0x7F) as usize % self.rom.len();
+                self.rom_idx = if value & 0x7F == 0 {
+                    1
+                } else {
+                    masked_index as u8
+                };
+            }
+            0x4000..=0x5FFF => self.ram_idx = value,
+            0x6000..=0x7FFF => {
+                if let Some(rtc) = self.rtc.as_mut() {
+                    rtc.write_latch(value);
+                }
+            }
+            0xA000..=0xBFFF => match (self.ram_idx, self.rtc.as_mut()) {
+                _ if !self.ram_enabled => {}
+                (0x00..=0x03, _) if !self.ram.is_empty() => {
+                    let bank = self.ram_idx as usize % self.ram.len();
+                    let offset = (address as usize - 0xA000) % self.ram[bank].len();
+                    self.ram[bank][offset] = value;
+                }
+                (0x08..=0x0C, Some(rtc)) => rtc.write(self.ram_idx, value),
+                _ => {}
+            },
+            _ => return Err(Error::CartridgeAddressError),
+        }
+        Ok(())
+    }
+
+    fn from_bytes(bytes: Vec<u8>) -> Result<Self, Error> {
+        let header = CartridgeHeader::from_bytes(&bytes)?;
+        let rom_bank_numbers = (header.rom_size as usize / 0x4000).clamp(2, 128);
+        let rom: Vec<Vec<u8>> = bytes
+            .chunks(0x4000)
+            .take(rom_bank_numbers)
+            .map(|bank| bank.to_vec())
+            .collect();
+        if rom.len() < 2 || rom[rom.len() - 1].len() < 0x4000 {
+            return Err(Error::CartridgeFileHeaderError);
+        }
+        // 2 kb ram is a single smaller bank
+        let ram_size = header.ram_size as usize;
+        let ram = vec![vec![0; ram_size.min(0x2000)]; ram_size.div_ceil(0x2000)];
+        let rtc = matches!(header.cartridge_type, 0x0F | 0x10).then(Rtc::new);
+
+        Ok(Self {
+            rom,
+            ram,
+            header,
+            rom_idx: 1,
+            ram_idx: 0,
+            ram_enabled: false,
+            rtc,
+            saved_at: None,
+        })
+    }
+
+    fn step(&mut self, cycles: u32) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.step(cycles);
+        }
+    }
+
+    fn has_battery(&self) -> bool {
+        matches!(self.header.cartridge_type, 0x0F | 0x10 | 0x13)
+    }
+
+    // the ram, then the clock in the 48 byte format
+    fn save_data(&self) -> Vec<u8> {
+        let mut bytes = self.ram.concat();
+        if let Some(rtc) = &self.rtc {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs());
+            bytes.extend_from_slice(&rtc.save(now));
+        }
+        bytes
+    }
+
+    // the clock is optional, saves without it keep the clock running from 0
+    fn load_save_data(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let ram_bytes = self.ram_bytes();
+        let clock_bytes = bytes.len().wrapping_sub(ram_bytes);
+        match (&mut self.rtc, clock_bytes) {
+            (_, 0) => {}
+            (Some(rtc), RTC_SAVE_SIZE | RTC_SAVE_SIZE_32) => {
+                self.saved_at = Some(rtc.load(&bytes[ram_bytes..]));
+            }
+            _ => return Err(Error::SaveDataSizeError),
+        }
+        let mut saved_ram = bytes[..ram_bytes].chunks(0x2000);
+        for (bank, saved) in self.ram.iter_mut().zip(&mut saved_ram) {
+            bank.copy_from_slice(saved);
+        }
+        Ok(())
+    }
+
+    // the clock keeps running while the emulator is closed
+    fn advance_clock_to(&mut self, timestamp: u64) {
+        if let (Some(rtc), Some(saved_at)) = (self.rtc.as_mut(), self.saved_at) {
+            rtc.advance(timestamp.saturating_sub(saved_at));
+            self.saved_at = Some(timestamp);
+        }
+    }
+
+    implement_cartridge_getters!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::{test_rom, test_rom_byte};
+    use crate::core::DEFAULT_TIMER_FREQUENCY;
+
+    // 2 mb rom, 32 kb ram, timer and battery
+    fn test_cartridge() -> MBC3Cartridge {
+        MBC3Cartridge::from_bytes(test_rom(0x10, 0x06, 0x03)).unwrap()
+    }
+
+    #[test]
+    fn test_mbc3_rom_banks() {
+        let mut cartridge = test_cartridge();
+        assert_eq!(cartridge.get_rom().len(), 128);
+        assert_eq!(cartridge.read_byte(0x4000).unwrap(), test_rom_byte(0x4000));
+
+        // 7 bits select the bank, 0 selects bank 1
+        cartridge.write_byte(0x2000, 0x7F).unwrap();
+        assert_eq!(
+            cartridge.read_byte(0x4321).unwrap(),
+            test_rom_byte(0x7F * 0x4000 + 0x321)
+        );
+        cartridge.write_byte(0x3FFF, 0x80).unwrap();
+        assert_eq!(cartridge.read_byte(0x4000).unwrap(), test_rom_byte(0x4000));
+        cartridge.write_byte(0x2000, 0x45).unwrap();
+        assert_eq!(
+            cartridge.read_byte(0x7FFF).unwrap(),
+            test_rom_byte(0x45 * 0x4000 + 0x3FFF)
+        );
+    }
+
+    #[test]
+    fn test_mbc3_ram_banks() {
+        let mut cartridge = test_cartridge();
+        assert_eq!(cartridge.get_ram().len(), 4);
+        // disabled by default
+        cartridge.write_byte(0xA000, 0x12).unwrap();
+        assert_eq!(cartridge.read_byte(0xA000).unwrap(), 0xFF);
+
+        cartridge.write_byte(0x0000, 0x0A).unwrap();
+        for bank in 0..4 {
+            cartridge.write_byte(0x4000, bank).unwrap();
+            cartridge.write_byte(0xA000, 0x10 + bank).unwrap();
+        }
+        for bank in 0..4 {
+            cartridge.write_byte(0x4000, bank).unwrap();
+            assert_eq!(cartridge.read_byte(0xA000).unwrap(), 0x10 + bank);
+        }
+        // no register at 0x04 - 0x07
+        cartridge.write_byte(0x4000, 0x05).unwrap();
+        assert_eq!(cartridge.read_byte(0xA000).unwrap(), 0xFF);
+    }
+
+    #[test]
+    fn test_mbc3_rtc_registers() {
+        let mut cartridge = test_cartridge();
+        cartridge.write_byte(0x0000, 0x0A).unwrap();
+        cartridge.write_byte(0x4000, 0x09).unwrap();
+        cartridge.write_byte(0xA000, 30).unwrap();
+
+        // a minute of emulated time
+        cartridge.step(DEFAULT_TIMER_FREQUENCY as u32 * 60);
+        assert_eq!(cartridge.read_byte(0xA000).unwrap(), 30);
+        cartridge.write_byte(0x6000, 0x00).unwrap();
+        cartridge.write_byte(0x6000, 0x01).unwrap();
+        assert_eq!(cartridge.read_byte(0xA000).unwrap(), 31);
+
+        // the ram banks are still there
+        cartridge.write_byte(0x4000, 0x00).unwrap();
+        cartridge.write_byte(0xA000, 0x42).unwrap();
+        assert_eq!(cartridge.read_byte(0xA000).unwrap(), 0x42);
+
+        // without a timer the registers read open bus
+        let mut cartridge = MBC3Cartridge::from_bytes(test_rom(0x13, 0x00, 0x02)).unwrap();
+        cartridge.write_byte(0x0000, 0x0A).unwrap();
+        cartridge.write_byte(0x4000, 0x08).unwrap();
+        cartridge.write_byte(0xA000, 0x12).unwrap();
+        assert_eq!(cartridge.read_byte(0xA000).unwrap(), 0xFF);
+        assert!(cartridge.has_battery());
+    }
+
+    #[test]
+    fn test_mbc3_save_data() {
+        let mut cartridge = test_cartridge();
+        cartridge.write_byte(0x0000, 0x0A).unwrap();
+        cartridge.write_byte(0x4000, 0x03).unwrap();
+        cartridge.write_byte(0xBFFF, 0x42).unwrap();
+        cartridge.write_byte(0x4000, 0x0A).unwrap();
+        cartridge.write_byte(0xA000, 5).unwrap();
+        let save = cartridge.save_data();
+        assert_eq!(save.len(), 0x8000 + 48);
+        assert_eq!(save[0x7FFF], 0x42);
+        assert_eq!(save[0x8000 + 8], 5);
+
+        let mut loaded = test_cartridge();
+        assert!(loaded.load_save_data(&save[..0x8000 + 20]).is_err());
+        loaded.load_save_data(&save).unwrap();
+        loaded.write_byte(0x0000, 0x0A).unwrap();
+        loaded.write_byte(0x4000, 0x03).unwrap();
+        assert_eq!(loaded.read_byte(0xBFFF).unwrap(), 0x42);
+        loaded.write_byte(0x4000, 0x0A).unwrap();
+        assert_eq!(loaded.read_byte(0xA000).unwrap(), 5);
+
+        // two hours and a minute on the host between the sessions
+        let saved_at = u64::from_le_bytes(save[0x8028..].try_into().unwrap());
+        loaded.advance_clock_to(saved_at + 7260);
+        loaded.write_byte(0x6000, 0x00).unwrap();
+        loaded.write_byte(0x6000, 0x01).unwrap();
+        assert_eq!(loaded.read_byte(0xA000).unwrap(), 7);
+        loaded.write_byte(0x4000, 0x09).unwrap();
+        assert_eq!(loaded.read_byte(0xA000).unwrap(), 1);
+
+        // the ram alone is accepted too
+        let mut loaded = test_cartridge();
+        loaded.load_save_data(&save[..0x8000]).unwrap();
+    }
+}
@@ -1,12 +1,17 @@
 pub mod interface;
 mod mbc1;
+mod mbc2;
+mod mbc3;
 pub mod rom_only;
+mod rtc;
 
 use crate::core::Error;
 pub use interface::{Cartridge, CartridgeHeader};
 
 use log::debug;
 pub use mbc1::MBC1Cartridge;
+pub use mbc2::MBC2Cartridge;
+pub use mbc3::MBC3Cartridge;
 pub use rom_only::RomOnlyCartridge;
 use std::fs::File;
 use std::io::Read;
@@ -32,6 +37,8 @@ pub fn load_cartridge_from_file(path: &str) -> Result<Box<dyn Cartridge>, Error>
     let cartridge: Box<dyn Cartridge> = match header.cartridge_type {
         0x00 => Box::new(RomOnlyCartridge::from_bytes(bytes)?),
         0x01 => Box::new(MBC1Cartridge::from_bytes(bytes)?),
+        0x05 | 0x06 => Box::new(MBC2Cartridge::from_bytes(bytes)?),
+        0x0F..=0x13 => Box::new(MBC3Cartridge::from_bytes(bytes)?),
         _ => return Err(Error::CartridgeTypeUnsupported),
     };
     Ok(cartridge)
@@ -62,6 +69,26 @@ pub mod tests {
     use std::fs::File;
     use std::io::{Read, Seek, SeekFrom};
 
+    // the byte a test rom holds at the given offset, distinct for every bank and address
+    pub fn test_rom_byte(offset: usize) -> u8 {
+        (offset >> 14) as u8 ^ (offset >> 8) as u8 ^ offset as u8
+    }
+
+    // a rom image with a valid header, rom_size and ram_size are the header codes at 0x0148 and 0x0149
+    pub fn test_rom(cartridge_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+        let mut bytes: Vec<u8> = (0..0x8000 << rom_size).map(test_rom_byte).collect();
+        bytes[0x143] = 0x00;
+        bytes[0x147] = cartridge_type;
+        bytes[0x148] = rom_size;
+        bytes[0x149] = ram_size;
+        let mut checksum = 0u8;
+        for byte in &bytes[0x134..0x14D] {
+            checksum = checksum.wrapping_sub(*byte).wrapping_sub(1);
+        }
+        bytes[0x14D] = checksum;
+        bytes
+    }
+
     #[test]
     #[test_log::test]
     fn test_load_file() {
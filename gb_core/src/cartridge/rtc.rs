@@ -0,0 +1,270 @@
+/*
+Real time clock of the MBC3:
+08 S:  seconds 0 - 59
+09 M:  minutes 0 - 59
+0A H:  hours 0 - 23
+0B DL: lower 8 bits of the day counter
+0C DH: bit 0 is bit 8 of the day counter, bit 6 halts the clock, bit 7 is the day counter carry
+
+The registers are read through a latched copy, writing 0x00 then 0x01 to 6000 - 7FFF latches them.
+Writes go to the running registers, writing the seconds restarts the current second.
+The registers only keep their bits, a value out of range counts up until it wraps around to 0 without a carry.
+The carry stays set after the 511th day until it is written with 0.
+
+Save files append 48 bytes after the ram, all little endian:
+S, M, H, DL, DH as 4 bytes each, the latched S, M, H, DL, DH as 4 bytes each,
+then the UNIX time when it was saved as 8 bytes. Older saves have a 4 byte time.
+*/
+
+use crate::core::DEFAULT_TIMER_FREQUENCY;
+
+pub const RTC_SAVE_SIZE: usize = 48;
+pub const RTC_SAVE_SIZE_32: usize = 44;
+
+#[derive(Debug)]
+pub struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+    latched: [u8; 5],
+    // the last write to 6000 - 7FFF was 0x00
+    latch_armed: bool,
+    // clock cycles into the current second
+    cycles: u64,
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            latched: [0; 5],
+            latch_armed: false,
+            cycles: 0,
+        }
+    }
+
+    fn registers(&self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            (self.day_carry as u8) << 7 | (self.halted as u8) << 6 | (self.days >> 8) as u8,
+        ]
+    }
+
+    // register 0x08 - 0x0C from the latched copy
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched[(register - 0x08) as usize]
+    }
+
+    // register 0x08 - 0x0C
+    pub fn write(&mut self, register: u8, value: u8) {
+        match register {
+            0x08 => {
+                self.seconds = value & 0x3F;
+                self.cycles = 0;
+            }
+            0x09 => self.minutes = value & 0x3F,
+            0x0A => self.hours = value & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | value as u16,
+            _ => {
+                self.days = (self.days & 0xFF) | ((value & 0x01) as u16) << 8;
+                self.halted = value & 0x40 != 0;
+                self.day_carry = value & 0x80 != 0;
+            }
+        }
+        // the latched copy shows the write
+        self.latched = self.registers();
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 0x01 {
+            self.latched = self.registers();
+        }
+        self.latch_armed = value == 0x00;
+    }
+
+    // advance by emulated clock cycles, the clock crystal does not speed up in double speed
+    pub fn step(&mut self, cycles: u32) {
+        if self.halted {
+            return;
+        }
+        self.cycles += cycles as u64;
+        while self.cycles >= DEFAULT_TIMER_FREQUENCY {
+            self.cycles -= DEFAULT_TIMER_FREQUENCY;
+            self.tick();
+        }
+    }
+
+    fn tick(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days += 1;
+        if self.days == 512 {
+            self.days = 0;
+            self.day_carry = true;
+        }
+    }
+
+    // advance by whole seconds, used for the time between sessions
+    pub fn advance(&mut self, mut seconds: u64) {
+        if self.halted {
+            return;
+        }
+        // values out of range count up to their wrap around first
+        while seconds > 0 && (self.seconds >= 60 || self.minutes >= 60 || self.hours >= 24) {
+            self.tick();
+            seconds -= 1;
+        }
+        let total = self.seconds as u64 + seconds;
+        self.seconds = (total % 60) as u8;
+        let total = self.minutes as u64 + total / 60;
+        self.minutes = (total % 60) as u8;
+        let total = self.hours as u64 + total / 60;
+        self.hours = (total % 24) as u8;
+        let total = self.days as u64 + total / 24;
+        if total >= 512 {
+            self.day_carry = true;
+        }
+        self.days = (total % 512) as u16;
+    }
+
+    pub fn save(&self, timestamp: u64) -> [u8; RTC_SAVE_SIZE] {
+        let mut bytes = [0; RTC_SAVE_SIZE];
+        let registers = self.registers().into_iter().chain(self.latched);
+        for (chunk, register) in bytes.chunks_exact_mut(4).zip(registers) {
+            chunk[0] = register;
+        }
+        bytes[40..].copy_from_slice(&timestamp.to_le_bytes());
+        bytes
+    }
+
+    // restore from 48 or 44 bytes, return the UNIX time when it was saved
+    pub fn load(&mut self, bytes: &[u8]) -> u64 {
+        let register = |index: usize| bytes[index * 4];
+        self.write(0x08, register(0));
+        self.write(0x09, register(1));
+        self.write(0x0A, register(2));
+        self.write(0x0B, register(3));
+        self.write(0x0C, register(4));
+        for (index, latched) in self.latched.iter_mut().enumerate() {
+            *latched = register(5 + index);
+        }
+        let mut timestamp = [0; 8];
+        timestamp[..bytes.len() - 40].copy_from_slice(&bytes[40..]);
+        u64::from_le_bytes(timestamp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latched(rtc: &mut Rtc) -> [u8; 5] {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        [0x08, 0x09, 0x0A, 0x0B, 0x0C].map(|register| rtc.read(register))
+    }
+
+    #[test]
+    fn test_rtc_latch() {
+        let mut rtc = Rtc::new();
+        rtc.step(DEFAULT_TIMER_FREQUENCY as u32 * 3);
+        // not latched yet
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 0);
+        assert_eq!(latched(&mut rtc), [3, 0, 0, 0, 0]);
+
+        // the latched copy stays until the next latch
+        rtc.step(DEFAULT_TIMER_FREQUENCY as u32);
+        assert_eq!(rtc.read(0x08), 3);
+        assert_eq!(latched(&mut rtc)[0], 4);
+    }
+
+    #[test]
+    fn test_rtc_carry() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x08, 59);
+        rtc.write(0x09, 59);
+        rtc.write(0x0A, 23);
+        rtc.write(0x0B, 0xFF);
+        rtc.write(0x0C, 0x01);
+        rtc.step(DEFAULT_TIMER_FREQUENCY as u32);
+        // day 511 overflows to 0 and sets the carry
+        assert_eq!(latched(&mut rtc), [0, 0, 0, 0, 0x80]);
+
+        rtc.write(0x0B, 0xFF);
+        rtc.advance(86_400);
+        assert_eq!(latched(&mut rtc), [0, 0, 0, 0, 0x81]);
+    }
+
+    #[test]
+    fn test_rtc_halt() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x0C, 0x40);
+        rtc.step(DEFAULT_TIMER_FREQUENCY as u32 * 2);
+        rtc.advance(100);
+        assert_eq!(latched(&mut rtc), [0, 0, 0, 0, 0x40]);
+
+        rtc.write(0x0C, 0x00);
+        rtc.advance(3_723);
+        assert_eq!(latched(&mut rtc), [3, 2, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_rtc_out_of_range() {
+        let mut rtc = Rtc::new();
+        // only 6 bits are kept, 62 counts to 63 and wraps to 0 without a minute
+        rtc.write(0x08, 0xFE);
+        assert_eq!(latched(&mut rtc)[0], 62);
+        rtc.advance(2);
+        assert_eq!(latched(&mut rtc)[..2], [0, 0]);
+
+        rtc.write(0x0A, 30);
+        rtc.advance(2 * 3600 + 60);
+        assert_eq!(latched(&mut rtc), [0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_rtc_save() {
+        let mut rtc = Rtc::new();
+        rtc.write(0x08, 10);
+        rtc.write(0x0C, 0x41);
+        rtc.write_latch(0x00);
+        rtc.write(0x09, 20);
+        let bytes = rtc.save(0x1234_5678_9ABC);
+        assert_eq!(bytes[0..8], [10, 0, 0, 0, 20, 0, 0, 0]);
+        assert_eq!(bytes[16], 0x41);
+        assert_eq!(bytes[40..], 0x1234_5678_9ABCu64.to_le_bytes());
+
+        let mut loaded = Rtc::new();
+        assert_eq!(loaded.load(&bytes), 0x1234_5678_9ABC);
+        assert_eq!(loaded.registers(), rtc.registers());
+        assert_eq!(loaded.latched, rtc.latched);
+        // the older format with a 4 byte time
+        assert_eq!(loaded.load(&bytes[..RTC_SAVE_SIZE_32]), 0x5678_9ABC);
+    }
+}
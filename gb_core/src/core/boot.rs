@@ -0,0 +1,233 @@
+/*
+Boot:
+A boot rom supplied by the user is mapped over the cartridge at 0x0000 - 0x00FF,
+the 2304 byte CGB boot rom also at 0x0200 - 0x08FF, the cartridge header in between stays visible.
+The boot rom unmaps itself by writing 0xFF50 as its last instruction, then runs the cartridge from 0x0100.
+
+Without a boot rom, skip_boot sets the registers to the values the boot rom of the model leaves behind.
+DMG and MGB set the H and C flags unless the header checksum is 0.
+The boot sound leaves channel 1 on with its envelope decayed to volume 0.
+*/
+
+use super::bus::Bus;
+use super::cpu::CPU;
+use super::memory::Model;
+
+pub const BOOT_ROM_DISABLE: u16 = 0xFF50;
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+const HEADER_CHECKSUM: u16 = 0x014D;
+
+// DIV reads 0xAB on DMG and MGB, 0x1E on CGB when the cartridge starts
+const DMG_POST_BOOT_SYSTEM_COUNTER: u16 = 0xABCC;
+const CGB_POST_BOOT_SYSTEM_COUNTER: u16 = 0x1EA0;
+
+// IO registers after the boot rom of every model, NR52 first so the sound registers are accepted.
+// channel 1 is triggered with volume 0 before NR12 gets its final value, the DAC stays on.
+// the other NRx4 registers read 0xBF, they are written without the trigger bit
+const POST_BOOT_IO: [(u16, u8); 40] = [
+    (0xFF00, 0xCF), // P1
+    (0xFF01, 0x00), // SB
+    (0xFF05, 0x00), // TIMA
+    (0xFF06, 0x00), // TMA
+    (0xFF07, 0xF8), // TAC
+    (0xFF26, 0xF1), // NR52
+    (0xFF10, 0x80), // NR10
+    (0xFF11, 0xBF), // NR11
+    (0xFF12, 0x08), // NR12
+    (0xFF13, 0xFF), // NR13
+    (0xFF14, 0x87), // NR14
+    (0xFF12, 0xF3), // NR12
+    (0xFF16, 0x3F), // NR21
+    (0xFF17, 0x00), // NR22
+    (0xFF18, 0xFF), // NR23
+    (0xFF19, 0x3F), // NR24
+    (0xFF1A, 0x7F), // NR30
+    (0xFF1B, 0xFF), // NR31
+    (0xFF1C, 0x9F), // NR32
+    (0xFF1D, 0xFF), // NR33
+    (0xFF1E, 0x3F), // NR34
+    (0xFF20, 0xFF), // NR41
+    (0xFF21, 0x00), // NR42
+    (0xFF22, 0x00), // NR43
+    (0xFF23, 0x3F), // NR44
+    (0xFF24, 0x77), // NR50
+    (0xFF25, 0xF3), // NR51
+    (0xFF40, 0x91), // LCDC
+    (0xFF41, 0x85), // STAT
+    (0xFF42, 0x00), // SCY
+    (0xFF43, 0x00), // SCX
+    (0xFF45, 0x00), // LYC
+    (0xFF47, 0xFC), // BGP
+    (0xFF48, 0xFF), // OBP0
+    (0xFF49, 0xFF), // OBP1
+    (0xFF4A, 0x00), // WY
+    (0xFF4B, 0x00), // WX
+    (BOOT_ROM_DISABLE, 0x01),
+    (0xFF0F, 0xE1), // IF
+    (0xFFFF, 0x00), // IE
+];
+
+const DMG_POST_BOOT_IO: [(u16, u8); 1] = [
+    (0xFF02, 0x7E), // SC
+];
+
+// VBK, SVBK and HDMA1 - HDMA4 are ignored until the CGB banks are mapped,
+// HDMA5 is left alone, writing it starts a transfer. it reads 0xFF when idle
+const CGB_POST_BOOT_IO: [(u16, u8); 8] = [
+    (0xFF02, 0x7F), // SC
+    (0xFF4D, 0x00), // KEY1
+    (0xFF4F, 0x00), // VBK
+    (0xFF70, 0x00), // SVBK
+    (0xFF51, 0xFF), // HDMA1
+    (0xFF52, 0xFF), // HDMA2
+    (0xFF53, 0xFF), // HDMA3
+    (0xFF54, 0xFF), // HDMA4
+];
+
+impl CPU {
+    // start the cartridge at 0x0100 as if the boot rom of the bus model had run
+    pub fn skip_boot(&mut self) {
+        let model = self.memory_bus.model();
+        let (model_io, system_counter) = match model {
+            Model::Dmg | Model::Mgb => (&DMG_POST_BOOT_IO[..], DMG_POST_BOOT_SYSTEM_COUNTER),
+            Model::Cgb => (&CGB_POST_BOOT_IO[..], CGB_POST_BOOT_SYSTEM_COUNTER),
+        };
+        for &(address, value) in POST_BOOT_IO.iter().chain(model_io) {
+            self.memory_bus.write_byte(address, value);
+        }
+        self.memory_bus.set_system_counter(system_counter);
+
+        let checksum = self.memory_bus.try_read_byte(HEADER_CHECKSUM).unwrap_or(0);
+        let half_carry_carry = if checksum == 0 { 0x00 } else { 0x30 };
+        let (af, bc, de, hl) = match model {
+            Model::Dmg => (0x0180 | half_carry_carry, 0x0013, 0x00D8, 0x014D),
+            Model::Mgb => (0xFF80 | half_carry_carry, 0x0013, 0x00D8, 0x014D),
+            Model::Cgb => (0x1180, 0x0000, 0xFF56, 0x000D),
+        };
+        self.a = (af >> 8) as u8;
+        self.f = af as u8;
+        self.set_bc(bc);
+        self.set_de(de);
+        self.set_hl(hl);
+        self.sp = 0xFFFE;
+        self.pc = 0x0100;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::test_rom;
+    use crate::cartridge::Cartridge;
+    use crate::cartridge::RomOnlyCartridge;
+
+    fn test_cpu() -> CPU {
+        let mut cpu = CPU::new();
+        let cartridge = RomOnlyCartridge::from_bytes(test_rom(0x00, 0x00, 0x00)).unwrap();
+        cpu.memory_bus.load_cartridge(Box::new(cartridge));
+        cpu
+    }
+
+    #[test]
+    fn test_boot_rom_overlay() {
+        let mut cpu = test_cpu();
+        let cartridge_byte = cpu.memory_bus.read_byte(0x0000);
+        let mut boot_rom = vec![0x00; DMG_BOOT_ROM_SIZE];
+        // LD A, 0x01; LDH (0x50), A
+        boot_rom[0xFC..].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+        boot_rom[0] = !cartridge_byte;
+        cpu.memory_bus.load_boot_rom(boot_rom).unwrap();
+
+        assert_eq!(cpu.memory_bus.read_byte(0x0000), !cartridge_byte);
+        // the cartridge is visible after the boot rom
+        assert_eq!(cpu.memory_bus.read_byte(0x0100), test_rom(0, 0, 0)[0x100]);
+
+        cpu.pc = 0x00FC;
+        cpu.tick().unwrap();
+        cpu.tick().unwrap();
+        assert!(!cpu.memory_bus.boot_rom_mapped());
+        assert_eq!(cpu.pc, 0x0100);
+        assert_eq!(cpu.memory_bus.read_byte(0x0000), cartridge_byte);
+
+        // writing 0 does not map it again
+        cpu.memory_bus.write_byte(BOOT_ROM_DISABLE, 0x00);
+        assert_eq!(cpu.memory_bus.read_byte(0x0000), cartridge_byte);
+    }
+
+    #[test]
+    fn test_cgb_boot_rom_overlay() {
+        let mut cpu = test_cpu();
+        assert!(cpu.memory_bus.load_boot_rom(vec![0x42; 0x200]).is_err());
+
+        cpu.memory_bus
+            .load_boot_rom(vec![0x42; CGB_BOOT_ROM_SIZE])
+            .unwrap();
+        assert_eq!(cpu.memory_bus.read_byte(0x00FF), 0x42);
+        assert_eq!(cpu.memory_bus.read_byte(0x0200), 0x42);
+        assert_eq!(cpu.memory_bus.read_byte(0x08FF), 0x42);
+        // the header between the two parts comes from the cartridge
+        assert_eq!(cpu.memory_bus.read_byte(0x0147), 0x00);
+        assert_eq!(cpu.memory_bus.read_byte(0x0900), test_rom(0, 0, 0)[0x900]);
+    }
+
+    #[test]
+    fn test_skip_boot_dmg() {
+        let mut cpu = test_cpu();
+        cpu.skip_boot();
+        assert_eq!((cpu.a, cpu.f), (0x01, 0xB0));
+        assert_eq!((cpu.bc(), cpu.de(), cpu.hl()), (0x0013, 0x00D8, 0x014D));
+        assert_eq!((cpu.sp, cpu.pc), (0xFFFE, 0x0100));
+        assert_eq!(cpu.memory_bus.read_byte(0xFF04), 0xAB);
+        assert_eq!(cpu.memory_bus.read_byte(0xFF02), 0x7E);
+        assert_eq!(cpu.memory_bus.read_byte(0xFF40), 0x91);
+        assert_eq!(cpu.memory_bus.read_byte(0xFF47), 0xFC);
+        assert_eq!(cpu.memory_bus.read_byte(0xFF0F), 0xE1);
+        assert_eq!(cpu.memory_bus.read_byte(0xFF4D), 0xFF);
+        // channel 1 is on, silent, with the envelope of the boot sound
+        assert_eq!(cpu.memory_bus.read_byte(0xFF26), 0xF1);
+        assert_eq!(cpu.memory_bus.read_byte(0xFF12), 0xF3);
+        assert_eq!(cpu.memory_bus.read_byte(0xFF14), 0xBF);
+        assert!(cpu.memory_bus.take_fault().is_none());
+    }
+
+    #[test]
+    fn test_skip_boot_mgb() {
+        let mut cpu = test_cpu();
+        cpu.memory_bus.set_model(Model::Mgb);
+        cpu.skip_boot();
+        assert_eq!((cpu.a, cpu.f), (0xFF, 0xB0));
+        assert_eq!((cpu.bc(), cpu.de(), cpu.hl()), (0x0013, 0x00D8, 0x014D));
+        assert_eq!(cpu.memory_bus.read_byte(0xFF04), 0xAB);
+        assert_eq!(cpu.memory_bus.read_byte(0xFF02), 0x7E);
+        assert_eq!(cpu.memory_bus.read_byte(0xFF26), 0xF1);
+        assert!(cpu.memory_bus.take_fault().is_none());
+    }
+
+    #[test]
+    fn test_skip_boot_cgb() {
+        let mut rom = test_rom(0x00, 0x00, 0x00);
+        // a CGB game, the header checksum covers the flag
+        rom[0x143] = 0x80;
+        rom[0x14D] = rom[0x134..0x14D].iter().fold(0u8, |checksum, byte| {
+            checksum.wrapping_sub(*byte).wrapping_sub(1)
+        });
+        let mut cpu = CPU::new();
+        let cartridge = RomOnlyCartridge::from_bytes(rom).unwrap();
+        cpu.memory_bus.load_cartridge(Box::new(cartridge));
+        cpu.memory_bus.set_model(Model::Cgb);
+        cpu.skip_boot();
+        assert_eq!((cpu.a, cpu.f), (0x11, 0x80));
+        assert_eq!((cpu.bc(), cpu.de(), cpu.hl()), (0x0000, 0xFF56, 0x000D));
+        assert_eq!((cpu.sp, cpu.pc), (0xFFFE, 0x0100));
+        assert_eq!(cpu.memory_bus.read_byte(0xFF04), 0x1E);
+        assert_eq!(cpu.memory_bus.read_byte(0xFF02), 0x7F);
+        // normal speed, not armed
+        assert_eq!(cpu.memory_bus.read_byte(0xFF4D), 0x7E);
+        // no HDMA transfer was started
+        assert_eq!(cpu.memory_bus.read_byte(0xFF55), 0xFF);
+        assert_eq!(cpu.memory_bus.read_byte(0xFF26), 0xF1);
+        assert!(cpu.memory_bus.take_fault().is_none());
+    }
+}
@@ -0,0 +1,236 @@
+/*
+Bus:
+Everything the cpu can reach, the cpu is generic over it.
+MemoryBus is the Game Boy memory map with all peripherals,
+FlatBus is 64 KiB of plain RAM for running the cpu standalone.
+RecordingBus wraps another bus and records what happens on it in every machine cycle, for tests.
+
+The cpu calls tick once for every machine cycle it takes, the bus advances its peripherals.
+
+Accesses can't fail mid instruction, a bus that hits an error records it as a fault instead,
+the cpu takes it after the instruction and returns it from tick.
+*/
+
+use super::errors::Error;
+use std::cell::Cell;
+
+use super::interrupts::INTERRUPT_MASK;
+use super::memory::{IE, IF};
+
+// a failed access, the cpu adds the pc when it reports it
+#[derive(Debug)]
+pub struct BusFault {
+    pub address: u16,
+    pub error: Error,
+}
+
+// what MemoryBus does when an access fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultPolicy {
+    // reads return 0xFF and writes are dropped, like an unmapped address
+    OpenBus,
+    // the access reads 0xFF, and the fault is returned by CPU::tick
+    Fault,
+}
+
+pub trait Bus {
+    fn read_byte(&self, address: u16) -> u8;
+
+    fn write_byte(&mut self, address: u16, value: u8);
+
+    // read without side effects, for debuggers and the disassembler
+    fn peek_byte(&self, address: u16) -> u8 {
+        self.read_byte(address)
+    }
+
+    // advance the peripherals by one machine cycle
+    fn tick(&mut self);
+
+    fn reset(&mut self);
+
+    // STOP with the CGB speed switch armed toggles the speed, return true when switched
+    fn speed_switch(&mut self) -> bool {
+        false
+    }
+
+    // the first fault since the last call
+    fn take_fault(&mut self) -> Option<BusFault> {
+        None
+    }
+
+    fn read_word(&self, address: u16) -> u16 {
+        // Little-endian
+        let low = self.read_byte(address) as u16;
+        let high = self.read_byte(address.wrapping_add(1)) as u16;
+        (high << 8) | low
+    }
+
+    fn write_word(&mut self, address: u16, value: u16) {
+        // Little-endian
+        self.write_byte(address, (value & 0xFF) as u8);
+        self.write_byte(address.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    // interrupts both requested in IF and enabled in IE
+    fn pending_interrupts(&self) -> u8 {
+        self.read_byte(IE) & self.read_byte(IF) & INTERRUPT_MASK
+    }
+}
+
+// flat 64 KiB without any mapping or peripherals
+pub struct FlatBus {
+    memory: Box<[u8; 0x10000]>,
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlatBus {
+    pub fn new() -> Self {
+        Self {
+            memory: Box::new([0; 0x10000]),
+        }
+    }
+}
+
+impl Bus for FlatBus {
+    fn read_byte(&self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+
+    fn tick(&mut self) {}
+
+    fn reset(&mut self) {
+        self.memory.fill(0);
+    }
+}
+
+// what the bus did in one machine cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusCycle {
+    Read { address: u16, value: u8 },
+    Write { address: u16, value: u8 },
+    Idle,
+}
+
+// the bus activity of every machine cycle, accesses made outside a cycle show up in the next one
+pub struct RecordingBus<B: Bus = FlatBus> {
+    bus: B,
+    // access of the current cycle, reads only borrow the bus
+    access: Cell<Option<BusCycle>>,
+    cycles: Vec<BusCycle>,
+}
+
+impl<B: Bus> RecordingBus<B> {
+    pub fn new(bus: B) -> Self {
+        Self {
+            bus,
+            access: Cell::new(None),
+            cycles: Vec::new(),
+        }
+    }
+
+    // the wrapped bus, accesses through it are not recorded
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    pub fn cycles(&self) -> &[BusCycle] {
+        &self.cycles
+    }
+
+    // the cycles recorded so far, the recording starts over
+    pub fn take_cycles(&mut self) -> Vec<BusCycle> {
+        self.access.set(None);
+        std::mem::take(&mut self.cycles)
+    }
+}
+
+impl<B: Bus> Bus for RecordingBus<B> {
+    fn read_byte(&self, address: u16) -> u8 {
+        let value = self.bus.read_byte(address);
+        self.access.set(Some(BusCycle::Read { address, value }));
+        value
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        self.bus.write_byte(address, value);
+        self.access.set(Some(BusCycle::Write { address, value }));
+    }
+
+    fn peek_byte(&self, address: u16) -> u8 {
+        self.bus.peek_byte(address)
+    }
+
+    fn tick(&mut self) {
+        self.bus.tick();
+        self.cycles
+            .push(self.access.take().unwrap_or(BusCycle::Idle));
+    }
+
+    fn reset(&mut self) {
+        self.bus.reset();
+        self.take_cycles();
+    }
+
+    fn speed_switch(&mut self) -> bool {
+        self.bus.speed_switch()
+    }
+
+    fn take_fault(&mut self) -> Option<BusFault> {
+        self.bus.take_fault()
+    }
+
+    // the cpu polls the interrupts every instruction, that is not an access
+    fn pending_interrupts(&self) -> u8 {
+        self.bus.pending_interrupts()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::CPU;
+
+    #[test]
+    fn test_recording_bus() {
+        let mut cpu = CPU::with_bus(RecordingBus::new(FlatBus::new()));
+        cpu.pc = 0xC000;
+        cpu.sp = 0xD000;
+        cpu.set_bc(0x1234);
+        // PUSH BC
+        cpu.memory_bus.bus_mut().write_byte(0xC000, 0xC5);
+
+        assert_eq!(cpu.tick().unwrap(), 16);
+        assert_eq!(
+            cpu.memory_bus.take_cycles(),
+            vec![
+                BusCycle::Read {
+                    address: 0xC000,
+                    value: 0xC5
+                },
+                BusCycle::Idle,
+                BusCycle::Write {
+                    address: 0xCFFF,
+                    value: 0x12
+                },
+                BusCycle::Write {
+                    address: 0xCFFE,
+                    value: 0x34
+                },
+            ]
+        );
+        assert!(cpu.memory_bus.cycles().is_empty());
+    }
+}
@@ -1,11 +1,16 @@
 use log::info;
 
+use super::bus::Bus;
+use super::errors::Error;
+use super::interrupts::Interrupt;
 use super::memory::*;
 use super::time::Timer;
+use crate::io_registers::joypad::P1;
 use crate::opcodes::OPCode;
 use std::time::{Duration, Instant};
 
-pub struct CPU {
+// the bus defaults to the Game Boy memory map
+pub struct CPU<B: Bus = MemoryBus> {
     // Registers
     pub a: u8, // Flags
     pub b: u8,
@@ -20,14 +25,34 @@ pub struct CPU {
     pub pc: u16,
     pub sp: u16,
     pub ime: bool,
+    // EI enables IME only after the following instruction
+    pub ime_scheduled: bool,
     pub is_halted: bool,
+    // HALT with IME = 0 and an interrupt pending does not halt, the next opcode byte is read twice
+    pub halt_bug: bool,
+    // STOP sleeps until a button is pressed
+    pub is_stopped: bool,
+    // machine cycles left of the CGB speed switch, the cpu stalls meanwhile
+    pub speed_switch_stall: u16,
+    // set by LD B,B, the software breakpoint used by test roms
+    pub breakpoint: bool,
+    // the unused opcode that locked up the cpu, only a reset recovers
+    pub illegal_opcode: Option<u8>,
+    // machine cycles of the current instruction the bus was already ticked for
+    cycles_ticked: u8,
     // Program Counter
-    pub memory_bus: MemoryBus,
+    pub memory_bus: B,
     pub timer: Timer,
 }
 
 impl CPU {
     pub fn new() -> Self {
+        CPU::with_bus(MemoryBus::new())
+    }
+}
+
+impl<B: Bus> CPU<B> {
+    pub fn with_bus(memory_bus: B) -> Self {
         CPU {
             a: 0,
             f: 0,
@@ -39,10 +64,17 @@ impl CPU {
             l: 0,
             sp: 0,
             pc: 0,
-            memory_bus: MemoryBus::new(),
+            memory_bus,
             timer: Timer::new(),
             ime: false,
+            ime_scheduled: false,
             is_halted: false,
+            halt_bug: false,
+            is_stopped: false,
+            speed_switch_stall: 0,
+            breakpoint: false,
+            illegal_opcode: None,
+            cycles_ticked: 0,
         }
     }
 
@@ -58,7 +90,13 @@ impl CPU {
         self.sp = 0;
         self.pc = 0;
         self.ime = false;
+        self.ime_scheduled = false;
         self.is_halted = false;
+        self.halt_bug = false;
+        self.is_stopped = false;
+        self.speed_switch_stall = 0;
+        self.breakpoint = false;
+        self.illegal_opcode = None;
         self.timer.reset();
         self.memory_bus.reset();
     }
@@ -145,6 +183,11 @@ impl CPU {
         self.ime
     }
 
+    // enable IME after the next instruction, used by EI
+    pub fn schedule_ime(&mut self) {
+        self.ime_scheduled = true;
+    }
+
     // get IF
     pub fn r#if(&self) -> u8 {
         self.memory_bus.read_byte(IF)
@@ -191,55 +234,184 @@ impl CPU {
 
     // fetch-decode-execute cycle, return cycles taken
     // be careful about CB prefix, if CB prefix encountered, fetch the next bit manipulation opcode.
-    pub fn tick(&mut self) -> u32 {
+    // a failed bus access is returned as Error::BusFault after the instruction completes
+    // an unused opcode locks up the cpu and is returned once as Error::IllegalOpcode
+    pub fn tick(&mut self) -> Result<u32, Error> {
+        let pc = self.pc;
+        let locked = self.illegal_opcode.is_some();
+        self.cycles_ticked = 0;
+        let cycles = self.execute();
+        // memory accesses already ticked the bus, the remaining machine cycles are internal
+        while self.cycles_ticked < cycles {
+            self.internal_cycle();
+        }
+
+        if let Some(fault) = self.memory_bus.take_fault() {
+            return Err(Error::BusFault {
+                pc,
+                address: fault.address,
+                cause: Box::new(fault.error),
+            });
+        }
+
+        if let (false, Some(opcode)) = (locked, self.illegal_opcode) {
+            return Err(Error::IllegalOpcode { pc, opcode });
+        }
+
+        // return t cycles
+        Ok(cycles as u32 * 4)
+    }
+
+    // handle interrupts and execute one instruction, return machine cycles taken
+    fn execute(&mut self) -> u8 {
+        // IME scheduled by an EI in the previous instruction is enabled after this instruction
+        let ime_scheduled = self.ime_scheduled;
+        let pending = self.memory_bus.pending_interrupts();
+
+        // locked up, nothing but a reset wakes the cpu
+        if self.illegal_opcode.is_some() {
+            return 1;
+        }
+
+        if self.speed_switch_stall > 0 {
+            self.speed_switch_stall -= 1;
+            self.stopped_cycle();
+            return 1;
+        }
+
+        if self.is_stopped {
+            // a pressed button of a selected row pulls its P1 line low
+            if self.memory_bus.read_byte(P1) & 0x0F == 0x0F {
+                self.stopped_cycle();
+                return 1;
+            }
+            self.is_stopped = false;
+        }
+
+        if self.is_halted {
+            if pending == 0 {
+                // stay halted, one machine cycle passes
+                return 1;
+            }
+            // any pending interrupt wakes the cpu, even when ime = false
+            self.is_halted = false;
+        }
+
+        if self.ime && pending != 0 {
+            return self.handle_interrupt(pending);
+        }
+
         let cycles = {
-            if !self.is_halted {
-                // fetch and execute instruction
-                // fetch byte from pc
-                let (opcode, is_cb, is_stop) = {
-                    let first_byte = self.memory_bus.read_byte(self.pc);
+            // fetch and execute instruction
+            // fetch byte from pc
+            // STOP (0x10) skips its second byte itself, whatever the value
+            let (opcode, is_cb) = {
+                let first_byte = self.read_cycle(self.pc);
+                // the halt bug keeps pc from incrementing once
+                if self.halt_bug {
+                    self.halt_bug = false;
+                } else {
+                    self.pc += 1;
+                }
+                // if the first byte is 0xcb, then its a bit opcode
+                if first_byte == 0xcb {
+                    let second_byte = self.read_cycle(self.pc);
                     self.pc += 1;
-                    // if the first byte is 0xcb, then its a bit opcode
-                    if first_byte == 0xcb {
-                        let second_byte = self.memory_bus.read_byte(self.pc);
-                        self.pc += 1;
-                        (second_byte, true, false)
-                    } else if first_byte == 0x10 {
-                        // execute stop instruction
-                        let second_byte = self.memory_bus.read_byte(self.pc);
-                        if second_byte == 0x00 {
-                            // update pc only when stop instruction encountered
-                            self.pc += 1;
-                            (0x10, false, true)
-                        } else {
-                            (first_byte, false, false)
-                        }
-                    } else {
-                        (first_byte, false, false)
-                    }
-                };
-
-                info!(
-                    "fetched opcode {:02x}, is_cb: {:?}, is_stop: {:?}, pc: {:04x}",
-                    opcode, is_cb, is_stop, self.pc
-                );
-                if is_stop {
-                    OPCode::exec_stop(self)
+                    (second_byte, true)
                 } else {
-                    OPCode::exec(self, opcode, is_cb)
+                    (first_byte, false)
                 }
-            } else {
-                // cpu halted
-                0
-            }
+            };
+
+            info!(
+                "fetched opcode {:02x}, is_cb: {:?}, pc: {:04x}",
+                opcode, is_cb, self.pc
+            );
+            OPCode::exec(self, opcode, is_cb)
         };
 
-        // TODO: implement interrupt handler
-        // check and handle interrupts
-        // unset the is_halted when interrupt
-        // when is_halted is set and ime = false, interrupt handler is not called.
+        // a DI in this instruction cancels the scheduled IME
+        if ime_scheduled && self.ime_scheduled {
+            self.ime_scheduled = false;
+            self.ime = true;
+        }
+
+        cycles
+    }
 
-        // return t cycles
-        (cycles * 4) as u32
+    // service the highest priority pending interrupt, return machine cycles taken
+    fn handle_interrupt(&mut self, pending: u8) -> u8 {
+        let interrupt = match Interrupt::highest_priority(pending) {
+            Some(interrupt) => interrupt,
+            None => return 0,
+        };
+        info!("handle interrupt {:?}, pc: {:04x}", interrupt, self.pc);
+
+        // disable further interrupts and acknowledge the request
+        self.ime = false;
+        self.ime_scheduled = false;
+        let flags = self.memory_bus.read_byte(IF);
+        self.memory_bus.write_byte(IF, flags & !interrupt.bit());
+
+        // push pc and jump to the interrupt vector
+        self.internal_cycle();
+        self.internal_cycle();
+        self.push_word(self.pc);
+        self.pc = interrupt.vector();
+
+        // 2 wait cycles, 2 cycles to push pc, 1 cycle to set pc
+        5
+    }
+
+    // memory accesses take one machine cycle each, the peripherals advance after the access
+
+    pub fn read_cycle(&mut self, address: u16) -> u8 {
+        let value = self.memory_bus.read_byte(address);
+        self.internal_cycle();
+        value
+    }
+
+    pub fn write_cycle(&mut self, address: u16, value: u8) {
+        self.memory_bus.write_byte(address, value);
+        self.internal_cycle();
+    }
+
+    // a machine cycle without memory access
+    pub fn internal_cycle(&mut self) {
+        self.memory_bus.tick();
+        self.cycles_ticked += 1;
+    }
+
+    // a machine cycle with the system clock stopped, DIV, the timer and the other peripherals stand still
+    fn stopped_cycle(&mut self) {
+        self.cycles_ticked += 1;
+    }
+
+    // little-endian, two machine cycles
+    pub fn read_word_cycles(&mut self, address: u16) -> u16 {
+        let low = self.read_cycle(address) as u16;
+        let high = self.read_cycle(address.wrapping_add(1)) as u16;
+        (high << 8) | low
+    }
+
+    // little-endian, two machine cycles
+    pub fn write_word_cycles(&mut self, address: u16, value: u16) {
+        self.write_cycle(address, (value & 0xFF) as u8);
+        self.write_cycle(address.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    // the high byte is pushed first, two machine cycles
+    pub fn push_word(&mut self, value: u16) {
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_cycle(self.sp, (value >> 8) as u8);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_cycle(self.sp, (value & 0xFF) as u8);
+    }
+
+    // two machine cycles
+    pub fn pop_word(&mut self) -> u16 {
+        let value = self.read_word_cycles(self.sp);
+        self.sp = self.sp.wrapping_add(2);
+        value
     }
 }
@@ -3,7 +3,6 @@ use std::io;
 
 #[derive(Debug)]
 pub enum Error {
-    OPCodeParseError,
     CartridgeCheckSumError,
     CartridgeFileHeaderError,
     CartridgeAddressError,
@@ -11,6 +10,22 @@ pub enum Error {
     IO(io::Error),
     IORegisterAddressError,
     VRAMAddressError,
+    OAMAddressError,
+    CartridgeNotLoaded,
+    BootRomSizeError,
+    SaveDataSizeError,
+    SpeedError,
+    // a failed bus access, pc is the instruction that made it
+    BusFault {
+        pc: u16,
+        address: u16,
+        cause: Box<Error>,
+    },
+    // one of the unused opcodes, the cpu locks up
+    IllegalOpcode {
+        pc: u16,
+        opcode: u8,
+    },
 }
 
 impl From<io::Error> for Error {
@@ -22,7 +37,6 @@ impl From<io::Error> for Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Error::OPCodeParseError => write!(f, "Failed to parse OPCode"),
             Error::CartridgeCheckSumError => write!(f, "CheckSum Error in Cartridge"),
             Error::IO(error) => write!(f, "IO Error Occurred: {}", error),
             Error::CartridgeFileHeaderError => write!(f, "The Cartridge File Header is invalid"),
@@ -30,7 +44,21 @@ impl fmt::Display for Error {
             Error::CartridgeTypeUnsupported => write!(f, "The Cartridge Type is not supported"),
             Error::IORegisterAddressError => write!(f, "The IO Register Address is invalid"),
             Error::VRAMAddressError => write!(f, "The VRAM Address is invalid"),
-            // _ => write!(f, "Unknown Error"),
+            Error::OAMAddressError => write!(f, "The OAM Address is invalid"),
+            Error::CartridgeNotLoaded => write!(f, "No Cartridge is loaded"),
+            Error::BootRomSizeError => write!(f, "The Boot ROM size is invalid"),
+            Error::SaveDataSizeError => {
+                write!(f, "The save data size does not match the Cartridge")
+            }
+            Error::SpeedError => write!(f, "The speed must be a positive finite number"),
+            Error::BusFault { pc, address, cause } => write!(
+                f,
+                "Bus fault at pc {:04x} accessing {:04x}: {}",
+                pc, address, cause
+            ),
+            Error::IllegalOpcode { pc, opcode } => {
+                write!(f, "Illegal opcode {:02x} at pc {:04x}", opcode, pc)
+            }
         }
     }
 }
@@ -0,0 +1,62 @@
+/*
+Interrupts:
+IE (0xFFFF) selects which interrupts may be serviced, IF (0xFF0F) holds the requested ones.
+An interrupt is serviced when IME is set and the same bit is set in both IE and IF.
+When several interrupts are pending, the one with the lowest bit has the highest priority.
+
+bit 0: VBlank   -> 0x40
+bit 1: LCD STAT -> 0x48
+bit 2: Timer    -> 0x50
+bit 3: Serial   -> 0x58
+bit 4: Joypad   -> 0x60
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+// mask of the 5 interrupt bits used in IE and IF
+pub const INTERRUPT_MASK: u8 = 0x1F;
+
+impl Interrupt {
+    // ordered by priority, highest first
+    pub const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::LcdStat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    pub fn bit(self) -> u8 {
+        match self {
+            Interrupt::VBlank => 0b0000_0001,
+            Interrupt::LcdStat => 0b0000_0010,
+            Interrupt::Timer => 0b0000_0100,
+            Interrupt::Serial => 0b0000_1000,
+            Interrupt::Joypad => 0b0001_0000,
+        }
+    }
+
+    pub fn vector(self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x0040,
+            Interrupt::LcdStat => 0x0048,
+            Interrupt::Timer => 0x0050,
+            Interrupt::Serial => 0x0058,
+            Interrupt::Joypad => 0x0060,
+        }
+    }
+
+    // the highest priority interrupt in the given IE & IF value
+    pub fn highest_priority(pending: u8) -> Option<Interrupt> {
+        Interrupt::ALL
+            .into_iter()
+            .find(|interrupt| pending & interrupt.bit() != 0)
+    }
+}
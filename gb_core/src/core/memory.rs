@@ -1,104 +1,536 @@
+use super::boot::{BOOT_ROM_DISABLE, CGB_BOOT_ROM_SIZE, DMG_BOOT_ROM_SIZE};
+use super::bus::{Bus, BusFault, FaultPolicy};
+use super::errors::Error;
+use super::interrupts::{Interrupt, INTERRUPT_MASK};
+use crate::audio::Apu;
 use crate::cartridge::Cartridge;
-use crate::io_registers::IOResgisters;
+use crate::graphics::Ppu;
+use crate::io_registers::{
+    Button, HardwareTimer, IOResgisters, Joypad, OamDma, Serial, SerialDevice, SpeedSwitch,
+};
+use log::warn;
+use std::cell::Cell;
+use std::result::Result;
 
-const VRAM_START: u16 = 0x8000;
 const WRAM_START: u16 = 0xC000;
-const OAM_START: u16 = 0xFE00;
+const ECHO_START: u16 = 0xE000;
 // const IO_REGISTERS_START: u16 = 0xFF00;
 const HRAM_START: u16 = 0xFF80;
 pub const IF: u16 = 0xFF0F;
 pub const IE: u16 = 0xFFFF;
+// value of a failed read
+const OPEN_BUS: u8 = 0xFF;
+
+// the console the bus behaves like, it selects the boot values and the prohibited area 0xFEA0 - 0xFEFF
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Dmg,
+    // Game Boy Pocket
+    Mgb,
+    Cgb,
+}
 
 pub struct MemoryBus {
-    // memory: Box<[u8; 0x10000]>,
-    // 0x0000 - 0x00FF is Boot ROM, write to this area is ignored
+    // 0x0000 - 0x00FF, and 0x0200 - 0x08FF for CGB, over the cartridge until the first write to 0xFF50
+    boot_rom: Option<Vec<u8>>,
+    boot_rom_mapped: bool,
     cartridge: Option<Box<dyn Cartridge>>, // 0x0000 - 0x7FFF ROM, A000 - BFFF RAM
-    vram: Box<[u8; 0x2000]>,               // 0x8000 - 0x9FFF (Video RAM, stores tile data)
-    wram: Box<[u8; 0x2000]>,               // 0xC000 - 0xDFFF
-    // Echo RAM: 0xE000 - 0xFDFF
-    oam: Box<[u8; 0xA0]>, // 0xFE00 - 0xFE9F (Object Attribute Memory, stores sprite data)
-    // Unused: 0xFEA0 - 0xFEFF
+    // 0x8000 - 0x9FFF (Video RAM) is owned by the ppu
+    wram: Box<[u8; 0x2000]>, // 0xC000 - 0xDFFF
+    // Echo RAM: 0xE000 - 0xFDFF mirrors 0xC000 - 0xDDFF
+    // 0xFE00 - 0xFE9F (Object Attribute Memory) is owned by the ppu
+    // Prohibited: 0xFEA0 - 0xFEFF, depends on the model
     io_registers: IOResgisters, // 0xFF00 - 0xFF7F
+    joypad: Joypad,             // 0xFF00
+    serial: Serial,             // 0xFF01 - 0xFF02
+    timer: HardwareTimer,       // 0xFF04 - 0xFF07
+    apu: Apu,                   // 0xFF10 - 0xFF26, 0xFF30 - 0xFF3F
+    dma: OamDma,                // 0xFF46
+    speed: SpeedSwitch,         // 0xFF4D
+    ppu: Ppu,                   // 0xFF40 - 0xFF45, 0xFF47 - 0xFF4B, VRAM and OAM
     hram: Box<[u8; 0x7F]>,      // 0xFF80 - 0xFFFE
     interrupt_enable: u8,       // 0xFFFF
+    model: Model,
+    fault_policy: FaultPolicy,
+    // first failed access since the cpu took the last one, reads only borrow the bus
+    fault: Cell<Option<BusFault>>,
+}
+
+impl Default for MemoryBus {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MemoryBus {
     pub fn new() -> Self {
         Self {
-            // memory: Box::new([0; 0x10000]),
+            boot_rom: None,
+            boot_rom_mapped: false,
             cartridge: None,
-            vram: Box::new([0; 0x2000]),
             wram: Box::new([0; 0x2000]),
-            oam: Box::new([0; 0xA0]),
             io_registers: IOResgisters::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            timer: HardwareTimer::new(),
+            apu: Apu::new(),
+            dma: OamDma::new(),
+            speed: SpeedSwitch::new(),
+            ppu: Ppu::new(),
             hram: Box::new([0; 0x7F]),
             interrupt_enable: 0,
+            model: Model::Dmg,
+            fault_policy: FaultPolicy::Fault,
+            fault: Cell::new(None),
         }
     }
 
     pub fn load_cartridge(&mut self, cartridge: Box<dyn Cartridge>) {
         self.cartridge = Some(cartridge);
+        self.update_cgb_mode();
     }
 
-    pub fn reset(&mut self) {
-        self.cartridge = None;
-        self.vram = Box::new([0; 0x2000]);
-        self.wram = Box::new([0; 0x2000]);
-        self.oam = Box::new([0; 0xA0]);
-        self.io_registers = IOResgisters::new();
-        self.hram = Box::new([0; 0x7F]);
-        self.interrupt_enable = 0;
+    // bit 7 of 0x0143 marks a CGB game, the speed switch only exists for one running on a CGB
+    fn update_cgb_mode(&mut self) {
+        let cgb_game = self
+            .cartridge
+            .as_ref()
+            .is_some_and(|cartridge| cartridge.get_header().cgb_mode & 0x80 != 0);
+        self.speed
+            .set_cgb_mode(self.model == Model::Cgb && cgb_game);
+    }
+
+    pub fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.speed.set_cgb_mode(cgb_mode);
+    }
+
+    // the model can be set after the cartridge is loaded
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+        self.update_cgb_mode();
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    // 256 bytes for DMG and MGB, 2304 bytes for CGB, mapped until the boot rom writes 0xFF50
+    pub fn load_boot_rom(&mut self, bytes: Vec<u8>) -> Result<(), Error> {
+        if bytes.len() != DMG_BOOT_ROM_SIZE && bytes.len() != CGB_BOOT_ROM_SIZE {
+            return Err(Error::BootRomSizeError);
+        }
+        self.boot_rom = Some(bytes);
+        self.boot_rom_mapped = true;
+        Ok(())
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom_mapped
     }
 
-    // TODO: return Result
-    pub fn read_byte(&self, address: u16) -> u8 {
-        // self.memory[address as usize]
+    pub fn set_system_counter(&mut self, counter: u16) {
+        self.timer.set_system_counter(counter);
+    }
+
+    // the boot rom byte when it is mapped at the address
+    fn read_boot_rom(&self, address: u16) -> Option<u8> {
+        let boot_rom = self.boot_rom.as_ref().filter(|_| self.boot_rom_mapped)?;
         match address {
-            0x0000..=0x7FF | 0xA000..=0xBFFF => {
-                self.cartridge.as_ref().unwrap().read_byte(address).unwrap()
+            0x0000..=0x00FF => Some(boot_rom[address as usize]),
+            // the CGB boot rom skips the cartridge header
+            0x0200..=0x08FF if boot_rom.len() == CGB_BOOT_ROM_SIZE => {
+                Some(boot_rom[address as usize])
             }
-            0x8000..=0x9FFF => self.vram[(address - VRAM_START) as usize],
+            _ => None,
+        }
+    }
+
+    // faults are returned by CPU::tick by default, OpenBus ignores them
+    pub fn set_fault_policy(&mut self, fault_policy: FaultPolicy) {
+        self.fault_policy = fault_policy;
+    }
+
+    pub fn cartridge(&self) -> Result<&dyn Cartridge, Error> {
+        self.cartridge.as_deref().ok_or(Error::CartridgeNotLoaded)
+    }
+
+    pub fn cartridge_mut(&mut self) -> Result<&mut dyn Cartridge, Error> {
+        match self.cartridge.as_deref_mut() {
+            Some(cartridge) => Ok(cartridge),
+            None => Err(Error::CartridgeNotLoaded),
+        }
+    }
+
+    fn read_mapped(&self, address: u16) -> Result<u8, Error> {
+        if let Some(value) = self.read_boot_rom(address) {
+            return Ok(value);
+        }
+        let value = match address {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self.cartridge()?.read_byte(address)?,
+            0x8000..=0x9FFF => self.ppu.read_vram(address)?,
             0xC000..=0xDFFF => self.wram[(address - WRAM_START) as usize],
-            0xFE00..=0xFE9F => self.oam[(address - OAM_START) as usize],
-            0xFF00..=0xFF7F => self.io_registers.read_byte(address).unwrap(),
+            0xE000..=0xFDFF => self.wram[(address - ECHO_START) as usize],
+            0xFE00..=0xFE9F => self.ppu.read_oam(address)?,
+            0xFEA0..=0xFEFF => self.read_prohibited(address),
+            0xFF00 => self.joypad.read_byte(address)?,
+            0xFF01..=0xFF02 => self.serial.read_byte(address)?,
+            0xFF04..=0xFF07 => self.timer.read_byte(address)?,
+            // upper 3 bits of IF are unused and always read as 1
+            IF => self.io_registers.read_byte(address)? | !INTERRUPT_MASK,
+            0xFF10..=0xFF3F => self.apu.read_byte(address)?,
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.read_byte(address)?,
+            0xFF46 => self.dma.read_byte(address)?,
+            0xFF4D => self.speed.read_byte(address)?,
+            BOOT_ROM_DISABLE => 0xFF,
+            // unused io addresses, nothing drives the bus
+            0xFF03 | 0xFF08..=0xFF0E | 0xFF4C | 0xFF4E..=0xFF4F | 0xFF51..=0xFF7F => OPEN_BUS,
             0xFF80..=0xFFFE => self.hram[(address - HRAM_START) as usize],
             0xFFFF => self.interrupt_enable,
-            _ => 0,
+        };
+        Ok(value)
+    }
+
+    pub fn try_read_byte(&self, address: u16) -> Result<u8, Error> {
+        // only HRAM and the IO registers are accessible during OAM DMA
+        if self.dma.is_active() && address < 0xFF00 {
+            return Ok(OPEN_BUS);
         }
+        self.read_mapped(address)
     }
-    // TODO: return Result
-    pub fn write_byte(&mut self, address: u16, value: u8) {
-        // self.memory[address as usize] = value;
+
+    pub fn try_write_byte(&mut self, address: u16, value: u8) -> Result<(), Error> {
+        if self.dma.is_active() && address < 0xFF00 {
+            return Ok(());
+        }
         match address {
-            0x0000..=0x7FF | 0xA000..=0xBFFF => self
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => self
                 .cartridge
                 .as_mut()
-                .unwrap()
-                .write_byte(address, value)
-                .unwrap(),
-            0x8000..=0x9FFF => self.vram[(address - VRAM_START) as usize] = value,
+                .ok_or(Error::CartridgeNotLoaded)?
+                .write_byte(address, value)?,
+            0x8000..=0x9FFF => self.ppu.write_vram(address, value)?,
             0xC000..=0xDFFF => self.wram[(address - WRAM_START) as usize] = value,
-            0xFE00..=0xFE9F => self.oam[(address - OAM_START) as usize] = value,
-            0xFF00..=0xFF7F => self.io_registers.write_byte(address, value).unwrap(),
+            0xE000..=0xFDFF => self.wram[(address - ECHO_START) as usize] = value,
+            0xFE00..=0xFE9F => self.ppu.write_oam(address, value)?,
+            // writes to the prohibited area are ignored
+            0xFEA0..=0xFEFF => (),
+            0xFF00 => {
+                if self.joypad.write_byte(address, value)? {
+                    self.request_interrupt(Interrupt::Joypad);
+                }
+            }
+            0xFF01..=0xFF02 => self.serial.write_byte(address, value)?,
+            0xFF04..=0xFF07 => self.timer.write_byte(address, value)?,
+            IF => self.io_registers.write_byte(address, value)?,
+            0xFF10..=0xFF3F => self.apu.write_byte(address, value)?,
+            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => {
+                let interrupts = self.ppu.write_byte(address, value)?;
+                self.request_interrupts(interrupts);
+            }
+            0xFF46 => self.dma.write_byte(address, value)?,
+            0xFF4D => self.speed.write_byte(address, value)?,
+            // the boot rom can not be mapped again
+            BOOT_ROM_DISABLE => self.boot_rom_mapped = false,
+            // writes to the unused io addresses are ignored
+            0xFF03 | 0xFF08..=0xFF0E | 0xFF4C | 0xFF4E..=0xFF4F | 0xFF51..=0xFF7F => (),
             0xFF80..=0xFFFE => self.hram[(address - HRAM_START) as usize] = value,
             0xFFFF => self.interrupt_enable = value,
-            _ => (),
         }
+        Ok(())
+    }
+
+    // DMG reads 0 unless the ppu blocks OAM,
+    // CGB (revision E) repeats the upper nibble of the low address byte, 0xFEA5 reads 0xAA
+    fn read_prohibited(&self, address: u16) -> u8 {
+        match self.model {
+            Model::Dmg | Model::Mgb if self.ppu.oam_blocked() => 0xFF,
+            Model::Dmg | Model::Mgb => 0x00,
+            Model::Cgb => {
+                let nibble = address as u8 & 0xF0;
+                nibble | nibble >> 4
+            }
+        }
+    }
+
+    // keep the first fault until the cpu takes it
+    fn record_fault(&self, address: u16, error: Error) {
+        if self.fault_policy == FaultPolicy::OpenBus {
+            return;
+        }
+        warn!("bus fault accessing {:04x}: {}", address, error);
+        let fault = self.fault.take();
+        self.fault.set(fault.or(Some(BusFault { address, error })));
+    }
+    // advance the peripherals by the clock cycles taken by the cpu
+    pub fn step(&mut self, cycles: u32) {
+        if self.timer.step(cycles) {
+            self.request_interrupt(Interrupt::Timer);
+        }
+        if self.serial.step(cycles) {
+            self.request_interrupt(Interrupt::Serial);
+        }
+        for _ in 0..cycles / 4 {
+            if let Some((source, offset)) = self.dma.tick() {
+                let value = self.dma_source_byte(source);
+                self.ppu.dma_write_oam(offset, value);
+            }
+        }
+        // the ppu, the apu and the cartridge clock keep their clock in double speed
+        let ppu_cycles = if self.speed.is_double_speed() {
+            cycles / 2
+        } else {
+            cycles
+        };
+        self.apu.step(ppu_cycles);
+        if let Some(cartridge) = self.cartridge.as_mut() {
+            cartridge.step(ppu_cycles);
+        }
+        let interrupts = self.ppu.step(ppu_cycles);
+        self.request_interrupts(interrupts);
+    }
+
+    // the dma reads VRAM directly, without the ppu mode blocking
+    fn dma_source_byte(&self, address: u16) -> u8 {
+        match address {
+            0x8000..=0x9FFF => self.ppu.vram().read_byte(address).unwrap(),
+            _ => self.read_mapped(address).unwrap_or_else(|error| {
+                self.record_fault(address, error);
+                OPEN_BUS
+            }),
+        }
+    }
+
+    // update a button from the host, pressing a button of a selected row requests the joypad interrupt
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if self.joypad.set_button(button, pressed) {
+            self.request_interrupt(Interrupt::Joypad);
+        }
+    }
+
+    // connect the other end of the link cable
+    pub fn set_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.serial.set_device(device);
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    // set the interrupt bit in IF, the cpu services it on its next tick
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        let flags = self.read_byte(IF);
+        self.write_byte(IF, flags | interrupt.bit());
+    }
+
+    // set multiple interrupt bits in IF at once
+    pub fn request_interrupts(&mut self, interrupts: u8) {
+        if interrupts != 0 {
+            let flags = self.read_byte(IF);
+            self.write_byte(IF, flags | (interrupts & INTERRUPT_MASK));
+        }
+    }
+}
+
+impl Bus for MemoryBus {
+    fn read_byte(&self, address: u16) -> u8 {
+        self.try_read_byte(address).unwrap_or_else(|error| {
+            self.record_fault(address, error);
+            OPEN_BUS
+        })
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        if let Err(error) = self.try_write_byte(address, value) {
+            self.record_fault(address, error);
+        }
+    }
+
+    // no fault is recorded, and the memory behind a running OAM DMA is visible
+    fn peek_byte(&self, address: u16) -> u8 {
+        self.read_mapped(address).unwrap_or(OPEN_BUS)
+    }
+
+    fn tick(&mut self) {
+        self.step(4);
+    }
+
+    fn speed_switch(&mut self) -> bool {
+        self.speed.switch()
+    }
+
+    fn take_fault(&mut self) -> Option<BusFault> {
+        self.fault.take()
+    }
+
+    fn reset(&mut self) {
+        self.boot_rom_mapped = self.boot_rom.is_some();
+        self.cartridge = None;
+        *self.wram = [0; 0x2000];
+        self.io_registers = IOResgisters::new();
+        self.joypad.reset();
+        self.serial.reset();
+        self.timer.reset();
+        self.apu.reset();
+        self.dma.reset();
+        self.speed.reset();
+        self.ppu.reset();
+        *self.hram = [0; 0x7F];
+        self.interrupt_enable = 0;
+        self.fault.set(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::tests::{test_rom, test_rom_byte};
+    use crate::cartridge::RomOnlyCartridge;
+
+    fn test_cartridge() -> Box<dyn Cartridge> {
+        // 32 KiB rom only with 8 KiB RAM
+        Box::new(RomOnlyCartridge::from_bytes(test_rom(0x00, 0x00, 0x02)).unwrap())
+    }
+
+    fn test_bus() -> MemoryBus {
+        let mut bus = MemoryBus::new();
+        bus.load_cartridge(test_cartridge());
+        bus
+    }
+
+    #[test]
+    fn test_cartridge_rom() {
+        let mut bus = test_bus();
+        for address in 0x0000..=0x7FFF {
+            if (0x134..=0x14D).contains(&address) {
+                continue;
+            }
+            let expected = test_rom_byte(address as usize);
+            assert_eq!(
+                bus.try_read_byte(address).unwrap(),
+                expected,
+                "{:04x}",
+                address
+            );
+        }
+        // rom only cartridges ignore writes to rom
+        bus.try_write_byte(0x4000, 0x12).unwrap();
+        assert_eq!(bus.read_byte(0x4000), test_rom_byte(0x4000));
+    }
+
+    #[test]
+    fn test_read_write_regions() {
+        // regions that read back what was written
+        let regions = [
+            ("vram", 0x8000..=0x9FFF),
+            ("external ram", 0xA000..=0xBFFF),
+            ("wram", 0xC000..=0xDFFF),
+            ("oam", 0xFE00..=0xFE9F),
+            ("hram", 0xFF80..=0xFFFE),
+        ];
+        let mut bus = test_bus();
+        for (name, region) in regions {
+            for address in region.clone() {
+                bus.try_write_byte(address, address as u8 ^ 0x5A).unwrap();
+            }
+            for address in region {
+                assert_eq!(
+                    bus.try_read_byte(address).unwrap(),
+                    address as u8 ^ 0x5A,
+                    "{} {:04x}",
+                    name,
+                    address
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_echo_ram() {
+        let mut bus = test_bus();
+        for address in 0xE000..=0xFDFF {
+            bus.write_byte(address, address as u8);
+            assert_eq!(bus.read_byte(address - 0x2000), address as u8);
+            bus.write_byte(address - 0x2000, !address as u8);
+            assert_eq!(bus.read_byte(address), !address as u8);
+        }
+        // 0xDE00 - 0xDFFF is not mirrored, 0xFE00 is OAM
+        bus.write_byte(0xDE00, 0x42);
+        bus.write_byte(0xFE00, 0x24);
+        assert_eq!(bus.read_byte(0xFE00), 0x24);
+        assert_eq!(bus.read_byte(0xDE00), 0x42);
+        bus.write_byte(0xDE00, 0x99);
+        assert_eq!(bus.read_byte(0xFE00), 0x24);
     }
 
-    pub fn read_word(&self, address: u16) -> u16 {
-        // Little-endian
-        let low = self.read_byte(address) as u16;
-        let high = self.read_byte(address.wrapping_add(1)) as u16;
-        (high << 8) | low
+    #[test]
+    fn test_prohibited_area() {
+        let mut bus = test_bus();
+        for address in 0xFEA0..=0xFEFF {
+            bus.write_byte(address, 0x12);
+            assert_eq!(bus.read_byte(address), 0x00);
+        }
+
+        bus.set_model(Model::Cgb);
+        assert_eq!(bus.read_byte(0xFEA0), 0xAA);
+        assert_eq!(bus.read_byte(0xFEB5), 0xBB);
+        assert_eq!(bus.read_byte(0xFEFF), 0xFF);
     }
 
-    pub fn write_word(&mut self, address: u16, value: u16) {
-        // Little-endian
-        // self.memory[address as usize] = (value & 0xFF) as u8;
-        // self.memory[(address.wrapping_add(1)) as usize] = (value >> 8) as u8;
-        self.write_byte(address, (value & 0xFF) as u8);
-        self.write_byte(address.wrapping_add(1), (value >> 8) as u8);
+    #[test]
+    fn test_cgb_mode_needs_cgb_model() {
+        use crate::io_registers::speed::KEY1;
+
+        // a CGB game
+        let mut bytes = test_rom(0x00, 0x00, 0x00);
+        bytes[0x143] = 0x80;
+        bytes[0x14D] = bytes[0x14D].wrapping_sub(0x80);
+        let mut bus = MemoryBus::new();
+        bus.load_cartridge(Box::new(RomOnlyCartridge::from_bytes(bytes).unwrap()));
+        // the speed switch does not exist on a DMG
+        assert_eq!(bus.read_byte(KEY1), 0xFF);
+
+        // the model set after the cartridge
+        bus.set_model(Model::Cgb);
+        bus.write_byte(KEY1, 0x01);
+        assert_eq!(bus.read_byte(KEY1), 0x7F);
+        assert!(bus.speed_switch());
+
+        bus.set_model(Model::Dmg);
+        assert_eq!(bus.read_byte(KEY1), 0xFF);
+
+        // a DMG game on a CGB
+        let mut bus = test_bus();
+        bus.set_model(Model::Cgb);
+        assert_eq!(bus.read_byte(KEY1), 0xFF);
+    }
+
+    #[test]
+    fn test_io_registers_range() {
+        let mut bus = test_bus();
+        for address in 0xFF00..=0xFF7F {
+            assert!(bus.try_read_byte(address).is_ok(), "{:04x}", address);
+        }
+        // unused addresses read 0xFF and ignore writes
+        for address in [0xFF03, 0xFF08, 0xFF0E, 0xFF4C, 0xFF4F, 0xFF51, 0xFF7F] {
+            bus.try_write_byte(address, 0x12).unwrap();
+            assert_eq!(bus.read_byte(address), 0xFF, "{:04x}", address);
+        }
+        bus.write_byte(IF, 0x01);
+        assert_eq!(bus.read_byte(IF), 0xE1);
+        bus.write_byte(IE, 0x1F);
+        assert_eq!(bus.read_byte(IE), 0x1F);
+        assert!(bus.take_fault().is_none());
     }
 }
@@ -1,9 +1,15 @@
+pub mod boot;
+pub mod bus;
 pub mod cpu;
 pub mod errors;
+pub mod interrupts;
 pub mod memory;
 pub mod time;
 
+pub use boot::*;
+pub use bus::*;
 pub use cpu::*;
 pub use errors::*;
+pub use interrupts::*;
 pub use memory::*;
 pub use time::*;
@@ -0,0 +1,389 @@
+/*
+Pixel FIFO:
+In mode 3 the PPU pushes one pixel per dot to the LCD from the pixel FIFOs.
+
+Background fetcher, each step takes 2 dots:
+    get tile: read the tile index from the bg or window tile map
+    get tile data low / high: read the 2 bytes of the tile row
+    push: push 8 pixels into the bg FIFO, only when the bg FIFO is empty
+The first fetch of a line is thrown away, which costs 6 dots.
+
+SCX % 8 pixels are discarded at the start of the line to scroll finer than a tile.
+When the window starts on the current pixel, the bg FIFO is cleared and the fetcher restarts from the window map.
+When an object starts on the current pixel, the fetcher is paused and the object row is mixed into the object FIFO.
+Objects and the window make mode 3 longer, so the length of HBlank changes.
+
+Registers are read when they are used, so SCX, BGP, OBP changes in the middle of a line are visible.
+*/
+
+use super::ppu::*;
+use std::collections::VecDeque;
+
+// dots of the thrown away first fetch
+const STARTUP_DOTS: u8 = 6;
+// dots to fetch an object row
+const OBJECT_FETCH_DOTS: u8 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FetchStep {
+    GetTile,
+    GetTileDataLow,
+    GetTileDataHigh,
+    Push,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ObjectPixel {
+    color: u8,
+    obp1: bool,
+    bg_priority: bool,
+}
+
+pub(super) struct PixelFifo {
+    bg_fifo: VecDeque<u8>,
+    object_fifo: VecDeque<ObjectPixel>,
+    step: FetchStep,
+    step_dots: u8,
+    tile_x: u8,
+    tile_index: u8,
+    tile_low: u8,
+    tile_high: u8,
+    startup_dots: u8,
+    discard: u8,
+    lx: u8, // x of the next pixel sent to the LCD
+    in_window: bool,
+    objects: Vec<Object>,
+    object_fetched: Vec<bool>,
+    object_fetch_dots: u8,
+}
+
+impl PixelFifo {
+    pub(super) fn new() -> Self {
+        Self {
+            bg_fifo: VecDeque::with_capacity(16),
+            object_fifo: VecDeque::with_capacity(8),
+            step: FetchStep::GetTile,
+            step_dots: 0,
+            tile_x: 0,
+            tile_index: 0,
+            tile_low: 0,
+            tile_high: 0,
+            startup_dots: STARTUP_DOTS,
+            discard: 0,
+            lx: 0,
+            in_window: false,
+            objects: Vec::with_capacity(10),
+            object_fetched: Vec::with_capacity(10),
+            object_fetch_dots: 0,
+        }
+    }
+}
+
+impl Ppu {
+    // prepare the FIFO for a new line at the start of mode 3
+    pub(super) fn fifo_start_line(&mut self) {
+        let objects = if self.lcdc & 0b0000_0010 != 0 {
+            self.scan_oam()
+        } else {
+            Vec::new()
+        };
+        let fifo = &mut self.fifo;
+        fifo.bg_fifo.clear();
+        fifo.object_fifo.clear();
+        fifo.step = FetchStep::GetTile;
+        fifo.step_dots = 0;
+        fifo.tile_x = 0;
+        fifo.startup_dots = STARTUP_DOTS;
+        fifo.discard = self.scx % 8;
+        fifo.lx = 0;
+        fifo.in_window = false;
+        fifo.object_fetched = vec![false; objects.len()];
+        fifo.objects = objects;
+        fifo.object_fetch_dots = 0;
+    }
+
+    // advance mode 3 by one dot, return true when the line is completed
+    pub(super) fn fifo_tick(&mut self) -> bool {
+        if self.fifo.startup_dots > 0 {
+            self.fifo.startup_dots -= 1;
+            return false;
+        }
+
+        // an object fetch pauses the fetcher and the pixel output
+        if self.fifo.object_fetch_dots > 0 {
+            self.fifo.object_fetch_dots -= 1;
+            return false;
+        }
+        if self.fifo.discard == 0 && self.start_object_fetch() {
+            return false;
+        }
+
+        self.window_trigger();
+        self.fetcher_tick();
+        self.shift_pixel();
+
+        if self.fifo.lx as usize == SCREEN_WIDTH {
+            if self.fifo.in_window {
+                self.window_line += 1;
+            }
+            return true;
+        }
+        false
+    }
+
+    // restart the fetcher on the window when it starts at the current pixel
+    fn window_trigger(&mut self) {
+        let window_enabled = self.lcdc & 0b0010_0001 == 0b0010_0001;
+        if !self.fifo.in_window
+            && window_enabled
+            && self.ly >= self.wy
+            && self.fifo.discard == 0
+            && self.fifo.lx as u16 + 7 >= self.wx as u16
+        {
+            self.fifo.in_window = true;
+            self.fifo.bg_fifo.clear();
+            self.fifo.tile_x = 0;
+            self.fifo.step = FetchStep::GetTile;
+            self.fifo.step_dots = 0;
+        }
+    }
+
+    fn fetcher_tick(&mut self) {
+        if self.fifo.step == FetchStep::Push {
+            // push only when the bg FIFO is empty, otherwise retry next dot
+            if self.fifo.bg_fifo.is_empty() {
+                for bit in (0..8).rev() {
+                    let color = (((self.fifo.tile_high >> bit) & 1) << 1)
+                        | ((self.fifo.tile_low >> bit) & 1);
+                    self.fifo.bg_fifo.push_back(color);
+                }
+                self.fifo.tile_x = self.fifo.tile_x.wrapping_add(1);
+                self.fifo.step = FetchStep::GetTile;
+            }
+            return;
+        }
+
+        // other steps take 2 dots, the work is done on the second dot
+        self.fifo.step_dots += 1;
+        if self.fifo.step_dots < 2 {
+            return;
+        }
+        self.fifo.step_dots = 0;
+
+        let (map, x, y) = if self.fifo.in_window {
+            let map = if self.lcdc & 0b0100_0000 != 0 {
+                WINDOW_START
+            } else {
+                BACKGROUND_START
+            };
+            (map, self.fifo.tile_x, self.window_line)
+        } else {
+            let map = if self.lcdc & 0b0000_1000 != 0 {
+                WINDOW_START
+            } else {
+                BACKGROUND_START
+            };
+            let x = (self.scx / 8).wrapping_add(self.fifo.tile_x) & 0x1F;
+            (map, x, self.ly.wrapping_add(self.scy))
+        };
+        // offset of the tile row in the 16 bytes of the tile
+        let row = (y as usize % 8) * 2;
+
+        match self.fifo.step {
+            FetchStep::GetTile => {
+                self.fifo.tile_index = self.vram.tile_map_index(map, x, y / 8);
+                self.fifo.step = FetchStep::GetTileDataLow;
+            }
+            FetchStep::GetTileDataLow => {
+                let address = self.bg_tile_address(self.fifo.tile_index);
+                self.fifo.tile_low = self.vram.read_tile(address).unwrap().read_byte(row);
+                self.fifo.step = FetchStep::GetTileDataHigh;
+            }
+            FetchStep::GetTileDataHigh => {
+                let address = self.bg_tile_address(self.fifo.tile_index);
+                self.fifo.tile_high = self.vram.read_tile(address).unwrap().read_byte(row + 1);
+                self.fifo.step = FetchStep::Push;
+            }
+            FetchStep::Push => {}
+        }
+    }
+
+    // start fetching the first object starting at the current pixel, return true if started
+    fn start_object_fetch(&mut self) -> bool {
+        let lx = self.fifo.lx as u16;
+        let next = self
+            .fifo
+            .objects
+            .iter()
+            .zip(self.fifo.object_fetched.iter())
+            .position(|(object, fetched)| !fetched && object.x as u16 <= lx + 8);
+        let Some(index) = next else {
+            return false;
+        };
+        self.fifo.object_fetched[index] = true;
+        self.fifo.object_fetch_dots = OBJECT_FETCH_DOTS - 1;
+
+        let object = self.fifo.objects[index];
+        let tall = self.lcdc & 0b0000_0100 != 0;
+        let mut y = (self.ly as i16 + 16 - object.y as i16) as u8;
+        if object.flags & 0b0100_0000 != 0 {
+            y = if tall { 15 - y } else { 7 - y };
+        }
+        let tile = if tall {
+            object.tile & 0xFE
+        } else {
+            object.tile
+        };
+        let tile_address = Ppu::object_tile_address(tile);
+
+        for px in 0..8u8 {
+            let screen_x = object.x as i16 - 8 + px as i16;
+            if screen_x < lx as i16 {
+                continue;
+            }
+            let tx = if object.flags & 0b0010_0000 != 0 {
+                7 - px
+            } else {
+                px
+            };
+            let pixel = ObjectPixel {
+                color: self.vram.tile_color_index(tile_address, tx, y),
+                obp1: object.flags & 0b0001_0000 != 0,
+                bg_priority: object.flags & 0b1000_0000 != 0,
+            };
+            let slot = (screen_x - lx as i16) as usize;
+            match self.fifo.object_fifo.get_mut(slot) {
+                // an earlier object keeps its opaque pixels
+                Some(existing) if existing.color == 0 => *existing = pixel,
+                Some(_) => {}
+                None => self.fifo.object_fifo.push_back(pixel),
+            }
+        }
+        true
+    }
+
+    // send one pixel to the LCD
+    fn shift_pixel(&mut self) {
+        let Some(bg) = self.fifo.bg_fifo.pop_front() else {
+            return;
+        };
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+        let bg = if self.lcdc & 0b0000_0001 != 0 { bg } else { 0 };
+        let object = self.fifo.object_fifo.pop_front();
+
+        let shade = match object {
+            Some(object)
+                if object.color != 0
+                    && self.lcdc & 0b0000_0010 != 0
+                    && !(object.bg_priority && bg != 0) =>
+            {
+                let palette = if object.obp1 { self.obp1 } else { self.obp0 };
+                Ppu::apply_palette(palette, object.color)
+            }
+            _ => Ppu::apply_palette(self.bgp, bg),
+        };
+        self.frame_buffer[self.ly as usize * SCREEN_WIDTH + self.fifo.lx as usize] = shade;
+        self.fifo.lx += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // tile 1 filled with color 3 and tile 2 with color 1 in the left half
+    // objects are placed in OAM before the LCD is enabled
+    fn new_ppu(render_mode: RenderMode, lcdc: u8, objects: &[[u8; 4]]) -> Ppu {
+        let mut ppu = Ppu::new();
+        for i in 0..16 {
+            ppu.write_vram(0x8010 + i, 0xFF).unwrap();
+        }
+        for i in 0..8 {
+            ppu.write_vram(0x8020 + i * 2, 0xF0).unwrap();
+        }
+        // checker board on the background map, tile 2 on the window map
+        for i in 0..0x400 {
+            ppu.write_vram(0x9800 + i, (i % 2) as u8).unwrap();
+            ppu.write_vram(0x9C00 + i, 2).unwrap();
+        }
+        for (i, object) in objects.iter().enumerate() {
+            for (j, byte) in object.iter().enumerate() {
+                ppu.write_oam(0xFE00 + (i * 4 + j) as u16, *byte).unwrap();
+            }
+        }
+        ppu.set_render_mode(render_mode);
+        ppu.write_byte(BGP, 0b1110_0100).unwrap();
+        ppu.write_byte(OBP0, 0b1110_0100).unwrap();
+        ppu.write_byte(OBP1, 0b0001_1011).unwrap();
+        ppu.write_byte(LCDC, lcdc).unwrap();
+        ppu
+    }
+
+    // dots spent in mode 3 of the first line
+    fn drawing_dots(ppu: &mut Ppu) -> u32 {
+        ppu.step(80);
+        let mut dots = 0;
+        while ppu.mode() == PpuMode::Drawing {
+            ppu.step(1);
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn test_mode3_length() {
+        let mut ppu = new_ppu(RenderMode::PixelFifo, 0b1001_0011, &[]);
+        assert_eq!(drawing_dots(&mut ppu), 172);
+
+        // fine scroll discards pixels
+        let mut ppu = new_ppu(RenderMode::PixelFifo, 0b1001_0011, &[]);
+        ppu.write_byte(SCX, 3).unwrap();
+        assert_eq!(drawing_dots(&mut ppu), 175);
+
+        // objects pause the fetcher
+        let objects = [[16, 40, 0, 0], [16, 80, 0, 0]];
+        let mut ppu = new_ppu(RenderMode::PixelFifo, 0b1001_0011, &objects);
+        assert!(drawing_dots(&mut ppu) > 172);
+    }
+
+    #[test]
+    fn test_fifo_matches_scanline() {
+        // overlapping objects with flip, palette and priority flags
+        let objects = [
+            [20, 13, 2, 0b0000_0000],
+            [24, 17, 1, 0b0011_0000],
+            [30, 60, 2, 0b1000_0000],
+            [16, 4, 1, 0b0001_0000],
+            [40, 164, 2, 0b0110_0000],
+        ];
+
+        let lcdc = 0b1111_0011;
+        let mut scanline = new_ppu(RenderMode::Scanline, lcdc, &objects);
+        let mut fifo = new_ppu(RenderMode::PixelFifo, lcdc, &objects);
+        for ppu in [&mut scanline, &mut fifo] {
+            ppu.write_byte(SCX, 5).unwrap();
+            ppu.write_byte(SCY, 3).unwrap();
+            ppu.write_byte(WX, 87).unwrap();
+            ppu.write_byte(WY, 20).unwrap();
+            ppu.step(DOTS_PER_LINE * 154);
+        }
+        assert_eq!(scanline.frame_buffer(), fifo.frame_buffer());
+    }
+
+    #[test]
+    fn test_mid_line_palette_change() {
+        let mut ppu = new_ppu(RenderMode::PixelFifo, 0b1001_0001, &[]);
+        // 12 dots before the first pixel, then 80 pixels
+        ppu.step(80 + 12 + 80);
+        ppu.write_byte(BGP, 0).unwrap();
+        ppu.step(DOTS_PER_LINE - 80 - 12 - 80);
+
+        let line = &ppu.frame_buffer()[0..SCREEN_WIDTH];
+        assert_eq!(line[0..8], [0; 8]);
+        assert_eq!(line[8..16], [3; 8]);
+        assert!(line[80..].iter().all(|&shade| shade == 0));
+    }
+}
@@ -1,4 +1,6 @@
+mod fifo;
 pub(crate) mod ppu;
 pub(crate) mod tile;
 
+pub use ppu::{Ppu, PpuMode, RenderMode, SCREEN_HEIGHT, SCREEN_WIDTH};
 pub use tile::Tile;
@@ -32,9 +32,9 @@ Sprites:
 Several objects can be combined to draw a larger graphical element.
 */
 
+use super::fifo::PixelFifo;
 use super::Tile;
-use crate::core::Error;
-use crate::io_registers::IOResgisters;
+use crate::core::{Error, Interrupt};
 use std::result::Result;
 
 pub const VRAM_START: u16 = 0x8000;
@@ -52,7 +52,7 @@ pub const TILE_COUNT: usize = 384; // 0x8000 - 0x97FF can store 384 tiles
 pub const TILE_MAP_SIZE: usize = 32 * 32; // 32x32 tiles, each tile is 1 byte pointer to the tile data in VRAM
 
 pub struct VRAM {
-    pub tiles: Vec<u8>,               // 0x8000 - 0x97FF
+    pub tiles: Vec<Tile>,             // 0x8000 - 0x97FF
     pub background_tile_map: Vec<u8>, // 0x9800 - 0x9BFF
     pub window_tile_map: Vec<u8>,     //  0x9C00 - 0x9FFF
 }
@@ -60,24 +60,24 @@ pub struct VRAM {
 impl VRAM {
     pub fn new() -> Self {
         Self {
-            tiles: vec![0; TILE_COUNT * TILE_SIZE],
+            tiles: vec![Tile::default(); TILE_COUNT],
             background_tile_map: vec![0; TILE_MAP_SIZE],
             window_tile_map: vec![0; TILE_MAP_SIZE],
         }
     }
 
-    pub fn read_tile(&self, address: u16) -> Result<Tile, Error> {
+    // borrow the tile starting at address, the tile is not copied
+    pub fn read_tile(&self, address: u16) -> Result<&Tile, Error> {
         // check address in tile store range
-        if address < VRAM_START || address >= BACKGROUND_START as u16 {
+        if !(VRAM_START..BACKGROUND_START).contains(&address) {
             return Err(Error::VRAMAddressError);
         }
         // check address is aligned to TILE_SIZE
-        if ((address - VRAM_START) as usize) % TILE_SIZE != 0 {
+        if !((address - VRAM_START) as usize).is_multiple_of(TILE_SIZE) {
             return Err(Error::VRAMAddressError);
         }
-        let address = (address - VRAM_START) as usize;
-        let tile_data = &self.tiles[address..address + TILE_SIZE];
-        Ok(Tile::from_bytes(tile_data))
+        let index = (address - VRAM_START) as usize / TILE_SIZE;
+        Ok(&self.tiles[index])
     }
 
     // read the tile as color index, which is a vector of 64 pixels, each pixel is 2 bit.
@@ -87,34 +87,44 @@ impl VRAM {
     }
 
     pub fn read_background_tile_index(&self, address: u16) -> Result<u8, Error> {
-        if address < BACKGROUND_START || address >= WINDOW_START {
+        if !(BACKGROUND_START..WINDOW_START).contains(&address) {
             return Err(Error::VRAMAddressError);
         }
         let index = (address - BACKGROUND_START) as usize;
 
-        Ok(self.background_tile_map[index] as u8)
+        Ok(self.background_tile_map[index])
     }
 
     pub fn read_window_tile_index(&self, address: u16) -> Result<u8, Error> {
-        if address < WINDOW_START || address >= VRAM_END {
+        if !(WINDOW_START..=VRAM_END).contains(&address) {
             return Err(Error::VRAMAddressError);
         }
         let index = (address - WINDOW_START) as usize;
 
-        Ok(self.window_tile_map[index] as u8)
+        Ok(self.window_tile_map[index])
+    }
+
+    // tile index at tile (x, y) of the tile map starting at map, BACKGROUND_START or WINDOW_START
+    pub fn tile_map_index(&self, map: u16, x: u8, y: u8) -> u8 {
+        let index = (y as usize % 32) * 32 + (x as usize % 32);
+        if map == WINDOW_START {
+            self.window_tile_map[index]
+        } else {
+            self.background_tile_map[index]
+        }
     }
 
     pub fn read_byte(&self, address: u16) -> Result<u8, Error> {
         match address {
             VRAM_START..BACKGROUND_START => {
                 let index = (address - VRAM_START) as usize;
-                Ok(self.tiles[index])
+                Ok(self.tiles[index / TILE_SIZE].read_byte(index % TILE_SIZE))
             }
             BACKGROUND_START..WINDOW_START => {
                 let index = (address - BACKGROUND_START) as usize;
                 Ok(self.background_tile_map[index])
             }
-            WINDOW_START..VRAM_END => {
+            WINDOW_START..=VRAM_END => {
                 let index = (address - WINDOW_START) as usize;
                 Ok(self.window_tile_map[index])
             }
@@ -126,13 +136,13 @@ impl VRAM {
         match address {
             VRAM_START..BACKGROUND_START => {
                 let address = (address - VRAM_START) as usize;
-                self.tiles[address] = value;
+                self.tiles[address / TILE_SIZE].write_byte(address % TILE_SIZE, value);
             }
             BACKGROUND_START..WINDOW_START => {
                 let address = (address - BACKGROUND_START) as usize;
                 self.background_tile_map[address] = value;
             }
-            WINDOW_START..VRAM_END => {
+            WINDOW_START..=VRAM_END => {
                 let address = (address - WINDOW_START) as usize;
                 self.window_tile_map[address] = value;
             }
@@ -142,15 +152,656 @@ impl VRAM {
         Ok(())
     }
 
-    // calculate the tile address, based on the tile index, and lcd.4 value
-    pub fn get_tile_address(index: u8, lcd: &[bool]) -> u16 {
-        if lcd[4] {
-            // lcd.4 == 1, use BASE_PONTER_1
+    // calculate the tile address, based on the tile index, and lcdc.4 value
+    pub fn get_tile_address(index: u8, lcdc: u8) -> u16 {
+        if lcdc & 0b0001_0000 != 0 {
+            // lcdc.4 == 1, use BASE_PONTER_1
             BASE_PONTER_1 + (index as u16 * TILE_SIZE as u16)
         } else {
-            // lcd.4 == 0, use BASE_PONTER_2
+            // lcdc.4 == 0, use BASE_PONTER_2
             // index interpret as signed, -128 to 127
-            BASE_PONTER_2 + (((index as i8) as i16) * (TILE_SIZE as i16)) as u16
+            BASE_PONTER_2.wrapping_add((((index as i8) as i16) * (TILE_SIZE as i16)) as u16)
+        }
+    }
+
+    // color index of pixel (x, y) of the tile starting at address, y can reach into the next tile for 8x16 objects
+    pub fn tile_color_index(&self, address: u16, x: u8, y: u8) -> u8 {
+        let index = (address - VRAM_START) as usize / TILE_SIZE + y as usize / 8;
+        self.tiles[index].color_index(x, y % 8)
+    }
+}
+
+/*
+PPU:
+The screen is drawn line by line, each line takes 456 dots (clock cycles), 154 lines per frame.
+Line 0 - 143 are visible, line 144 - 153 are VBlank.
+
+Modes of a visible line:
+mode 2 OAM scan: 80 dots, search objects overlapping the line
+mode 3 Drawing: 172 - 289 dots, send pixels to the LCD
+mode 0 HBlank: rest of the line
+mode 1 VBlank: the whole line 144 - 153
+
+LCD registers:
+0xFF40 LCDC
+    bit 7: LCD enable
+    bit 6: window tile map, 0 = 9800, 1 = 9C00
+    bit 5: window enable
+    bit 4: bg & window tile data, 0 = 8800 (signed index), 1 = 8000 (unsigned index)
+    bit 3: bg tile map, 0 = 9800, 1 = 9C00
+    bit 2: object size, 0 = 8x8, 1 = 8x16
+    bit 1: object enable
+    bit 0: bg & window enable
+0xFF41 STAT: bit 6-3 interrupt select for LYC, mode 2, mode 1, mode 0. bit 2 LYC == LY, bit 1-0 mode
+0xFF42 SCY, 0xFF43 SCX: background scroll
+0xFF44 LY: current line, read only
+0xFF45 LYC: compared with LY
+0xFF47 BGP, 0xFF48 OBP0, 0xFF49 OBP1: palettes, 2 bits per color index
+0xFF4A WY, 0xFF4B WX: window position, the window starts at (WX - 7, WY)
+*/
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+pub const DOTS_PER_LINE: u32 = 456;
+pub const LINES_PER_FRAME: u8 = 154;
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAWING_DOTS: u32 = 172;
+
+pub const LCDC: u16 = 0xFF40;
+pub const STAT: u16 = 0xFF41;
+pub const SCY: u16 = 0xFF42;
+pub const SCX: u16 = 0xFF43;
+pub const LY: u16 = 0xFF44;
+pub const LYC: u16 = 0xFF45;
+pub const BGP: u16 = 0xFF47;
+pub const OBP0: u16 = 0xFF48;
+pub const OBP1: u16 = 0xFF49;
+pub const WY: u16 = 0xFF4A;
+pub const WX: u16 = 0xFF4B;
+
+pub const OAM_START: u16 = 0xFE00;
+pub const OAM_END: u16 = 0xFE9F;
+pub const OAM_SIZE: usize = 0xA0;
+
+const OBJECTS_PER_LINE: usize = 10;
+const OAM_ENTRY_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PpuMode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+// Scanline draws a whole line at the end of mode 3, mode 3 always takes 172 dots.
+// PixelFifo draws pixel by pixel during mode 3, register changes in the middle of a line are visible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    Scanline,
+    PixelFifo,
+}
+
+// one entry of OAM, 4 bytes
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Object {
+    pub(super) y: u8,
+    pub(super) x: u8,
+    pub(super) tile: u8,
+    pub(super) flags: u8,
+}
+
+pub struct Ppu {
+    pub(super) lcdc: u8,
+    pub(super) stat: u8, // only interrupt select bits 3-6 are stored
+    pub(super) scy: u8,
+    pub(super) scx: u8,
+    pub(super) ly: u8,
+    pub(super) lyc: u8,
+    pub(super) bgp: u8,
+    pub(super) obp0: u8,
+    pub(super) obp1: u8,
+    pub(super) wy: u8,
+    pub(super) wx: u8,
+    mode: PpuMode,
+    dot: u32,                         // dot in the current line
+    pub(super) window_line: u8,       // internal line counter of the window
+    stat_line: bool,                  // STAT interrupt is requested on the rising edge of this line
+    pub(super) frame_buffer: Vec<u8>, // 160 x 144 shades, 0 white - 3 black
+    frame_ready: bool,
+    render_mode: RenderMode,
+    line_render_mode: RenderMode, // render mode latched at the start of mode 3
+    pub(super) fifo: PixelFifo,
+    pub(super) vram: VRAM,   // 0x8000 - 0x9FFF
+    pub(super) oam: Vec<u8>, // 0xFE00 - 0xFE9F
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Self {
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            mode: PpuMode::HBlank,
+            dot: 0,
+            window_line: 0,
+            stat_line: false,
+            frame_buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_ready: false,
+            render_mode: RenderMode::Scanline,
+            line_render_mode: RenderMode::Scanline,
+            fifo: PixelFifo::new(),
+            vram: VRAM::new(),
+            oam: vec![0; OAM_SIZE],
         }
     }
+
+    pub fn reset(&mut self) {
+        let render_mode = self.render_mode;
+        *self = Self::new();
+        self.render_mode = render_mode;
+    }
+
+    pub fn render_mode(&self) -> RenderMode {
+        self.render_mode
+    }
+
+    // takes effect from the next line
+    pub fn set_render_mode(&mut self, render_mode: RenderMode) {
+        self.render_mode = render_mode;
+    }
+
+    pub fn mode(&self) -> PpuMode {
+        self.mode
+    }
+
+    pub fn ly(&self) -> u8 {
+        self.ly
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0x80 != 0
+    }
+
+    pub fn vram(&self) -> &VRAM {
+        &self.vram
+    }
+
+    // VRAM can not be accessed by the cpu in mode 3
+    fn vram_blocked(&self) -> bool {
+        self.lcd_enabled() && self.mode == PpuMode::Drawing
+    }
+
+    // OAM can not be accessed by the cpu in mode 2 and 3
+    pub fn oam_blocked(&self) -> bool {
+        self.lcd_enabled() && matches!(self.mode, PpuMode::OamScan | PpuMode::Drawing)
+    }
+
+    // cpu read of 0x8000 - 0x9FFF, 0xFF when blocked
+    pub fn read_vram(&self, address: u16) -> Result<u8, Error> {
+        if self.vram_blocked() {
+            return Ok(0xFF);
+        }
+        self.vram.read_byte(address)
+    }
+
+    // cpu write of 0x8000 - 0x9FFF, ignored when blocked
+    pub fn write_vram(&mut self, address: u16, value: u8) -> Result<(), Error> {
+        if self.vram_blocked() {
+            return Ok(());
+        }
+        self.vram.write_byte(address, value)
+    }
+
+    // cpu read of 0xFE00 - 0xFE9F, 0xFF when blocked
+    pub fn read_oam(&self, address: u16) -> Result<u8, Error> {
+        if !(OAM_START..=OAM_END).contains(&address) {
+            return Err(Error::OAMAddressError);
+        }
+        if self.oam_blocked() {
+            return Ok(0xFF);
+        }
+        Ok(self.oam[(address - OAM_START) as usize])
+    }
+
+    // cpu write of 0xFE00 - 0xFE9F, ignored when blocked
+    pub fn write_oam(&mut self, address: u16, value: u8) -> Result<(), Error> {
+        if !(OAM_START..=OAM_END).contains(&address) {
+            return Err(Error::OAMAddressError);
+        }
+        if !self.oam_blocked() {
+            self.oam[(address - OAM_START) as usize] = value;
+        }
+        Ok(())
+    }
+
+    // OAM DMA write, not blocked by the ppu mode
+    pub fn dma_write_oam(&mut self, offset: u8, value: u8) {
+        self.oam[offset as usize] = value;
+    }
+
+    pub fn frame_buffer(&self) -> &[u8] {
+        &self.frame_buffer
+    }
+
+    // return true once after a frame is completed
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::replace(&mut self.frame_ready, false)
+    }
+
+    pub fn read_byte(&self, address: u16) -> Result<u8, Error> {
+        let value = match address {
+            LCDC => self.lcdc,
+            STAT => {
+                let coincidence = if self.ly == self.lyc { 0b100 } else { 0 };
+                let mode = if self.lcd_enabled() {
+                    self.mode as u8
+                } else {
+                    0
+                };
+                0x80 | self.stat | coincidence | mode
+            }
+            SCY => self.scy,
+            SCX => self.scx,
+            LY => self.ly,
+            LYC => self.lyc,
+            BGP => self.bgp,
+            OBP0 => self.obp0,
+            OBP1 => self.obp1,
+            WY => self.wy,
+            WX => self.wx,
+            _ => return Err(Error::IORegisterAddressError),
+        };
+        Ok(value)
+    }
+
+    // write to LCD registers, return the interrupt bits requested by the write
+    pub fn write_byte(&mut self, address: u16, value: u8) -> Result<u8, Error> {
+        match address {
+            LCDC => {
+                let was_enabled = self.lcd_enabled();
+                self.lcdc = value;
+                if was_enabled && !self.lcd_enabled() {
+                    // LCD off, LY is reset and the PPU stops
+                    self.ly = 0;
+                    self.dot = 0;
+                    self.mode = PpuMode::HBlank;
+                    self.window_line = 0;
+                } else if !was_enabled && self.lcd_enabled() {
+                    // LCD on, start from the OAM scan of line 0
+                    self.mode = PpuMode::OamScan;
+                }
+            }
+            STAT => self.stat = value & 0b0111_1000,
+            SCY => self.scy = value,
+            SCX => self.scx = value,
+            LY => {} // read only
+            LYC => self.lyc = value,
+            BGP => self.bgp = value,
+            OBP0 => self.obp0 = value,
+            OBP1 => self.obp1 = value,
+            WY => self.wy = value,
+            WX => self.wx = value,
+            _ => return Err(Error::IORegisterAddressError),
+        }
+        Ok(self.update_stat_line())
+    }
+
+    // advance the PPU by clock cycles, return the interrupt bits requested
+    pub fn step(&mut self, cycles: u32) -> u8 {
+        if !self.lcd_enabled() {
+            return 0;
+        }
+        let mut interrupts = 0;
+        let mut remaining = cycles;
+        while remaining > 0 {
+            if self.mode == PpuMode::Drawing && self.line_render_mode == RenderMode::PixelFifo {
+                // mode 3 length depends on the FIFO, advance dot by dot
+                self.dot += 1;
+                remaining -= 1;
+                if self.fifo_tick() {
+                    interrupts |= self.next_mode();
+                }
+                continue;
+            }
+            let advance = remaining.min(self.mode_end_dot() - self.dot);
+            self.dot += advance;
+            remaining -= advance;
+            if self.dot == self.mode_end_dot() {
+                interrupts |= self.next_mode();
+            }
+        }
+        interrupts
+    }
+
+    fn mode_end_dot(&self) -> u32 {
+        match self.mode {
+            PpuMode::OamScan => OAM_SCAN_DOTS,
+            PpuMode::Drawing => OAM_SCAN_DOTS + DRAWING_DOTS,
+            PpuMode::HBlank | PpuMode::VBlank => DOTS_PER_LINE,
+        }
+    }
+
+    fn next_mode(&mut self) -> u8 {
+        let mut interrupts = 0;
+        match self.mode {
+            PpuMode::OamScan => {
+                self.line_render_mode = self.render_mode;
+                if self.line_render_mode == RenderMode::PixelFifo {
+                    self.fifo_start_line();
+                }
+                self.mode = PpuMode::Drawing;
+            }
+            PpuMode::Drawing => {
+                // the FIFO has already drawn the line
+                if self.line_render_mode == RenderMode::Scanline {
+                    self.render_line();
+                }
+                self.mode = PpuMode::HBlank;
+            }
+            PpuMode::HBlank | PpuMode::VBlank => {
+                self.dot = 0;
+                self.ly += 1;
+                if self.ly == SCREEN_HEIGHT as u8 {
+                    self.mode = PpuMode::VBlank;
+                    self.window_line = 0;
+                    self.frame_ready = true;
+                    interrupts |= Interrupt::VBlank.bit();
+                } else if self.ly == LINES_PER_FRAME {
+                    self.ly = 0;
+                    self.mode = PpuMode::OamScan;
+                } else if self.mode == PpuMode::HBlank {
+                    self.mode = PpuMode::OamScan;
+                }
+            }
+        }
+        interrupts | self.update_stat_line()
+    }
+
+    // STAT interrupt is requested on the rising edge of the OR of all selected sources
+    fn update_stat_line(&mut self) -> u8 {
+        let line = self.lcd_enabled()
+            && ((self.stat & 0b0100_0000 != 0 && self.ly == self.lyc)
+                || (self.stat & 0b0010_0000 != 0 && self.mode == PpuMode::OamScan)
+                || (self.stat & 0b0001_0000 != 0 && self.mode == PpuMode::VBlank)
+                || (self.stat & 0b0000_1000 != 0 && self.mode == PpuMode::HBlank));
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        if rising {
+            Interrupt::LcdStat.bit()
+        } else {
+            0
+        }
+    }
+
+    // address of a bg / window tile, based on LCDC.4
+    pub(super) fn bg_tile_address(&self, index: u8) -> u16 {
+        VRAM::get_tile_address(index, self.lcdc)
+    }
+
+    // address of an object tile, objects always use 0x8000 - 0x8FFF
+    pub(super) fn object_tile_address(index: u8) -> u16 {
+        BASE_PONTER_1 + index as u16 * TILE_SIZE as u16
+    }
+
+    pub(super) fn apply_palette(palette: u8, color_index: u8) -> u8 {
+        (palette >> (color_index * 2)) & 0b11
+    }
+
+    fn render_line(&mut self) {
+        let ly = self.ly;
+        // bg color index of every pixel, used by the object priority
+        let mut bg_line = [0u8; SCREEN_WIDTH];
+
+        if self.lcdc & 0b0000_0001 != 0 {
+            // background
+            let map = if self.lcdc & 0b0000_1000 != 0 {
+                WINDOW_START
+            } else {
+                BACKGROUND_START
+            };
+            let y = ly.wrapping_add(self.scy);
+            for (x, color) in bg_line.iter_mut().enumerate() {
+                let x = (x as u8).wrapping_add(self.scx);
+                let index = self.vram.tile_map_index(map, x / 8, y / 8);
+                *color = self
+                    .vram
+                    .tile_color_index(self.bg_tile_address(index), x % 8, y % 8);
+            }
+
+            // window
+            let window_x = self.wx as i16 - 7;
+            if self.lcdc & 0b0010_0000 != 0 && ly >= self.wy && window_x < SCREEN_WIDTH as i16 {
+                let map = if self.lcdc & 0b0100_0000 != 0 {
+                    WINDOW_START
+                } else {
+                    BACKGROUND_START
+                };
+                let y = self.window_line;
+                for (x, color) in bg_line
+                    .iter_mut()
+                    .enumerate()
+                    .skip(window_x.max(0) as usize)
+                {
+                    let x = (x as i16 - window_x) as u8;
+                    let index = self.vram.tile_map_index(map, x / 8, y / 8);
+                    *color = self
+                        .vram
+                        .tile_color_index(self.bg_tile_address(index), x % 8, y % 8);
+                }
+                self.window_line += 1;
+            }
+        }
+
+        let row = ly as usize * SCREEN_WIDTH;
+        for (x, color) in bg_line.iter().enumerate() {
+            self.frame_buffer[row + x] = Self::apply_palette(self.bgp, *color);
+        }
+
+        if self.lcdc & 0b0000_0010 != 0 {
+            self.render_objects(&bg_line);
+        }
+    }
+
+    // select up to 10 objects on the current line, in OAM order
+    pub(super) fn scan_oam(&self) -> Vec<Object> {
+        let height = if self.lcdc & 0b0000_0100 != 0 { 16 } else { 8 };
+        let line = self.ly as i16 + 16;
+        self.oam
+            .chunks(OAM_ENTRY_SIZE)
+            .map(|entry| Object {
+                y: entry[0],
+                x: entry[1],
+                tile: entry[2],
+                flags: entry[3],
+            })
+            .filter(|object| line >= object.y as i16 && line < object.y as i16 + height)
+            .take(OBJECTS_PER_LINE)
+            .collect()
+    }
+
+    fn render_objects(&mut self, bg_line: &[u8; SCREEN_WIDTH]) {
+        let tall = self.lcdc & 0b0000_0100 != 0;
+        let mut objects = self.scan_oam();
+        // smaller x has higher priority, the earlier OAM entry wins when x is equal
+        objects.sort_by_key(|object| object.x);
+
+        let row = self.ly as usize * SCREEN_WIDTH;
+        let mut drawn = [false; SCREEN_WIDTH];
+        for object in objects {
+            let mut y = (self.ly as i16 + 16 - object.y as i16) as u8;
+            if object.flags & 0b0100_0000 != 0 {
+                y = if tall { 15 - y } else { 7 - y };
+            }
+            let tile = if tall {
+                object.tile & 0xFE
+            } else {
+                object.tile
+            };
+            let tile_address = Self::object_tile_address(tile);
+            let palette = if object.flags & 0b0001_0000 != 0 {
+                self.obp1
+            } else {
+                self.obp0
+            };
+
+            for px in 0..8u8 {
+                let x = object.x as i16 - 8 + px as i16;
+                if x < 0 || x >= SCREEN_WIDTH as i16 || drawn[x as usize] {
+                    continue;
+                }
+                let tx = if object.flags & 0b0010_0000 != 0 {
+                    7 - px
+                } else {
+                    px
+                };
+                let color = self.vram.tile_color_index(tile_address, tx, y);
+                if color == 0 {
+                    // transparent, an object behind can still be drawn
+                    continue;
+                }
+                drawn[x as usize] = true;
+                // bg over object, object is hidden behind bg color 1 - 3
+                if object.flags & 0b1000_0000 != 0 && bg_line[x as usize] != 0 {
+                    continue;
+                }
+                self.frame_buffer[row + x as usize] = Self::apply_palette(palette, color);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_timing() {
+        let mut ppu = Ppu::new();
+        ppu.write_byte(LCDC, 0x80).unwrap();
+        assert_eq!(ppu.mode(), PpuMode::OamScan);
+        ppu.step(80);
+        assert_eq!(ppu.mode(), PpuMode::Drawing);
+        ppu.step(172);
+        assert_eq!(ppu.mode(), PpuMode::HBlank);
+        ppu.step(204);
+        assert_eq!(ppu.mode(), PpuMode::OamScan);
+        assert_eq!(ppu.ly(), 1);
+    }
+
+    #[test]
+    fn test_vblank_interrupt() {
+        let mut ppu = Ppu::new();
+        ppu.write_byte(LCDC, 0x80).unwrap();
+        let interrupts = ppu.step(DOTS_PER_LINE * 143);
+        assert_eq!(interrupts & Interrupt::VBlank.bit(), 0);
+        let interrupts = ppu.step(DOTS_PER_LINE);
+        assert_ne!(interrupts & Interrupt::VBlank.bit(), 0);
+        assert_eq!(ppu.mode(), PpuMode::VBlank);
+        assert_eq!(ppu.ly(), 144);
+        assert!(ppu.take_frame_ready());
+        assert!(!ppu.take_frame_ready());
+
+        // a frame is 154 lines
+        ppu.step(DOTS_PER_LINE * 10);
+        assert_eq!(ppu.ly(), 0);
+        assert_eq!(ppu.mode(), PpuMode::OamScan);
+    }
+
+    #[test]
+    fn test_lyc_stat_interrupt() {
+        let mut ppu = Ppu::new();
+        ppu.write_byte(LYC, 2).unwrap();
+        ppu.write_byte(STAT, 0b0100_0000).unwrap();
+        ppu.write_byte(LCDC, 0x80).unwrap();
+        let interrupts = ppu.step(DOTS_PER_LINE);
+        assert_eq!(interrupts & Interrupt::LcdStat.bit(), 0);
+        let interrupts = ppu.step(DOTS_PER_LINE);
+        assert_ne!(interrupts & Interrupt::LcdStat.bit(), 0);
+        assert_eq!(ppu.read_byte(STAT).unwrap() & 0b100, 0b100);
+    }
+
+    #[test]
+    fn test_render_background_and_object() {
+        let mut ppu = Ppu::new();
+        // tile 1 is filled with color 3, tile 2 with color 1
+        for i in 0..16 {
+            ppu.write_vram(0x8010 + i, 0xFF).unwrap();
+        }
+        for i in 0..8 {
+            ppu.write_vram(0x8020 + i * 2, 0xFF).unwrap();
+        }
+        // background map 9800, first tile uses tile 1
+        ppu.write_vram(0x9800, 1).unwrap();
+        // object 0 at screen (16, 0) using tile 2
+        ppu.write_oam(0xFE00, 16).unwrap();
+        ppu.write_oam(0xFE01, 24).unwrap();
+        ppu.write_oam(0xFE02, 2).unwrap();
+
+        ppu.write_byte(BGP, 0b1110_0100).unwrap();
+        ppu.write_byte(OBP0, 0b1110_0100).unwrap();
+        ppu.write_byte(LCDC, 0b1001_0011).unwrap();
+        ppu.step(DOTS_PER_LINE);
+
+        let line = &ppu.frame_buffer()[0..SCREEN_WIDTH];
+        assert!(line[0..8].iter().all(|&shade| shade == 3));
+        assert!(line[8..16].iter().all(|&shade| shade == 0));
+        assert!(line[16..24].iter().all(|&shade| shade == 1));
+        assert!(line[24..].iter().all(|&shade| shade == 0));
+    }
+
+    #[test]
+    fn test_vram_oam_blocking() {
+        let mut ppu = Ppu::new();
+        ppu.write_vram(0x8000, 0x12).unwrap();
+        ppu.write_oam(0xFE00, 0x34).unwrap();
+        ppu.write_byte(LCDC, 0x80).unwrap();
+
+        // mode 2, OAM is blocked
+        assert_eq!(ppu.read_vram(0x8000).unwrap(), 0x12);
+        assert_eq!(ppu.read_oam(0xFE00).unwrap(), 0xFF);
+        ppu.write_oam(0xFE00, 0x56).unwrap();
+
+        // mode 3, both are blocked
+        ppu.step(80);
+        assert_eq!(ppu.read_vram(0x8000).unwrap(), 0xFF);
+        assert_eq!(ppu.read_oam(0xFE00).unwrap(), 0xFF);
+        ppu.write_vram(0x8000, 0x78).unwrap();
+
+        // mode 0, writes in the blocked modes were ignored
+        ppu.step(172);
+        assert_eq!(ppu.read_vram(0x8000).unwrap(), 0x12);
+        assert_eq!(ppu.read_oam(0xFE00).unwrap(), 0x34);
+    }
+
+    #[test]
+    fn test_vram_tiles() {
+        let mut ppu = Ppu::new();
+        ppu.write_vram(0x8010, 0x3C).unwrap();
+        ppu.write_vram(0x8011, 0x7E).unwrap();
+        ppu.write_vram(0x9FFF, 0xAB).unwrap();
+
+        let tile = ppu.vram().read_tile(0x8010).unwrap();
+        assert_eq!(tile.color_index(0, 0), 0b00);
+        assert_eq!(tile.color_index(1, 0), 0b10);
+        assert_eq!(tile.color_index(2, 0), 0b11);
+        assert_eq!(ppu.read_vram(0x9FFF).unwrap(), 0xAB);
+        assert_eq!(ppu.vram().read_window_tile_index(0x9FFF).unwrap(), 0xAB);
+        assert!(ppu.vram().read_tile(0x8011).is_err());
+    }
 }
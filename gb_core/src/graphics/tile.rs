@@ -25,11 +25,29 @@ The content of the window is not scrollable.
 The only way to modify the window is to change its position using the WX and WY registers.
 */
 
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Tile {
     data: [u8; 16],
 }
 
 impl Tile {
+    // update one of the 16 bytes of the tile
+    pub fn write_byte(&mut self, offset: usize, value: u8) {
+        self.data[offset] = value;
+    }
+
+    pub fn read_byte(&self, offset: usize) -> u8 {
+        self.data[offset]
+    }
+
+    // color index (0 - 3) of the pixel at column x, row y
+    pub fn color_index(&self, x: u8, y: u8) -> u8 {
+        let byte1 = self.data[y as usize * 2]; // Least significant bits
+        let byte2 = self.data[y as usize * 2 + 1]; // Most significant bits
+        let bit = 7 - x;
+        (((byte2 >> bit) & 1) << 1) | ((byte1 >> bit) & 1)
+    }
+
     pub fn from_bytes(bytes: &[u8]) -> Tile {
         let mut data = [0u8; 16];
         for i in 0..bytes.len() {
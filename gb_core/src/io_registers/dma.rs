@@ -0,0 +1,133 @@
+/*
+OAM DMA:
+0xFF46 DMA: writing XX starts a transfer of 160 bytes from 0xXX00 - 0xXX9F into OAM (0xFE00 - 0xFE9F).
+            reading returns the last written value.
+
+The transfer takes one machine cycle to set up, then copies one byte per machine cycle for 160 machine cycles.
+While the transfer is running the cpu can only access HRAM (and the IO registers), other reads return 0xFF.
+Writing DMA during a transfer restarts it, the old transfer keeps running until the new one has been set up.
+Sources above 0xDFFF read from WRAM, like the echo RAM.
+*/
+
+use crate::core::Error;
+use std::result::Result;
+
+pub const DMA: u16 = 0xFF46;
+pub const DMA_LENGTH: u16 = 0xA0;
+
+pub struct OamDma {
+    register: u8,
+    // source address of the running transfer
+    source: u16,
+    // bytes copied by the running transfer
+    index: u16,
+    active: bool,
+    // machine cycles until the requested transfer replaces the running one
+    start_delay: u8,
+    next_source: u16,
+}
+
+impl OamDma {
+    pub fn new() -> Self {
+        Self {
+            register: 0,
+            source: 0,
+            index: 0,
+            active: false,
+            start_delay: 0,
+            next_source: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    // true while bytes are being copied, the cpu bus is blocked
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // advance one machine cycle, return the (source address, OAM offset) of the byte to copy
+    pub fn tick(&mut self) -> Option<(u16, u8)> {
+        let transfer = if self.active {
+            let transfer = (self.source + self.index, self.index as u8);
+            self.index += 1;
+            if self.index == DMA_LENGTH {
+                self.active = false;
+            }
+            Some(transfer)
+        } else {
+            None
+        };
+
+        if self.start_delay > 0 {
+            self.start_delay -= 1;
+            if self.start_delay == 0 {
+                self.active = true;
+                self.source = self.next_source;
+                self.index = 0;
+            }
+        }
+        transfer
+    }
+
+    pub fn read_byte(&self, address: u16) -> Result<u8, Error> {
+        match address {
+            DMA => Ok(self.register),
+            _ => Err(Error::IORegisterAddressError),
+        }
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) -> Result<(), Error> {
+        match address {
+            DMA => {
+                self.register = value;
+                // the source is always read from below 0xE000
+                let source = (value as u16) << 8;
+                self.next_source = if source >= 0xE000 {
+                    source - 0x2000
+                } else {
+                    source
+                };
+                self.start_delay = 1;
+                Ok(())
+            }
+            _ => Err(Error::IORegisterAddressError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dma_transfer_length() {
+        let mut dma = OamDma::new();
+        dma.write_byte(DMA, 0xC1).unwrap();
+        assert_eq!(dma.read_byte(DMA).unwrap(), 0xC1);
+
+        // one machine cycle to set up
+        assert_eq!(dma.tick(), None);
+        for i in 0..DMA_LENGTH {
+            assert!(dma.is_active());
+            assert_eq!(dma.tick(), Some((0xC100 + i, i as u8)));
+        }
+        assert!(!dma.is_active());
+        assert_eq!(dma.tick(), None);
+    }
+
+    #[test]
+    fn test_dma_restart() {
+        let mut dma = OamDma::new();
+        dma.write_byte(DMA, 0xC0).unwrap();
+        for _ in 0..11 {
+            dma.tick();
+        }
+        // the old transfer continues during the set up of the new one
+        dma.write_byte(DMA, 0xFE).unwrap();
+        assert_eq!(dma.tick(), Some((0xC00A, 0x0A)));
+        assert_eq!(dma.tick(), Some((0xDE00, 0x00)));
+    }
+}
@@ -0,0 +1,152 @@
+/*
+Joypad:
+0xFF00 P1: bit 5 selects the action buttons, bit 4 selects the direction buttons, both active low.
+           bit 0-3 are the input lines of the selected buttons, 0 means pressed.
+           bit 6-7 are unused and read as 1.
+
+           bit 3: Down  / Start
+           bit 2: Up    / Select
+           bit 1: Left  / B
+           bit 0: Right / A
+
+The joypad interrupt is requested when any input line goes from high to low,
+either because a button is pressed or because a row with a pressed button is selected.
+*/
+
+use crate::core::Error;
+use std::result::Result;
+
+pub const P1: u16 = 0xFF00;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    // bit in the pressed state, direction buttons in the low nibble, action buttons in the high nibble
+    fn bit(self) -> u8 {
+        match self {
+            Button::Right => 0b0000_0001,
+            Button::Left => 0b0000_0010,
+            Button::Up => 0b0000_0100,
+            Button::Down => 0b0000_1000,
+            Button::A => 0b0001_0000,
+            Button::B => 0b0010_0000,
+            Button::Select => 0b0100_0000,
+            Button::Start => 0b1000_0000,
+        }
+    }
+}
+
+pub struct Joypad {
+    // bit 4-5 of P1
+    select: u8,
+    // 1 means pressed
+    pressed: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self {
+            select: 0x30,
+            pressed: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    // the 4 input lines, active low
+    fn lines(&self) -> u8 {
+        let mut lines = 0;
+        if self.select & 0x10 == 0 {
+            lines |= self.pressed & 0x0F;
+        }
+        if self.select & 0x20 == 0 {
+            lines |= self.pressed >> 4;
+        }
+        !lines & 0x0F
+    }
+
+    // true when any line went from high to low
+    fn falling_edge(old_lines: u8, new_lines: u8) -> bool {
+        old_lines & !new_lines != 0
+    }
+
+    // update a button, return true when the joypad interrupt is requested
+    pub fn set_button(&mut self, button: Button, pressed: bool) -> bool {
+        let old_lines = self.lines();
+        if pressed {
+            self.pressed |= button.bit();
+        } else {
+            self.pressed &= !button.bit();
+        }
+        Self::falling_edge(old_lines, self.lines())
+    }
+
+    pub fn read_byte(&self, address: u16) -> Result<u8, Error> {
+        match address {
+            P1 => Ok(0xC0 | self.select | self.lines()),
+            _ => Err(Error::IORegisterAddressError),
+        }
+    }
+
+    // return true when the joypad interrupt is requested
+    pub fn write_byte(&mut self, address: u16, value: u8) -> Result<bool, Error> {
+        match address {
+            P1 => {
+                let old_lines = self.lines();
+                // only the select bits are writable
+                self.select = value & 0x30;
+                Ok(Self::falling_edge(old_lines, self.lines()))
+            }
+            _ => Err(Error::IORegisterAddressError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_lines() {
+        let mut joypad = Joypad::new();
+        // nothing selected reads all lines high
+        assert_eq!(joypad.read_byte(P1).unwrap(), 0xFF);
+
+        joypad.set_button(Button::Start, true);
+        joypad.set_button(Button::Left, true);
+
+        joypad.write_byte(P1, 0x20).unwrap();
+        assert_eq!(joypad.read_byte(P1).unwrap(), 0xE0 | 0b1101);
+        joypad.write_byte(P1, 0x10).unwrap();
+        assert_eq!(joypad.read_byte(P1).unwrap(), 0xD0 | 0b0111);
+        // both rows selected
+        joypad.write_byte(P1, 0x00).unwrap();
+        assert_eq!(joypad.read_byte(P1).unwrap(), 0xC0 | 0b0101);
+    }
+
+    #[test]
+    fn test_interrupt_on_falling_edge() {
+        let mut joypad = Joypad::new();
+        // the row is not selected, no line changes
+        assert!(!joypad.set_button(Button::A, true));
+        // selecting the row with A pressed pulls a line low
+        assert!(joypad.write_byte(P1, 0x10).unwrap());
+        // releasing is a rising edge
+        assert!(!joypad.set_button(Button::A, false));
+        assert!(joypad.set_button(Button::B, true));
+        // the line is already low
+        assert!(!joypad.set_button(Button::B, true));
+    }
+}
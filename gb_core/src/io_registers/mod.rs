@@ -1,3 +1,13 @@
+pub mod dma;
 pub mod io_registers;
+pub mod joypad;
+pub mod serial;
+pub mod speed;
+pub mod timer;
 
+pub use dma::OamDma;
 pub use io_registers::IOResgisters;
+pub use joypad::{Button, Joypad};
+pub use serial::{Disconnected, Serial, SerialCapture, SerialDevice};
+pub use speed::SpeedSwitch;
+pub use timer::HardwareTimer;
@@ -0,0 +1,202 @@
+/*
+Serial:
+0xFF01 SB: the byte to send, replaced bit by bit with the received byte during a transfer.
+0xFF02 SC: bit 7 starts a transfer and stays set until it is completed,
+           bit 0 selects the clock, 1 is the internal clock at 8192 Hz, 0 waits for the external clock.
+           bit 1-6 are unused and read as 1.
+
+With the internal clock one bit is shifted out every 512 clock cycles, a byte takes 4096 clock cycles.
+After the 8th bit SC bit 7 is cleared and the serial interrupt is requested.
+The other end of the link cable is a SerialDevice, it receives the outgoing byte and returns the incoming one.
+*/
+
+use crate::core::Error;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::result::Result;
+
+pub const SB: u16 = 0xFF01;
+pub const SC: u16 = 0xFF02;
+
+const CYCLES_PER_BIT: u32 = 512;
+
+// the other end of the link cable
+pub trait SerialDevice {
+    // exchange a byte, return the byte sent back
+    fn transfer(&mut self, value: u8) -> u8;
+}
+
+// no cable connected, the input line stays high
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn transfer(&mut self, _value: u8) -> u8 {
+        0xFF
+    }
+}
+
+// records every byte sent, used by test roms that print their results over serial
+pub struct SerialCapture {
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Default for SerialCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SerialCapture {
+    pub fn new() -> Self {
+        Self {
+            output: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    // shared handle to the bytes sent, still readable after the device is moved into the bus
+    pub fn output(&self) -> Rc<RefCell<Vec<u8>>> {
+        Rc::clone(&self.output)
+    }
+}
+
+impl SerialDevice for SerialCapture {
+    fn transfer(&mut self, value: u8) -> u8 {
+        self.output.borrow_mut().push(value);
+        0xFF
+    }
+}
+
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    device: Box<dyn SerialDevice>,
+    // byte received from the device, shifted into SB bit by bit
+    incoming: u8,
+    bits_left: u8,
+    cycles: u32,
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Self {
+            sb: 0,
+            sc: 0,
+            device: Box::new(Disconnected),
+            incoming: 0xFF,
+            bits_left: 0,
+            cycles: 0,
+        }
+    }
+
+    // the device stays connected
+    pub fn reset(&mut self) {
+        self.sb = 0;
+        self.sc = 0;
+        self.incoming = 0xFF;
+        self.bits_left = 0;
+        self.cycles = 0;
+    }
+
+    pub fn set_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.device = device;
+    }
+
+    fn transfer_running(&self) -> bool {
+        self.sc & 0x81 == 0x81
+    }
+
+    // advance by clock cycles, return true when the serial interrupt is requested
+    pub fn step(&mut self, cycles: u32) -> bool {
+        if !self.transfer_running() {
+            return false;
+        }
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_BIT && self.bits_left > 0 {
+            self.cycles -= CYCLES_PER_BIT;
+            self.sb = (self.sb << 1) | (self.incoming >> 7);
+            self.incoming <<= 1;
+            self.bits_left -= 1;
+        }
+        if self.bits_left == 0 {
+            self.sc &= 0x7F;
+            self.cycles = 0;
+            return true;
+        }
+        false
+    }
+
+    pub fn read_byte(&self, address: u16) -> Result<u8, Error> {
+        match address {
+            SB => Ok(self.sb),
+            SC => Ok(self.sc | 0x7E),
+            _ => Err(Error::IORegisterAddressError),
+        }
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) -> Result<(), Error> {
+        match address {
+            SB => self.sb = value,
+            SC => {
+                self.sc = value & 0x81;
+                if self.transfer_running() {
+                    self.incoming = self.device.transfer(self.sb);
+                    self.bits_left = 8;
+                    self.cycles = 0;
+                }
+            }
+            _ => return Err(Error::IORegisterAddressError),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // sends back the complement of the received byte
+    struct Inverter;
+
+    impl SerialDevice for Inverter {
+        fn transfer(&mut self, value: u8) -> u8 {
+            !value
+        }
+    }
+
+    #[test]
+    fn test_internal_clock_transfer() {
+        let mut serial = Serial::new();
+        serial.set_device(Box::new(Inverter));
+        serial.write_byte(SB, 0xA5).unwrap();
+        serial.write_byte(SC, 0x81).unwrap();
+
+        assert!(!serial.step(CYCLES_PER_BIT * 4));
+        assert_eq!(serial.read_byte(SB).unwrap(), 0x55);
+        assert_eq!(serial.read_byte(SC).unwrap(), 0xFF);
+        assert!(serial.step(CYCLES_PER_BIT * 4));
+        assert_eq!(serial.read_byte(SB).unwrap(), 0x5A);
+        assert_eq!(serial.read_byte(SC).unwrap(), 0x7F);
+        assert!(!serial.step(CYCLES_PER_BIT * 8));
+    }
+
+    #[test]
+    fn test_capture_and_external_clock() {
+        let mut serial = Serial::new();
+        let capture = SerialCapture::new();
+        let output = capture.output();
+        serial.set_device(Box::new(capture));
+
+        for byte in b"ok" {
+            serial.write_byte(SB, *byte).unwrap();
+            serial.write_byte(SC, 0x81).unwrap();
+            serial.step(CYCLES_PER_BIT * 8);
+        }
+        assert_eq!(output.borrow().as_slice(), b"ok");
+        assert_eq!(serial.read_byte(SB).unwrap(), 0xFF);
+
+        // without an external clock the transfer never completes
+        serial.write_byte(SC, 0x80).unwrap();
+        assert!(!serial.step(CYCLES_PER_BIT * 16));
+        assert_eq!(serial.read_byte(SC).unwrap(), 0xFE);
+    }
+}
@@ -0,0 +1,109 @@
+/*
+Speed switch (CGB only):
+0xFF4D KEY1: bit 7 is the current speed, 1 is double speed, read only.
+             bit 0 arms the switch, STOP then toggles the speed instead of sleeping.
+             bit 1-6 are unused and read as 1.
+
+In double speed the cpu, timer, serial and OAM DMA run twice as fast, the ppu keeps its clock.
+On DMG, or a CGB in DMG compatibility mode, KEY1 reads 0xFF and writes are ignored.
+*/
+
+use crate::core::Error;
+use std::result::Result;
+
+pub const KEY1: u16 = 0xFF4D;
+
+// machine cycles the cpu stalls for while the clock switches
+pub const SPEED_SWITCH_CYCLES: u16 = 2050;
+
+pub struct SpeedSwitch {
+    cgb_mode: bool,
+    double_speed: bool,
+    armed: bool,
+}
+
+impl SpeedSwitch {
+    pub fn new() -> Self {
+        Self {
+            cgb_mode: false,
+            double_speed: false,
+            armed: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn set_cgb_mode(&mut self, cgb_mode: bool) {
+        self.cgb_mode = cgb_mode;
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    // called by STOP, toggle the speed when armed, return true when switched
+    pub fn switch(&mut self) -> bool {
+        if !self.cgb_mode || !self.armed {
+            return false;
+        }
+        self.double_speed = !self.double_speed;
+        self.armed = false;
+        true
+    }
+
+    pub fn read_byte(&self, address: u16) -> Result<u8, Error> {
+        match address {
+            KEY1 if !self.cgb_mode => Ok(0xFF),
+            KEY1 => Ok(0x7E | (self.double_speed as u8) << 7 | self.armed as u8),
+            _ => Err(Error::IORegisterAddressError),
+        }
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) -> Result<(), Error> {
+        match address {
+            KEY1 => {
+                if self.cgb_mode {
+                    self.armed = value & 0x01 != 0;
+                }
+                Ok(())
+            }
+            _ => Err(Error::IORegisterAddressError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dmg_ignores_key1() {
+        let mut speed = SpeedSwitch::new();
+        speed.write_byte(KEY1, 0x01).unwrap();
+        assert_eq!(speed.read_byte(KEY1).unwrap(), 0xFF);
+        assert!(!speed.switch());
+        assert!(!speed.is_double_speed());
+    }
+
+    #[test]
+    fn test_armed_switch() {
+        let mut speed = SpeedSwitch::new();
+        speed.set_cgb_mode(true);
+        assert_eq!(speed.read_byte(KEY1).unwrap(), 0x7E);
+        // not armed, STOP does not switch
+        assert!(!speed.switch());
+
+        speed.write_byte(KEY1, 0x01).unwrap();
+        assert_eq!(speed.read_byte(KEY1).unwrap(), 0x7F);
+        assert!(speed.switch());
+        assert!(speed.is_double_speed());
+        // the switch disarms itself
+        assert_eq!(speed.read_byte(KEY1).unwrap(), 0xFE);
+
+        speed.write_byte(KEY1, 0x01).unwrap();
+        assert!(speed.switch());
+        assert!(!speed.is_double_speed());
+    }
+}
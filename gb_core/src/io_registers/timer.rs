@@ -0,0 +1,218 @@
+/*
+Timer:
+0xFF04 DIV: upper 8 bits of the 16-bit system counter, incremented every clock cycle.
+            writing any value resets the whole system counter to 0.
+0xFF05 TIMA: incremented at the frequency selected by TAC, when overflow it is reloaded from TMA
+             and the timer interrupt is requested.
+0xFF06 TMA: the value loaded into TIMA on overflow.
+0xFF07 TAC: bit 2 enables TIMA, bit 0-1 select the frequency.
+            00: 4096 Hz (bit 9), 01: 262144 Hz (bit 3), 10: 65536 Hz (bit 5), 11: 16384 Hz (bit 7)
+
+TIMA is increased on the falling edge of (selected system counter bit AND TAC enable),
+so resetting DIV or changing TAC can also increase TIMA.
+After an overflow TIMA reads 0x00 for one machine cycle, then TMA is loaded and the interrupt is requested.
+*/
+
+use crate::core::Error;
+use std::result::Result;
+
+pub const DIV: u16 = 0xFF04;
+pub const TIMA: u16 = 0xFF05;
+pub const TMA: u16 = 0xFF06;
+pub const TAC: u16 = 0xFF07;
+
+pub struct HardwareTimer {
+    system_counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // TIMA overflowed in the last machine cycle, reload from TMA in the next one
+    overflow: bool,
+    // TIMA was reloaded from TMA in the last machine cycle
+    reloaded: bool,
+}
+
+impl HardwareTimer {
+    pub fn new() -> Self {
+        Self {
+            system_counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflow: false,
+            reloaded: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    // the boot rom leaves the counter running, skipping it sets the counter directly
+    pub fn set_system_counter(&mut self, counter: u16) {
+        self.system_counter = counter;
+    }
+
+    pub fn div(&self) -> u8 {
+        (self.system_counter >> 8) as u8
+    }
+
+    // advance the timer by clock cycles, return true when the timer interrupt is requested
+    pub fn step(&mut self, cycles: u32) -> bool {
+        let mut interrupt = false;
+        // the timer works in machine cycles, 4 clock cycles each
+        for _ in 0..cycles / 4 {
+            self.reloaded = false;
+            if self.overflow {
+                self.overflow = false;
+                self.reloaded = true;
+                self.tima = self.tma;
+                interrupt = true;
+            }
+            let old_signal = self.signal();
+            self.system_counter = self.system_counter.wrapping_add(4);
+            if old_signal && !self.signal() {
+                self.increment_tima();
+            }
+        }
+        interrupt
+    }
+
+    // the selected system counter bit, AND the enable bit
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0b11 {
+            0b00 => 9,
+            0b01 => 3,
+            0b10 => 5,
+            _ => 7,
+        };
+        (self.tac & 0b100) != 0 && (self.system_counter >> bit) & 1 == 1
+    }
+
+    fn increment_tima(&mut self) {
+        let (result, overflow) = self.tima.overflowing_add(1);
+        self.tima = result;
+        if overflow {
+            self.overflow = true;
+        }
+    }
+
+    pub fn read_byte(&self, address: u16) -> Result<u8, Error> {
+        match address {
+            DIV => Ok(self.div()),
+            TIMA => Ok(self.tima),
+            TMA => Ok(self.tma),
+            // upper 5 bits are unused
+            TAC => Ok(self.tac | 0xF8),
+            _ => Err(Error::IORegisterAddressError),
+        }
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) -> Result<(), Error> {
+        match address {
+            DIV => {
+                let old_signal = self.signal();
+                self.system_counter = 0;
+                if old_signal {
+                    self.increment_tima();
+                }
+            }
+            TIMA => {
+                // write in the cycle TMA is loaded is ignored
+                if !self.reloaded {
+                    // write during the overflow cycle cancels the reload and the interrupt
+                    self.overflow = false;
+                    self.tima = value;
+                }
+            }
+            TMA => {
+                self.tma = value;
+                // write in the cycle TMA is loaded is also copied into TIMA
+                if self.reloaded {
+                    self.tima = value;
+                }
+            }
+            TAC => {
+                let old_signal = self.signal();
+                self.tac = value & 0x07;
+                if old_signal && !self.signal() {
+                    self.increment_tima();
+                }
+            }
+            _ => return Err(Error::IORegisterAddressError),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_div_increments_and_resets() {
+        let mut timer = HardwareTimer::new();
+        timer.step(256);
+        assert_eq!(timer.read_byte(DIV).unwrap(), 1);
+        timer.step(256 * 9);
+        assert_eq!(timer.read_byte(DIV).unwrap(), 10);
+
+        timer.write_byte(DIV, 0x55).unwrap();
+        assert_eq!(timer.read_byte(DIV).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_tima_frequency() {
+        let mut timer = HardwareTimer::new();
+        // enabled, 262144 Hz, increment every 16 clock cycles
+        timer.write_byte(TAC, 0b101).unwrap();
+        timer.step(16 * 10);
+        assert_eq!(timer.read_byte(TIMA).unwrap(), 10);
+
+        // disabled timer does not count
+        timer.write_byte(TAC, 0b001).unwrap();
+        let tima = timer.read_byte(TIMA).unwrap();
+        timer.step(16 * 10);
+        assert_eq!(timer.read_byte(TIMA).unwrap(), tima);
+    }
+
+    #[test]
+    fn test_tima_overflow_reload_delay() {
+        let mut timer = HardwareTimer::new();
+        timer.write_byte(TMA, 0xAB).unwrap();
+        timer.write_byte(TIMA, 0xFF).unwrap();
+        timer.write_byte(TAC, 0b101).unwrap();
+
+        // overflow, TIMA reads 0 for one machine cycle
+        assert!(!timer.step(16));
+        assert_eq!(timer.read_byte(TIMA).unwrap(), 0x00);
+        // reload from TMA and request interrupt
+        assert!(timer.step(4));
+        assert_eq!(timer.read_byte(TIMA).unwrap(), 0xAB);
+    }
+
+    #[test]
+    fn test_tima_write_cancels_reload() {
+        let mut timer = HardwareTimer::new();
+        timer.write_byte(TMA, 0xAB).unwrap();
+        timer.write_byte(TIMA, 0xFF).unwrap();
+        timer.write_byte(TAC, 0b101).unwrap();
+
+        timer.step(16);
+        timer.write_byte(TIMA, 0x12).unwrap();
+        assert!(!timer.step(4));
+        assert_eq!(timer.read_byte(TIMA).unwrap(), 0x12);
+    }
+
+    #[test]
+    fn test_div_reset_falling_edge() {
+        let mut timer = HardwareTimer::new();
+        timer.write_byte(TAC, 0b101).unwrap();
+        // bit 3 of the system counter is set
+        timer.step(8);
+        assert_eq!(timer.read_byte(TIMA).unwrap(), 0);
+        // resetting the counter is a falling edge
+        timer.write_byte(DIV, 0).unwrap();
+        assert_eq!(timer.read_byte(TIMA).unwrap(), 1);
+    }
+}
@@ -1,7 +1,9 @@
 pub mod app;
+pub mod audio;
 mod cartridge;
 pub mod core;
-mod graphics;
+pub mod graphics;
+pub mod harness;
 mod io_registers;
 pub mod opcodes;
 #[cfg(test)]
@@ -1,6 +1,5 @@
-use log::debug;
-
-use crate::core::CPU;
+use crate::core::{Bus, CPU};
+use crate::opcodes::decoder::{Register, RegisterPair};
 use crate::opcodes::opcode::OPCode;
 
 enum ALUOP {
@@ -13,7 +12,7 @@ enum ALUOP {
     XOR,
 }
 
-fn alu_helper(cpu: &mut CPU, op: ALUOP, num1: u8, num2: u8, carry_in: u8) -> u8 {
+fn alu_helper<B: Bus>(cpu: &mut CPU<B>, op: ALUOP, num1: u8, num2: u8, carry_in: u8) -> u8 {
     let result = match op {
         ALUOP::ADD => {
             let intermediate = (num1 as u16)
@@ -101,8 +100,8 @@ impl OPCode {
     // add opcodes
 
     //ADD r: Add (register) 0b10000xxx
-    pub(super) fn op_10000xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let value = OPCode::concat_bits(&bits[5..]);
+    pub(super) fn op_10000xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
+        let value = *OPCode::get_register(src, cpu);
         // let half_carry = (cpu.A & 0x0F).wrapping_add(value & 0x0F) > 0x0F;
         // let carry = (cpu.A as u16).wrapping_add(value as u16) > 0xFF;
         // cpu.A = cpu.A.wrapping_add(value);
@@ -115,42 +114,41 @@ impl OPCode {
     }
 
     // ADD (HL) 0b10000110
-    pub(super) fn op_10000110(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_10000110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.hl();
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         cpu.a = alu_helper(cpu, ALUOP::ADD, cpu.a, value, 0);
         2
     }
 
     // ADD n: 0b11000110
-    pub(super) fn op_11000110(cpu: &mut CPU) -> u8 {
-        let value = cpu.memory_bus.read_byte(cpu.pc);
+    pub(super) fn op_11000110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
+        let value = cpu.read_cycle(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(1);
         cpu.a = alu_helper(cpu, ALUOP::ADD, cpu.a, value, 0);
         2
     }
 
     // ADC r 0b10001xxx
-    pub(super) fn op_10001xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[5..]);
-        let value = *OPCode::get_register_by_index(index, cpu).unwrap();
+    pub(super) fn op_10001xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
+        let value = *OPCode::get_register(src, cpu);
         let carry = if cpu.c() { 1 } else { 0 };
         cpu.a = alu_helper(cpu, ALUOP::ADD, cpu.a, value, carry);
         1
     }
 
     // ADC (HL) 0b10001110
-    pub(super) fn op_10001110(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_10001110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.hl();
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         let carry = if cpu.c() { 1 } else { 0 };
         cpu.a = alu_helper(cpu, ALUOP::ADD, cpu.a, value, carry);
         2
     }
 
     // ADC n 0b11001110
-    pub(super) fn op_11001110(cpu: &mut CPU) -> u8 {
-        let value = cpu.memory_bus.read_byte(cpu.pc);
+    pub(super) fn op_11001110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
+        let value = cpu.read_cycle(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(1);
         let carry = if cpu.c() { 1 } else { 0 };
         cpu.a = alu_helper(cpu, ALUOP::ADD, cpu.a, value, carry);
@@ -162,50 +160,48 @@ impl OPCode {
     // sub opcodes
 
     // SUB r 0b10010xxx
-    pub(super) fn op_10010xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[5..]);
-        let value = *OPCode::get_register_by_index(index, cpu).unwrap();
+    pub(super) fn op_10010xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
+        let value = *OPCode::get_register(src, cpu);
         cpu.a = alu_helper(cpu, ALUOP::SUB, cpu.a, value, 0);
         1
     }
 
     // SUB (HL) 0b10010110
-    pub(super) fn op_10010110(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_10010110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.hl();
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         cpu.a = alu_helper(cpu, ALUOP::SUB, cpu.a, value, 0);
         2
     }
 
     // SUB n 0b11010110
-    pub(super) fn op_11010110(cpu: &mut CPU) -> u8 {
-        let value = cpu.memory_bus.read_byte(cpu.pc);
+    pub(super) fn op_11010110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
+        let value = cpu.read_cycle(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(1);
         cpu.a = alu_helper(cpu, ALUOP::SUB, cpu.a, value, 0);
         2
     }
 
     // SBC r 0b10011xxx
-    pub(super) fn op_10011xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[5..]);
-        let value = *OPCode::get_register_by_index(index, cpu).unwrap();
+    pub(super) fn op_10011xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
+        let value = *OPCode::get_register(src, cpu);
         let carry = if cpu.c() { 1 } else { 0 };
         cpu.a = alu_helper(cpu, ALUOP::SUB, cpu.a, value, carry);
         1
     }
 
     // SBC (HL) 0b10011110
-    pub(super) fn op_10011110(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_10011110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.hl();
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         let carry = if cpu.c() { 1 } else { 0 };
         cpu.a = alu_helper(cpu, ALUOP::SUB, cpu.a, value, carry);
         2
     }
 
     // SBC n 0b11011110
-    pub(super) fn op_11011110(cpu: &mut CPU) -> u8 {
-        let value = cpu.memory_bus.read_byte(cpu.pc);
+    pub(super) fn op_11011110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
+        let value = cpu.read_cycle(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(1);
         let carry = if cpu.c() { 1 } else { 0 };
         cpu.a = alu_helper(cpu, ALUOP::SUB, cpu.a, value, carry);
@@ -217,72 +213,69 @@ impl OPCode {
     // logical opcodes
 
     // AND r 0b10100xxx
-    pub(super) fn op_10100xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[5..]);
-        let value = *OPCode::get_register_by_index(index, cpu).unwrap();
+    pub(super) fn op_10100xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
+        let value = *OPCode::get_register(src, cpu);
         cpu.a = alu_helper(cpu, ALUOP::AND, cpu.a, value, 0);
         1
     }
 
     // AND (HL) 0b10100110
-    pub(super) fn op_10100110(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_10100110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.hl();
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         cpu.a = alu_helper(cpu, ALUOP::AND, cpu.a, value, 0);
         2
     }
 
     // AND n 0b11100110
-    pub(super) fn op_11100110(cpu: &mut CPU) -> u8 {
-        let value = cpu.memory_bus.read_byte(cpu.pc);
+    pub(super) fn op_11100110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
+        let value = cpu.read_cycle(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(1);
         cpu.a = alu_helper(cpu, ALUOP::AND, cpu.a, value, 0);
         2
     }
 
     // OR r  0b10110xxx
-    pub(super) fn op_10110xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[5..]);
-        let value = *OPCode::get_register_by_index(index, cpu).unwrap();
+    pub(super) fn op_10110xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
+        let value = *OPCode::get_register(src, cpu);
         cpu.a = alu_helper(cpu, ALUOP::OR, cpu.a, value, 0);
         1
     }
 
     // OR (HL) 0b10110110
-    pub(super) fn op_10110110(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_10110110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.hl();
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         cpu.a = alu_helper(cpu, ALUOP::OR, cpu.a, value, 0);
         2
     }
 
     // OR n 0b11110110
-    pub(super) fn op_11110110(cpu: &mut CPU) -> u8 {
-        let value = cpu.memory_bus.read_byte(cpu.pc);
+    pub(super) fn op_11110110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
+        let value = cpu.read_cycle(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(1);
         cpu.a = alu_helper(cpu, ALUOP::OR, cpu.a, value, 0);
         2
     }
 
     // XOR r 0b10101xxx
-    pub(super) fn op_10101xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[5..]);
-        let value = *OPCode::get_register_by_index(index, cpu).unwrap();
+    pub(super) fn op_10101xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
+        let value = *OPCode::get_register(src, cpu);
         cpu.a = alu_helper(cpu, ALUOP::XOR, cpu.a, value, 0);
         1
     }
 
     // XOR (HL) 0b10101110
-    pub(super) fn op_10101110(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_10101110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.hl();
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         cpu.a = alu_helper(cpu, ALUOP::XOR, cpu.a, value, 0);
         2
     }
 
     // XOR n 0b11101110
-    pub(super) fn op_11101110(cpu: &mut CPU) -> u8 {
-        let value = cpu.memory_bus.read_byte(cpu.pc);
+    pub(super) fn op_11101110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
+        let value = cpu.read_cycle(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(1);
         cpu.a = alu_helper(cpu, ALUOP::XOR, cpu.a, value, 0);
         2
@@ -293,75 +286,64 @@ impl OPCode {
     // other ALU opcodes
 
     // CP r 0b10111xxx
-    pub(super) fn op_10111xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[5..]);
-        let value = *OPCode::get_register_by_index(index, cpu).unwrap();
+    pub(super) fn op_10111xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
+        let value = *OPCode::get_register(src, cpu);
         alu_helper(cpu, ALUOP::SUB, cpu.a, value, 0);
         1
     }
 
     // CP (HL) 10111110
-    pub(super) fn op_10111110(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_10111110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.hl();
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         alu_helper(cpu, ALUOP::SUB, cpu.a, value, 0);
         2
     }
 
     // CP n 0b11111110
-    pub(super) fn op_11111110(cpu: &mut CPU) -> u8 {
-        let value = cpu.memory_bus.read_byte(cpu.pc);
+    pub(super) fn op_11111110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
+        let value = cpu.read_cycle(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(1);
         alu_helper(cpu, ALUOP::SUB, cpu.a, value, 0);
         2
     }
 
     // INC r 0b00xxx100
-    pub(super) fn op_00xxx100(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[2..5]);
-        // debug!("index {}", index);
-
-        let value = {
-            let register = OPCode::get_register_by_index(index, cpu).unwrap();
-            *register
-        };
+    pub(super) fn op_00xxx100<B: Bus>(cpu: &mut CPU<B>, dst: Register) -> u8 {
+        let value = *OPCode::get_register(dst, cpu);
         let result = alu_helper(cpu, ALUOP::INC, value, 1, 0);
-        *OPCode::get_register_by_index(index, cpu).unwrap() = result;
+        *OPCode::get_register(dst, cpu) = result;
         1
     }
 
     // INC (HL) 0b00110100
-    pub(super) fn op_00110100(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_00110100<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.hl();
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         let result = alu_helper(cpu, ALUOP::INC, value, 1, 0);
-        cpu.memory_bus.write_byte(address, result);
+        cpu.write_cycle(address, result);
         3
     }
 
     // DEC r 0b00xxx101
-    pub(super) fn op_00xxx101(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[2..5]);
-        let value = {
-            let register = OPCode::get_register_by_index(index, cpu).unwrap();
-            *register
-        };
+    pub(super) fn op_00xxx101<B: Bus>(cpu: &mut CPU<B>, dst: Register) -> u8 {
+        let value = *OPCode::get_register(dst, cpu);
         let result = alu_helper(cpu, ALUOP::DEC, value, 1, 0);
-        *OPCode::get_register_by_index(index, cpu).unwrap() = result;
+        *OPCode::get_register(dst, cpu) = result;
         1
     }
 
     // DEC (HL) 0b00110101
-    pub(super) fn op_00110101(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_00110101<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.hl();
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         let result = alu_helper(cpu, ALUOP::DEC, value, 1, 0);
-        cpu.memory_bus.write_byte(address, result);
+        cpu.write_cycle(address, result);
         3
     }
 
     // CCF 0b00111111
-    pub(super) fn op_00111111(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_00111111<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         cpu.set_c(!cpu.c());
         cpu.set_n(false);
         cpu.set_h(false);
@@ -369,7 +351,7 @@ impl OPCode {
     }
 
     // SCF 0b00110111
-    pub(super) fn op_00110111(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_00110111<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         cpu.set_c(true);
         cpu.set_n(false);
         cpu.set_h(false);
@@ -377,7 +359,7 @@ impl OPCode {
     }
 
     // DAA 0b00100111
-    pub(super) fn op_00100111(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_00100111<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         if cpu.n() {
             let mut adjustment = 0;
             if cpu.h() {
@@ -410,7 +392,7 @@ impl OPCode {
     }
 
     // CPL 0b00101111
-    pub(super) fn op_00101111(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_00101111<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         cpu.a = !cpu.a;
         cpu.set_n(true);
         cpu.set_h(true);
@@ -422,65 +404,37 @@ impl OPCode {
     // 16-bit ALU opcodes
 
     // INC rr 0b00xx0011
-    pub(super) fn op_00xx0011(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[2..4]);
-        let value = OPCode::get_16b_register_by_index(index, cpu);
+    pub(super) fn op_00xx0011<B: Bus>(cpu: &mut CPU<B>, pair: RegisterPair) -> u8 {
+        let value = OPCode::get_register_pair(pair, cpu);
         let result = value.wrapping_add(1);
-        OPCode::set_16b_register_by_index(index, cpu, result);
+        OPCode::set_register_pair(pair, cpu, result);
         2
     }
 
     // DEC rr 0b00xx1011
-    pub(super) fn op_00xx1011(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[2..4]);
-        let value = OPCode::get_16b_register_by_index(index, cpu);
+    pub(super) fn op_00xx1011<B: Bus>(cpu: &mut CPU<B>, pair: RegisterPair) -> u8 {
+        let value = OPCode::get_register_pair(pair, cpu);
         let result = value.wrapping_sub(1);
-        OPCode::set_16b_register_by_index(index, cpu, result);
+        OPCode::set_register_pair(pair, cpu, result);
         2
     }
 
     // ADD HL, rr 0b00xx1001
-    pub(super) fn op_00xx1001(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[2..4]);
-        let rr = OPCode::get_16b_register_by_index(index, cpu);
+    pub(super) fn op_00xx1001<B: Bus>(cpu: &mut CPU<B>, pair: RegisterPair) -> u8 {
+        let rr = OPCode::get_register_pair(pair, cpu);
         let hl = cpu.hl();
-
-        // let bits_for_num1 = OPCode::parse_bits_u16(hl);
-        // let bits_for_num2 = OPCode::parse_bits_u16(rr);
-        // let mut carry_bits = [0u8; 17];
-        // let mut result = [0u8; 16];
-        // for idx in (0..16).rev() {
-        //     let temp = bits_for_num1[idx as usize]
-        //         + bits_for_num2[idx as usize]
-        //         + carry_bits[(idx + 1) as usize];
-        //     result[idx as usize] = temp & 1;
-        //     carry_bits[idx as usize] = temp >> 1;
-        // }
-        // // set flags
-        // let sum = OPCode::concat_bits_u16(&result);
         let sum = hl.wrapping_add(rr);
         cpu.set_hl(sum);
         cpu.set_n(false);
-        // if (carry_bits[11] == 1) {
-        //     cpu.set_H(true);
-        // } else {
-        //     cpu.set_H(false);
-        // }
-
-        // if (carry_bits[15] == 1) {
-        //     cpu.set_C(true);
-        // } else {
-        //     cpu.set_C(false);
-        // }
         cpu.set_c((u32::from(hl) + u32::from(rr)) > 0xFFFF);
         cpu.set_h(((hl & 0x0FFF) + (rr & 0x0FFF)) > 0x0FFF);
         2
     }
 
     // ADD SP, e  11101000
-    pub(super) fn op_11101000(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_11101000<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let sp = cpu.sp;
-        let ee = cpu.memory_bus.read_byte(cpu.pc);
+        let ee = cpu.read_cycle(cpu.pc);
         let ee_i8 = ee as i8;
         cpu.pc = cpu.pc.wrapping_add(1);
 
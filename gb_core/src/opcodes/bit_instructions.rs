@@ -1,11 +1,12 @@
-use crate::core::CPU;
+use crate::core::{Bus, CPU};
+use crate::opcodes::decoder::Register;
 use crate::opcodes::opcode::OPCode;
 
 impl OPCode {
     // bit instructions, all bit instructions start with 0xcb
 
     // RLCA 00000111
-    pub(super) fn cb_op_00000111(cpu: &mut CPU) -> u8 {
+    pub(super) fn cb_op_00000111<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let value = cpu.a;
         let bit7 = value >> 7;
         cpu.a = (value << 1) | bit7;
@@ -17,7 +18,7 @@ impl OPCode {
     }
 
     // RRCA 00001111
-    pub(super) fn cb_op_00001111(cpu: &mut CPU) -> u8 {
+    pub(super) fn cb_op_00001111<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let value = cpu.a;
         let bit0 = value & 1;
         cpu.a = (value >> 1) | (bit0 << 7);
@@ -29,7 +30,7 @@ impl OPCode {
     }
 
     // RLA 00010111
-    pub(super) fn cb_op_00010111(cpu: &mut CPU) -> u8 {
+    pub(super) fn cb_op_00010111<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let value = cpu.a;
         let cflag = {
             if cpu.c() {
@@ -48,7 +49,7 @@ impl OPCode {
     }
 
     // RRA 00011111
-    pub(super) fn cb_op_00011111(cpu: &mut CPU) -> u8 {
+    pub(super) fn cb_op_00011111<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let value = cpu.a;
         let cflag = {
             if cpu.c() {
@@ -69,10 +70,9 @@ impl OPCode {
     // RLC is the first 0xcb bit operation
 
     // RLC r 00000xxx
-    pub(super) fn cb_op_00000xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[5..]);
-        let r = OPCode::get_register_by_index(index, cpu).unwrap();
-        let value = r.clone();
+    pub(super) fn cb_op_00000xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
+        let r = OPCode::get_register(src, cpu);
+        let value = *r;
         let bit7 = value >> 7;
         *r = (value << 1) | bit7;
         cpu.set_n(false);
@@ -83,12 +83,12 @@ impl OPCode {
     }
 
     // RLC (HL) 00000110
-    pub(super) fn cb_op_00000110(cpu: &mut CPU) -> u8 {
+    pub(super) fn cb_op_00000110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let hl = cpu.hl();
-        let value = cpu.memory_bus.read_byte(hl);
+        let value = cpu.read_cycle(hl);
         let bit7 = value >> 7;
         let result = (value << 1) | bit7;
-        cpu.memory_bus.write_byte(hl, result);
+        cpu.write_cycle(hl, result);
         cpu.set_n(false);
         cpu.set_h(false);
         cpu.set_c(bit7 == 1);
@@ -97,10 +97,9 @@ impl OPCode {
     }
 
     // RRC r 00001xxx
-    pub(super) fn cb_op_00001xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[5..]);
-        let r = OPCode::get_register_by_index(index, cpu).unwrap();
-        let value = r.clone();
+    pub(super) fn cb_op_00001xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
+        let r = OPCode::get_register(src, cpu);
+        let value = *r;
         let bit0 = value & 1;
         *r = (value >> 1) | (bit0 << 7);
         cpu.set_n(false);
@@ -111,12 +110,12 @@ impl OPCode {
     }
 
     // RRC (HL) 00001110
-    pub(super) fn cb_op_00001110(cpu: &mut CPU) -> u8 {
+    pub(super) fn cb_op_00001110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let hl = cpu.hl();
-        let value = cpu.memory_bus.read_byte(hl);
+        let value = cpu.read_cycle(hl);
         let bit0 = value & 1;
         let result = (value >> 1) | (bit0 << 7);
-        cpu.memory_bus.write_byte(hl, result);
+        cpu.write_cycle(hl, result);
         cpu.set_n(false);
         cpu.set_h(false);
         cpu.set_c(bit0 == 1);
@@ -125,7 +124,7 @@ impl OPCode {
     }
 
     // RL r 00010xxx
-    pub(super) fn cb_op_00010xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
+    pub(super) fn cb_op_00010xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
         let cflag = {
             if cpu.c() {
                 1u8
@@ -133,11 +132,8 @@ impl OPCode {
                 0u8
             }
         };
-        let r = {
-            let index = OPCode::concat_bits(&bits[5..]);
-            OPCode::get_register_by_index(index, cpu).unwrap()
-        };
-        let value = r.clone();
+        let r = OPCode::get_register(src, cpu);
+        let value = *r;
         let bit7 = value >> 7;
         let res = {
             *r = (value << 1) | cflag;
@@ -151,10 +147,10 @@ impl OPCode {
     }
 
     // RL (HL) 00010110
-    pub(super) fn cb_op_00010110(cpu: &mut CPU) -> u8 {
+    pub(super) fn cb_op_00010110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let value = {
             let hl = cpu.hl();
-            cpu.memory_bus.read_byte(hl)
+            cpu.read_cycle(hl)
         };
         let cflag = {
             if cpu.c() {
@@ -167,7 +163,7 @@ impl OPCode {
         let res = {
             let hl = cpu.hl();
             let result = (value << 1) | cflag;
-            cpu.memory_bus.write_byte(hl, result);
+            cpu.write_cycle(hl, result);
             result
         };
         cpu.set_n(false);
@@ -178,7 +174,7 @@ impl OPCode {
     }
 
     // RR r8 00011xxx
-    pub(super) fn cb_op_00011xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
+    pub(super) fn cb_op_00011xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
         let cflag = {
             if cpu.c() {
                 1u8
@@ -186,11 +182,8 @@ impl OPCode {
                 0u8
             }
         };
-        let r = {
-            let index = OPCode::concat_bits(&bits[5..]);
-            OPCode::get_register_by_index(index, cpu).unwrap()
-        };
-        let value = r.clone();
+        let r = OPCode::get_register(src, cpu);
+        let value = *r;
         let bit0 = value & 1;
 
         let result = {
@@ -205,10 +198,10 @@ impl OPCode {
     }
 
     // RR (HL) 00011110
-    pub(super) fn cb_op_00011110(cpu: &mut CPU) -> u8 {
+    pub(super) fn cb_op_00011110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let value = {
             let hl = cpu.hl();
-            cpu.memory_bus.read_byte(hl)
+            cpu.read_cycle(hl)
         };
         let cflag = {
             if cpu.c() {
@@ -221,7 +214,7 @@ impl OPCode {
         let res = {
             let hl = cpu.hl();
             let result = (value >> 1) | (cflag << 7);
-            cpu.memory_bus.write_byte(hl, result);
+            cpu.write_cycle(hl, result);
             result
         };
         cpu.set_n(false);
@@ -232,14 +225,11 @@ impl OPCode {
     }
 
     // SLA r8 00100xxx
-    pub(super) fn cb_op_00100xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let r = {
-            let index = OPCode::concat_bits(&bits[5..]);
-            OPCode::get_register_by_index(index, cpu).unwrap()
-        };
-        let bit7 = r.clone() >> 7;
+    pub(super) fn cb_op_00100xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
+        let r = OPCode::get_register(src, cpu);
+        let bit7 = *r >> 7;
         let res = {
-            *r = *r << 1;
+            *r <<= 1;
             *r
         };
         cpu.set_n(false);
@@ -250,16 +240,16 @@ impl OPCode {
     }
 
     // SLA (HL) 00100110
-    pub(super) fn cb_op_00100110(cpu: &mut CPU) -> u8 {
+    pub(super) fn cb_op_00100110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let value = {
             let hl = cpu.hl();
-            cpu.memory_bus.read_byte(hl)
+            cpu.read_cycle(hl)
         };
         let bit7 = value >> 7;
         let res = {
             let hl = cpu.hl();
             let result = value << 1;
-            cpu.memory_bus.write_byte(hl, result);
+            cpu.write_cycle(hl, result);
             result
         };
         cpu.set_n(false);
@@ -270,13 +260,10 @@ impl OPCode {
     }
 
     // SRA r 00101xxx
-    pub(super) fn cb_op_00101xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let r = {
-            let index = OPCode::concat_bits(&bits[5..]);
-            OPCode::get_register_by_index(index, cpu).unwrap()
-        };
+    pub(super) fn cb_op_00101xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
+        let r = OPCode::get_register(src, cpu);
         let (bit7, bit0) = {
-            let value = r.clone();
+            let value = *r;
             (value >> 7, value & 1)
         };
         let result = {
@@ -291,19 +278,16 @@ impl OPCode {
     }
 
     // SRA (HL) 00101110
-    pub(super) fn cb_op_00101110(cpu: &mut CPU) -> u8 {
+    pub(super) fn cb_op_00101110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let value = {
             let hl = cpu.hl();
-            cpu.memory_bus.read_byte(hl)
-        };
-        let (bit7, bit0) = {
-            let value = value.clone();
-            (value >> 7, value & 1)
+            cpu.read_cycle(hl)
         };
+        let (bit7, bit0) = { (value >> 7, value & 1) };
         let result = {
             let hl = cpu.hl();
             let result = (value >> 1) | (bit7 << 7);
-            cpu.memory_bus.write_byte(hl, result);
+            cpu.write_cycle(hl, result);
             result
         };
         cpu.set_n(false);
@@ -314,11 +298,8 @@ impl OPCode {
     }
 
     // SWAP r 00110xxx
-    pub(super) fn cb_op_00110xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let r = {
-            let index = OPCode::concat_bits(&bits[5..]);
-            OPCode::get_register_by_index(index, cpu).unwrap()
-        };
+    pub(super) fn cb_op_00110xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
+        let r = OPCode::get_register(src, cpu);
         let result = {
             *r = ((*r & 0xF0) >> 4) | ((*r & 0x0F) << 4);
             *r
@@ -331,15 +312,15 @@ impl OPCode {
     }
 
     // SWAP (HL) 00110110
-    pub(super) fn cb_op_00110110(cpu: &mut CPU) -> u8 {
+    pub(super) fn cb_op_00110110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let value = {
             let hl = cpu.hl();
-            cpu.memory_bus.read_byte(hl)
+            cpu.read_cycle(hl)
         };
         let result = {
             let hl = cpu.hl();
             let result = ((value & 0xF0) >> 4) | ((value & 0x0F) << 4);
-            cpu.memory_bus.write_byte(hl, result);
+            cpu.write_cycle(hl, result);
             result
         };
         cpu.set_n(false);
@@ -350,14 +331,11 @@ impl OPCode {
     }
 
     // SRL r 00111xxx
-    pub(super) fn cb_op_00111xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let r = {
-            let index = OPCode::concat_bits(&bits[5..]);
-            OPCode::get_register_by_index(index, cpu).unwrap()
-        };
-        let bit0 = r.clone() & 1;
+    pub(super) fn cb_op_00111xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
+        let r = OPCode::get_register(src, cpu);
+        let bit0 = *r & 1;
         let result = {
-            *r = *r >> 1;
+            *r >>= 1;
             *r
         };
         cpu.set_n(false);
@@ -368,16 +346,16 @@ impl OPCode {
     }
 
     // SRL (HL) 00111110
-    pub(super) fn cb_op_00111110(cpu: &mut CPU) -> u8 {
+    pub(super) fn cb_op_00111110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let value = {
             let hl = cpu.hl();
-            cpu.memory_bus.read_byte(hl)
+            cpu.read_cycle(hl)
         };
         let bit0 = value & 1;
         let result = {
             let hl = cpu.hl();
             let result = value >> 1;
-            cpu.memory_bus.write_byte(hl, result);
+            cpu.write_cycle(hl, result);
             result
         };
         cpu.set_n(false);
@@ -388,15 +366,10 @@ impl OPCode {
     }
 
     // BIT b, r 01xxxxxx
-    pub(super) fn cb_op_01xxxxxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let (bindex, rvalue) = {
-            let bit_index = OPCode::concat_bits(&bits[2..5]);
-            let register_index = OPCode::concat_bits(&bits[5..]);
-            let r = OPCode::get_register_by_index(register_index, cpu).unwrap();
-            (bit_index, r.clone())
-        };
+    pub(super) fn cb_op_01xxxxxx<B: Bus>(cpu: &mut CPU<B>, bit: u8, src: Register) -> u8 {
+        let value = *OPCode::get_register(src, cpu);
         // if selected bit is 0, set flag Z
-        let bit_is_zero = (rvalue & (1 << bindex)) == 0;
+        let bit_is_zero = (value & (1 << bit)) == 0;
         cpu.set_z(bit_is_zero);
         cpu.set_n(false);
         cpu.set_h(true);
@@ -404,13 +377,9 @@ impl OPCode {
     }
 
     // BIT b, (HL) 01xxx110
-    pub(super) fn cb_op_01xxx110(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let (bindex, value) = {
-            let bit_index = OPCode::concat_bits(&bits[2..5]);
-            let hl = cpu.hl();
-            (bit_index, cpu.memory_bus.read_byte(hl))
-        };
-        let bit_is_zero = (value & (1 << bindex)) == 0;
+    pub(super) fn cb_op_01xxx110<B: Bus>(cpu: &mut CPU<B>, bit: u8) -> u8 {
+        let value = cpu.read_cycle(cpu.hl());
+        let bit_is_zero = (value & (1 << bit)) == 0;
         cpu.set_z(bit_is_zero);
         cpu.set_n(false);
         cpu.set_h(true);
@@ -418,50 +387,30 @@ impl OPCode {
     }
 
     // RES b, r 10xxxxxx
-    pub(super) fn cb_op_10xxxxxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let (bindex, r) = {
-            let bit_index = OPCode::concat_bits(&bits[2..5]);
-            let register_index = OPCode::concat_bits(&bits[5..]);
-            let r = OPCode::get_register_by_index(register_index, cpu).unwrap();
-            (bit_index, r)
-        };
-        *r = *r & !(1 << bindex);
+    pub(super) fn cb_op_10xxxxxx<B: Bus>(cpu: &mut CPU<B>, bit: u8, src: Register) -> u8 {
+        *OPCode::get_register(src, cpu) &= !(1 << bit);
         2
     }
 
     // RES b, (HL) 10xxx110
-    pub(super) fn cb_op_10xxx110(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let (bindex, value) = {
-            let bit_index = OPCode::concat_bits(&bits[2..5]);
-            let hl = cpu.hl();
-            (bit_index, cpu.memory_bus.read_byte(hl))
-        };
-        let result = value & !(1 << bindex);
-        cpu.memory_bus.write_byte(cpu.hl(), result);
+    pub(super) fn cb_op_10xxx110<B: Bus>(cpu: &mut CPU<B>, bit: u8) -> u8 {
+        let value = cpu.read_cycle(cpu.hl());
+        let result = value & !(1 << bit);
+        cpu.write_cycle(cpu.hl(), result);
         4
     }
 
     //SET b, r 11xxxxxx
-    pub(super) fn cb_op_11xxxxxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let (bindex, r) = {
-            let bit_index = OPCode::concat_bits(&bits[2..5]);
-            let register_index = OPCode::concat_bits(&bits[5..]);
-            let r = OPCode::get_register_by_index(register_index, cpu).unwrap();
-            (bit_index, r)
-        };
-        *r = *r | (1 << bindex);
+    pub(super) fn cb_op_11xxxxxx<B: Bus>(cpu: &mut CPU<B>, bit: u8, src: Register) -> u8 {
+        *OPCode::get_register(src, cpu) |= 1 << bit;
         2
     }
 
     //SET b,(HL) 11xxx110
-    pub(super) fn cb_op_11xxx110(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let (bindex, value) = {
-            let bit_index = OPCode::concat_bits(&bits[2..5]);
-            let hl = cpu.hl();
-            (bit_index, cpu.memory_bus.read_byte(hl))
-        };
-        let result = value | (1 << bindex);
-        cpu.memory_bus.write_byte(cpu.hl(), result);
+    pub(super) fn cb_op_11xxx110<B: Bus>(cpu: &mut CPU<B>, bit: u8) -> u8 {
+        let value = cpu.read_cycle(cpu.hl());
+        let result = value | (1 << bit);
+        cpu.write_cycle(cpu.hl(), result);
         4
     }
 }
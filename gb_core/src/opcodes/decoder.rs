@@ -0,0 +1,466 @@
+/*
+Decoder:
+Every opcode and CB opcode is decoded once at compile time into a table of 256 + 256 entries.
+An entry holds the operation, which OPCode::exec maps to its handler, and the operands the handlers take,
+the registers, register pair, condition, bit and RST vector encoded in the opcode.
+
+The opcode is split into its octal fields, each opcode decodes to exactly one operation:
+bit 7-6: x
+bit 5-3: y (bit 5-4: p, bit 3: q)
+bit 2-0: z
+register index 6 is (HL), in the (HL) forms the register operands are replaced by memory.
+Every field is decoded for every opcode, only the ones of its operation are meaningful.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterPair {
+    Bc,
+    De,
+    Hl,
+    Sp,
+    Af,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Nz,
+    Z,
+    Nc,
+    C,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    // --- 8-bit loads ---
+    LdRR,
+    LdRN,
+    LdRHl,
+    LdHlR,
+    LdHlN,
+    LdABc,
+    LdADe,
+    LdBcA,
+    LdDeA,
+    LdANn,
+    LdNnA,
+    LdhAC,
+    LdhCA,
+    LdhAN,
+    LdhNA,
+    LdAHlDec,
+    LdHlDecA,
+    LdAHlInc,
+    LdHlIncA,
+    // --- 16-bit loads ---
+    LdRrNn,
+    LdNnSp,
+    LdSpHl,
+    Push,
+    Pop,
+    LdHlSpE,
+    // --- 8-bit ALU ---
+    AddR,
+    AddHl,
+    AddN,
+    AdcR,
+    AdcHl,
+    AdcN,
+    SubR,
+    SubHl,
+    SubN,
+    SbcR,
+    SbcHl,
+    SbcN,
+    AndR,
+    AndHl,
+    AndN,
+    XorR,
+    XorHl,
+    XorN,
+    OrR,
+    OrHl,
+    OrN,
+    CpR,
+    CpHl,
+    CpN,
+    IncR,
+    IncHl,
+    DecR,
+    DecHl,
+    Ccf,
+    Scf,
+    Daa,
+    Cpl,
+    // --- 16-bit ALU ---
+    AddSpE,
+    IncRr,
+    DecRr,
+    AddHlRr,
+    // --- single byte rotations ---
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    // --- control flow ---
+    JpNn,
+    JpHl,
+    JpCcNn,
+    JrE,
+    JrCcE,
+    CallNn,
+    CallCcNn,
+    Ret,
+    RetCc,
+    Reti,
+    Rst,
+    // --- miscellaneous ---
+    Halt,
+    Stop,
+    Di,
+    Ei,
+    Nop,
+    // LD B, B, the software breakpoint of test roms
+    LdBB,
+    // --- CB prefixed ---
+    RlcR,
+    RlcHl,
+    RrcR,
+    RrcHl,
+    RlR,
+    RlHl,
+    RrR,
+    RrHl,
+    SlaR,
+    SlaHl,
+    SraR,
+    SraHl,
+    SwapR,
+    SwapHl,
+    SrlR,
+    SrlHl,
+    BitR,
+    BitHl,
+    ResR,
+    ResHl,
+    SetR,
+    SetHl,
+    // the CB prefix itself and the 11 unused opcodes
+    Invalid,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Decoded {
+    pub operation: Operation,
+    // register of bits 5-3, the destination of LD, INC and DEC, None is (HL)
+    pub dst: Option<Register>,
+    // register of bits 2-0, the source of LD and the operand of the ALU and CB operations, None is (HL)
+    pub src: Option<Register>,
+    // register pair of bits 5-4, AF instead of SP for PUSH and POP
+    pub pair: RegisterPair,
+    // condition of bits 4-3
+    pub condition: Condition,
+    // bit 0-7 of BIT, RES and SET
+    pub bit: u8,
+    // RST target
+    pub vector: u16,
+}
+
+// opcodes at 0x000 - 0x0FF, CB opcodes at 0x100 - 0x1FF
+pub static DECODE_TABLE: [Decoded; 512] = build_table();
+
+// decoded opcode, CB opcodes when is_cb
+pub fn decode_opcode(opcode: u8, is_cb: bool) -> &'static Decoded {
+    &DECODE_TABLE[((is_cb as usize) << 8) | opcode as usize]
+}
+
+const fn build_table() -> [Decoded; 512] {
+    let mut table = [Decoded {
+        operation: Operation::Invalid,
+        dst: None,
+        src: None,
+        pair: RegisterPair::Bc,
+        condition: Condition::Nz,
+        bit: 0,
+        vector: 0,
+    }; 512];
+    let mut i = 0;
+    while i < 512 {
+        let opcode = (i & 0xFF) as u8;
+        let operation = if i >= 256 {
+            decode_cb(opcode)
+        } else {
+            decode(opcode)
+        };
+        let y = (opcode >> 3) & 0b111;
+        let last_pair = match operation {
+            Operation::Push | Operation::Pop => RegisterPair::Af,
+            _ => RegisterPair::Sp,
+        };
+        table[i] = Decoded {
+            operation,
+            dst: register(y),
+            src: register(opcode & 0b111),
+            pair: register_pair(y >> 1, last_pair),
+            condition: condition(y),
+            bit: y,
+            vector: y as u16 * 8,
+        };
+        i += 1;
+    }
+    table
+}
+
+// register of index 0-7, 6 is (HL)
+const fn register(index: u8) -> Option<Register> {
+    match index {
+        0 => Some(Register::B),
+        1 => Some(Register::C),
+        2 => Some(Register::D),
+        3 => Some(Register::E),
+        4 => Some(Register::H),
+        5 => Some(Register::L),
+        6 => None,
+        _ => Some(Register::A),
+    }
+}
+
+// register pair of index 0-3, SP or AF as the last one
+const fn register_pair(index: u8, last: RegisterPair) -> RegisterPair {
+    match index {
+        0 => RegisterPair::Bc,
+        1 => RegisterPair::De,
+        2 => RegisterPair::Hl,
+        _ => last,
+    }
+}
+
+// JR cc has the condition at y 4-7, the others at y 0-3
+const fn condition(y: u8) -> Condition {
+    match y & 0b11 {
+        0 => Condition::Nz,
+        1 => Condition::Z,
+        2 => Condition::Nc,
+        _ => Condition::C,
+    }
+}
+
+// the (HL) form when the operand is memory, the register form otherwise
+const fn pick(memory: bool, hl: Operation, r: Operation) -> Operation {
+    if memory {
+        hl
+    } else {
+        r
+    }
+}
+
+const fn decode(opcode: u8) -> Operation {
+    use Operation::*;
+    let x = opcode >> 6;
+    let y = (opcode >> 3) & 0b111;
+    let z = opcode & 0b111;
+    let p = y >> 1;
+    let q = y & 1;
+    match x {
+        0 => match z {
+            0 => match y {
+                0 => Nop,
+                1 => LdNnSp,
+                2 => Stop,
+                3 => JrE,
+                _ => JrCcE,
+            },
+            1 => match q {
+                0 => LdRrNn,
+                _ => AddHlRr,
+            },
+            2 => match y {
+                0 => LdBcA,
+                1 => LdABc,
+                2 => LdDeA,
+                3 => LdADe,
+                4 => LdHlIncA,
+                5 => LdAHlInc,
+                6 => LdHlDecA,
+                _ => LdAHlDec,
+            },
+            3 => match q {
+                0 => IncRr,
+                _ => DecRr,
+            },
+            4 => match y {
+                6 => IncHl,
+                _ => IncR,
+            },
+            5 => match y {
+                6 => DecHl,
+                _ => DecR,
+            },
+            6 => match y {
+                6 => LdHlN,
+                _ => LdRN,
+            },
+            _ => match y {
+                0 => Rlca,
+                1 => Rrca,
+                2 => Rla,
+                3 => Rra,
+                4 => Daa,
+                5 => Cpl,
+                6 => Scf,
+                _ => Ccf,
+            },
+        },
+        1 => match (y, z) {
+            (6, 6) => Halt,
+            (0, 0) => LdBB,
+            (_, 6) => LdRHl,
+            (6, _) => LdHlR,
+            _ => LdRR,
+        },
+        2 => {
+            let memory = z == 6;
+            match y {
+                0 => pick(memory, AddHl, AddR),
+                1 => pick(memory, AdcHl, AdcR),
+                2 => pick(memory, SubHl, SubR),
+                3 => pick(memory, SbcHl, SbcR),
+                4 => pick(memory, AndHl, AndR),
+                5 => pick(memory, XorHl, XorR),
+                6 => pick(memory, OrHl, OrR),
+                _ => pick(memory, CpHl, CpR),
+            }
+        }
+        _ => match z {
+            0 => match y {
+                0..=3 => RetCc,
+                4 => LdhNA,
+                5 => AddSpE,
+                6 => LdhAN,
+                _ => LdHlSpE,
+            },
+            1 => match (q, p) {
+                (0, _) => Pop,
+                (_, 0) => Ret,
+                (_, 1) => Reti,
+                (_, 2) => JpHl,
+                _ => LdSpHl,
+            },
+            2 => match y {
+                0..=3 => JpCcNn,
+                4 => LdhCA,
+                5 => LdNnA,
+                6 => LdhAC,
+                _ => LdANn,
+            },
+            3 => match y {
+                0 => JpNn,
+                6 => Di,
+                7 => Ei,
+                _ => Invalid,
+            },
+            4 => match y {
+                0..=3 => CallCcNn,
+                _ => Invalid,
+            },
+            5 => match (q, p) {
+                (0, _) => Push,
+                (_, 0) => CallNn,
+                _ => Invalid,
+            },
+            6 => match y {
+                0 => AddN,
+                1 => AdcN,
+                2 => SubN,
+                3 => SbcN,
+                4 => AndN,
+                5 => XorN,
+                6 => OrN,
+                _ => CpN,
+            },
+            _ => Rst,
+        },
+    }
+}
+
+const fn decode_cb(opcode: u8) -> Operation {
+    use Operation::*;
+    let x = opcode >> 6;
+    let y = (opcode >> 3) & 0b111;
+    let memory = opcode & 0b111 == 6;
+    match x {
+        0 => match y {
+            0 => pick(memory, RlcHl, RlcR),
+            1 => pick(memory, RrcHl, RrcR),
+            2 => pick(memory, RlHl, RlR),
+            3 => pick(memory, RrHl, RrR),
+            4 => pick(memory, SlaHl, SlaR),
+            5 => pick(memory, SraHl, SraR),
+            6 => pick(memory, SwapHl, SwapR),
+            _ => pick(memory, SrlHl, SrlR),
+        },
+        1 => pick(memory, BitHl, BitR),
+        2 => pick(memory, ResHl, ResR),
+        _ => pick(memory, SetHl, SetR),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_table() {
+        // the (HL) forms are not caught by the register forms
+        assert_eq!(decode_opcode(0xBE, false).operation, Operation::CpHl);
+        assert_eq!(decode_opcode(0x9E, false).operation, Operation::SbcHl);
+        assert_eq!(decode_opcode(0x76, false).operation, Operation::Halt);
+        assert_eq!(decode_opcode(0x70, false).operation, Operation::LdHlR);
+        assert_eq!(decode_opcode(0x7E, false).operation, Operation::LdRHl);
+        assert_eq!(decode_opcode(0x36, false).operation, Operation::LdHlN);
+        assert_eq!(decode_opcode(0x34, false).operation, Operation::IncHl);
+        assert_eq!(decode_opcode(0xF8, false).operation, Operation::LdHlSpE);
+        assert_eq!(decode_opcode(0xE8, false).operation, Operation::AddSpE);
+        assert_eq!(decode_opcode(0xF5, false).operation, Operation::Push);
+        assert_eq!(decode_opcode(0x46, true).operation, Operation::BitHl);
+        assert_eq!(decode_opcode(0x47, true).operation, Operation::BitR);
+
+        // the operands
+        let decoded = decode_opcode(0x53, false);
+        assert_eq!(
+            (decoded.dst, decoded.src),
+            (Some(Register::D), Some(Register::E))
+        );
+        assert_eq!(decode_opcode(0x70, false).dst, None);
+        assert_eq!(decode_opcode(0xC5, false).pair, RegisterPair::Bc);
+        assert_eq!(decode_opcode(0xF5, false).pair, RegisterPair::Af);
+        assert_eq!(decode_opcode(0x31, false).pair, RegisterPair::Sp);
+        assert_eq!(decode_opcode(0x38, false).condition, Condition::C);
+        assert_eq!(decode_opcode(0xC2, false).condition, Condition::Nz);
+        assert_eq!(decode_opcode(0xEF, false).vector, 0x28);
+        let decoded = decode_opcode(0x7C, true);
+        assert_eq!((decoded.bit, decoded.src), (7, Some(Register::H)));
+
+        // only the CB prefix and the unused opcodes are invalid
+        let invalid: Vec<u8> = (0..=255u8)
+            .filter(|opcode| decode_opcode(*opcode, false).operation == Operation::Invalid)
+            .collect();
+        assert_eq!(
+            invalid,
+            vec![0xCB, 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD]
+        );
+        assert!(
+            (0..=255u8).all(|opcode| decode_opcode(opcode, true).operation != Operation::Invalid)
+        );
+    }
+}
@@ -0,0 +1,489 @@
+/*
+Instruction:
+A decoded instruction with its operands, length and cycle counts, for debuggers, tracing and disassembly.
+The operation comes from the same decode table OPCode::exec dispatches on.
+
+Display prints RGBDS syntax: `ld a, [hl+]`, `jp nz, $0150`, `ldh [$ff40], a`.
+Relative jumps print their destination, the unused opcodes print as `db $d3`.
+Cycles are machine cycles, cycles_taken is the count when the condition of a branch holds.
+*/
+
+use crate::core::Bus;
+use crate::opcodes::decoder::{
+    decode_opcode, Condition, Decoded, Operation, Register, RegisterPair,
+};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(Register),
+    RegisterPair(RegisterPair),
+    // [bc], [de], [hl]
+    Indirect(RegisterPair),
+    // [hl+], [hl-]
+    HlIncrement,
+    HlDecrement,
+    // [c], 0xFF00 + c
+    HighC,
+    Immediate8(u8),
+    Immediate16(u16),
+    // [n16]
+    Address(u16),
+    // [n8], 0xFF00 + n8
+    HighAddress(u8),
+    // e8 of add sp, e8
+    Offset(i8),
+    // sp+e8 of ld hl, sp+e8
+    SpOffset(i8),
+    // destination of a relative jump
+    Target(u16),
+    Condition(Condition),
+    Bit(u8),
+    Vector(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: u16,
+    pub prefixed: bool,
+    // the opcode after the CB prefix when prefixed
+    pub opcode: u8,
+    pub operation: Operation,
+    pub operands: [Option<Operand>; 2],
+    // bytes including the CB prefix
+    pub length: u8,
+    pub cycles: u8,
+    pub cycles_taken: u8,
+}
+
+// decode the instruction at pc, the bus is only peeked
+pub fn decode<B: Bus>(bus: &B, pc: u16) -> Instruction {
+    let mut opcode = bus.peek_byte(pc);
+    let prefixed = opcode == 0xCB;
+    if prefixed {
+        opcode = bus.peek_byte(pc.wrapping_add(1));
+    }
+    let decoded = decode_opcode(opcode, prefixed);
+    let operation = decoded.operation;
+    let length = length(operation);
+    let n8 = bus.peek_byte(pc.wrapping_add(1));
+    let n16 = u16::from_le_bytes([n8, bus.peek_byte(pc.wrapping_add(2))]);
+    let (cycles, cycles_taken) = cycles(operation);
+
+    Instruction {
+        address: pc,
+        prefixed,
+        opcode,
+        operation,
+        operands: operands(decoded, n8, n16, pc.wrapping_add(length as u16)),
+        length,
+        cycles,
+        cycles_taken,
+    }
+}
+
+impl Instruction {
+    pub fn is_conditional(&self) -> bool {
+        self.cycles != self.cycles_taken
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        use Operation::*;
+        match self.operation {
+            LdRR | LdRN | LdRHl | LdHlR | LdHlN | LdABc | LdADe | LdBcA | LdDeA | LdANn | LdNnA
+            | LdAHlDec | LdHlDecA | LdAHlInc | LdHlIncA | LdRrNn | LdNnSp | LdSpHl | LdHlSpE
+            | LdBB => "ld",
+            LdhAC | LdhCA | LdhAN | LdhNA => "ldh",
+            Push => "push",
+            Pop => "pop",
+            AddR | AddHl | AddN | AddSpE | AddHlRr => "add",
+            AdcR | AdcHl | AdcN => "adc",
+            SubR | SubHl | SubN => "sub",
+            SbcR | SbcHl | SbcN => "sbc",
+            AndR | AndHl | AndN => "and",
+            XorR | XorHl | XorN => "xor",
+            OrR | OrHl | OrN => "or",
+            CpR | CpHl | CpN => "cp",
+            IncR | IncHl | IncRr => "inc",
+            DecR | DecHl | DecRr => "dec",
+            Ccf => "ccf",
+            Scf => "scf",
+            Daa => "daa",
+            Cpl => "cpl",
+            Rlca => "rlca",
+            Rrca => "rrca",
+            Rla => "rla",
+            Rra => "rra",
+            JpNn | JpHl | JpCcNn => "jp",
+            JrE | JrCcE => "jr",
+            CallNn | CallCcNn => "call",
+            Ret | RetCc => "ret",
+            Reti => "reti",
+            Rst => "rst",
+            Halt => "halt",
+            Stop => "stop",
+            Di => "di",
+            Ei => "ei",
+            Nop => "nop",
+            RlcR | RlcHl => "rlc",
+            RrcR | RrcHl => "rrc",
+            RlR | RlHl => "rl",
+            RrR | RrHl => "rr",
+            SlaR | SlaHl => "sla",
+            SraR | SraHl => "sra",
+            SwapR | SwapHl => "swap",
+            SrlR | SrlHl => "srl",
+            BitR | BitHl => "bit",
+            ResR | ResHl => "res",
+            SetR | SetHl => "set",
+            Invalid => "db",
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.operation == Operation::Invalid {
+            return write!(f, "db ${:02x}", self.opcode);
+        }
+        write!(f, "{}", self.mnemonic())?;
+        for (i, operand) in self.operands.iter().flatten().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, operand)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(register) => write!(f, "{}", register),
+            Operand::RegisterPair(pair) => write!(f, "{}", pair),
+            Operand::Indirect(pair) => write!(f, "[{}]", pair),
+            Operand::HlIncrement => write!(f, "[hl+]"),
+            Operand::HlDecrement => write!(f, "[hl-]"),
+            Operand::HighC => write!(f, "[c]"),
+            Operand::Immediate8(value) => write!(f, "${:02x}", value),
+            Operand::Immediate16(value) => write!(f, "${:04x}", value),
+            Operand::Address(address) => write!(f, "[${:04x}]", address),
+            Operand::HighAddress(offset) => write!(f, "[${:04x}]", 0xFF00 | *offset as u16),
+            Operand::Offset(offset) => write!(f, "{}", offset),
+            Operand::SpOffset(offset) => write!(f, "sp{:+}", offset),
+            Operand::Target(address) => write!(f, "${:04x}", address),
+            Operand::Condition(condition) => write!(f, "{}", condition),
+            Operand::Bit(bit) => write!(f, "{}", bit),
+            Operand::Vector(vector) => write!(f, "${:02x}", vector),
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Register::A => "a",
+            Register::B => "b",
+            Register::C => "c",
+            Register::D => "d",
+            Register::E => "e",
+            Register::H => "h",
+            Register::L => "l",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for RegisterPair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RegisterPair::Bc => "bc",
+            RegisterPair::De => "de",
+            RegisterPair::Hl => "hl",
+            RegisterPair::Sp => "sp",
+            RegisterPair::Af => "af",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Condition::Nz => "nz",
+            Condition::Z => "z",
+            Condition::Nc => "nc",
+            Condition::C => "c",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// register operand, None is [hl]
+fn r8(register: Option<Register>) -> Operand {
+    match register {
+        Some(register) => Operand::Register(register),
+        None => Operand::Indirect(RegisterPair::Hl),
+    }
+}
+
+// operands in RGBDS order, next_pc is the address after the instruction
+fn operands(decoded: &Decoded, n8: u8, n16: u16, next_pc: u16) -> [Option<Operand>; 2] {
+    use Operation::*;
+    let a = Operand::Register(Register::A);
+    let hl = Operand::RegisterPair(RegisterPair::Hl);
+    let sp = Operand::RegisterPair(RegisterPair::Sp);
+    let (dst, src) = (r8(decoded.dst), r8(decoded.src));
+    let pair = Operand::RegisterPair(decoded.pair);
+    let condition = Operand::Condition(decoded.condition);
+    let target = Operand::Target(next_pc.wrapping_add(n8 as i8 as u16));
+    let (first, second) = match decoded.operation {
+        LdRR | LdRHl | LdHlR | LdBB => (Some(dst), Some(src)),
+        LdRN | LdHlN => (Some(dst), Some(Operand::Immediate8(n8))),
+        LdABc => (Some(a), Some(Operand::Indirect(RegisterPair::Bc))),
+        LdADe => (Some(a), Some(Operand::Indirect(RegisterPair::De))),
+        LdBcA => (Some(Operand::Indirect(RegisterPair::Bc)), Some(a)),
+        LdDeA => (Some(Operand::Indirect(RegisterPair::De)), Some(a)),
+        LdANn => (Some(a), Some(Operand::Address(n16))),
+        LdNnA => (Some(Operand::Address(n16)), Some(a)),
+        LdhAC => (Some(a), Some(Operand::HighC)),
+        LdhCA => (Some(Operand::HighC), Some(a)),
+        LdhAN => (Some(a), Some(Operand::HighAddress(n8))),
+        LdhNA => (Some(Operand::HighAddress(n8)), Some(a)),
+        LdAHlDec => (Some(a), Some(Operand::HlDecrement)),
+        LdHlDecA => (Some(Operand::HlDecrement), Some(a)),
+        LdAHlInc => (Some(a), Some(Operand::HlIncrement)),
+        LdHlIncA => (Some(Operand::HlIncrement), Some(a)),
+        LdRrNn => (Some(pair), Some(Operand::Immediate16(n16))),
+        LdNnSp => (Some(Operand::Address(n16)), Some(sp)),
+        LdSpHl => (Some(sp), Some(hl)),
+        Push | Pop => (Some(pair), None),
+        LdHlSpE => (Some(hl), Some(Operand::SpOffset(n8 as i8))),
+        AddR | AddHl | AdcR | AdcHl | SbcR | SbcHl => (Some(a), Some(src)),
+        AddN | AdcN | SbcN => (Some(a), Some(Operand::Immediate8(n8))),
+        // rgbds writes sub, and, xor, or and cp without the a
+        SubR | SubHl | AndR | AndHl | XorR | XorHl | OrR | OrHl | CpR | CpHl => (Some(src), None),
+        SubN | AndN | XorN | OrN | CpN => (Some(Operand::Immediate8(n8)), None),
+        IncR | IncHl | DecR | DecHl => (Some(dst), None),
+        AddSpE => (Some(sp), Some(Operand::Offset(n8 as i8))),
+        IncRr | DecRr => (Some(pair), None),
+        AddHlRr => (Some(hl), Some(pair)),
+        JpNn | CallNn => (Some(Operand::Immediate16(n16)), None),
+        JpHl => (Some(hl), None),
+        JpCcNn | CallCcNn => (Some(condition), Some(Operand::Immediate16(n16))),
+        JrE => (Some(target), None),
+        JrCcE => (Some(condition), Some(target)),
+        RetCc => (Some(condition), None),
+        Rst => (Some(Operand::Vector(decoded.vector as u8)), None),
+        RlcR | RlcHl | RrcR | RrcHl | RlR | RlHl | RrR | RrHl | SlaR | SlaHl | SraR | SraHl
+        | SwapR | SwapHl | SrlR | SrlHl => (Some(src), None),
+        BitR | BitHl | ResR | ResHl | SetR | SetHl => (Some(Operand::Bit(decoded.bit)), Some(src)),
+        Ccf | Scf | Daa | Cpl | Rlca | Rrca | Rla | Rra | Ret | Reti | Halt | Stop | Di | Ei
+        | Nop | Invalid => (None, None),
+    };
+    [first, second]
+}
+
+// bytes of the instruction, stop takes a second byte like the CB prefixed ones
+fn length(operation: Operation) -> u8 {
+    use Operation::*;
+    match operation {
+        LdRrNn | LdNnSp | LdANn | LdNnA | JpNn | JpCcNn | CallNn | CallCcNn => 3,
+        LdRN | LdHlN | LdhAN | LdhNA | AddN | AdcN | SubN | SbcN | AndN | XorN | OrN | CpN
+        | AddSpE | LdHlSpE | JrE | JrCcE | Stop => 2,
+        RlcR | RlcHl | RrcR | RrcHl | RlR | RlHl | RrR | RrHl | SlaR | SlaHl | SraR | SraHl
+        | SwapR | SwapHl | SrlR | SrlHl | BitR | BitHl | ResR | ResHl | SetR | SetHl => 2,
+        _ => 1,
+    }
+}
+
+// machine cycles when not taken and when taken
+fn cycles(operation: Operation) -> (u8, u8) {
+    use Operation::*;
+    match operation {
+        JpCcNn => (3, 4),
+        JrCcE => (2, 3),
+        CallCcNn => (3, 6),
+        RetCc => (2, 5),
+        CallNn => (6, 6),
+        LdNnSp => (5, 5),
+        LdANn | LdNnA | Push | AddSpE | JpNn | Ret | Reti | Rst | RlcHl | RrcHl | RlHl | RrHl
+        | SlaHl | SraHl | SwapHl | SrlHl | ResHl | SetHl => (4, 4),
+        LdHlN | LdhAN | LdhNA | LdRrNn | Pop | LdHlSpE | IncHl | DecHl | JrE | BitHl => (3, 3),
+        LdRN | LdRHl | LdHlR | LdABc | LdADe | LdBcA | LdDeA | LdhAC | LdhCA | LdAHlDec
+        | LdHlDecA | LdAHlInc | LdHlIncA | LdSpHl | AddHl | AddN | AdcHl | AdcN | SubHl | SubN
+        | SbcHl | SbcN | AndHl | AndN | XorHl | XorN | OrHl | OrN | CpHl | CpN | IncRr | DecRr
+        | AddHlRr | RlcR | RrcR | RlR | RrR | SlaR | SraR | SwapR | SrlR | BitR | ResR | SetR => {
+            (2, 2)
+        }
+        _ => (1, 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{FlatBus, MemoryBus, CPU};
+    use crate::opcodes::OPCode;
+
+    fn bus_with(program: &[u8]) -> FlatBus {
+        let mut bus = FlatBus::new();
+        for (i, byte) in program.iter().enumerate() {
+            bus.write_byte(0xC000 + i as u16, *byte);
+        }
+        bus
+    }
+
+    fn disassemble(program: &[u8]) -> String {
+        decode(&bus_with(program), 0xC000).to_string()
+    }
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(disassemble(&[0x2A]), "ld a, [hl+]");
+        assert_eq!(disassemble(&[0x32]), "ld [hl-], a");
+        assert_eq!(disassemble(&[0x7E]), "ld a, [hl]");
+        assert_eq!(disassemble(&[0x40]), "ld b, b");
+        assert_eq!(disassemble(&[0x36, 0x12]), "ld [hl], $12");
+        assert_eq!(disassemble(&[0x01, 0x34, 0x12]), "ld bc, $1234");
+        assert_eq!(disassemble(&[0x08, 0x00, 0xC0]), "ld [$c000], sp");
+        assert_eq!(disassemble(&[0xE0, 0x40]), "ldh [$ff40], a");
+        assert_eq!(disassemble(&[0xF2]), "ldh a, [c]");
+        assert_eq!(disassemble(&[0xF8, 0xFE]), "ld hl, sp-2");
+        assert_eq!(disassemble(&[0xE8, 0x05]), "add sp, 5");
+        assert_eq!(disassemble(&[0xF5]), "push af");
+        assert_eq!(disassemble(&[0x09]), "add hl, bc");
+        assert_eq!(disassemble(&[0x90]), "sub b");
+        assert_eq!(disassemble(&[0x8E]), "adc a, [hl]");
+        assert_eq!(disassemble(&[0xFE, 0x10]), "cp $10");
+        assert_eq!(disassemble(&[0xC2, 0x50, 0x01]), "jp nz, $0150");
+        assert_eq!(disassemble(&[0x18, 0xFE]), "jr $c000");
+        assert_eq!(disassemble(&[0x38, 0x10]), "jr c, $c012");
+        assert_eq!(disassemble(&[0xFF]), "rst $38");
+        assert_eq!(disassemble(&[0xCB, 0x7C]), "bit 7, h");
+        assert_eq!(disassemble(&[0xCB, 0x86]), "res 0, [hl]");
+        assert_eq!(disassemble(&[0xCB, 0x37]), "swap a");
+        assert_eq!(disassemble(&[0x10, 0x00]), "stop");
+        assert_eq!(disassemble(&[0xD3]), "db $d3");
+    }
+
+    #[test]
+    fn test_instruction_fields() {
+        let instruction = decode(&bus_with(&[0xC4, 0x00, 0x02]), 0xC000);
+        assert_eq!(instruction.operation, Operation::CallCcNn);
+        assert_eq!(instruction.length, 3);
+        assert_eq!((instruction.cycles, instruction.cycles_taken), (3, 6));
+        assert!(instruction.is_conditional());
+        assert_eq!(
+            instruction.operands,
+            [
+                Some(Operand::Condition(Condition::Nz)),
+                Some(Operand::Immediate16(0x0200))
+            ]
+        );
+
+        let instruction = decode(&bus_with(&[0xCB, 0x46]), 0xC000);
+        assert!(instruction.prefixed);
+        assert_eq!(instruction.opcode, 0x46);
+        assert_eq!(instruction.length, 2);
+        assert_eq!(instruction.cycles, 3);
+        assert!(!instruction.is_conditional());
+    }
+
+    #[test]
+    fn test_decode_has_no_side_effects() {
+        // no cartridge, every read of 0x0000 - 0x7FFF fails
+        let mut bus = MemoryBus::new();
+        assert_eq!(decode(&bus, 0x0150).to_string(), "rst $38");
+        assert!(bus.take_fault().is_none());
+    }
+
+    #[test]
+    fn test_length_matches_exec() {
+        // every instruction that does not jump moves pc by its length, branches are not taken
+        for prefixed in [false, true] {
+            for opcode in 0..=255u8 {
+                if !prefixed && opcode == 0xCB {
+                    continue;
+                }
+                let program = if prefixed {
+                    vec![0xCB, opcode]
+                } else {
+                    vec![opcode, 0x00, 0xD0]
+                };
+                let instruction = decode(&bus_with(&program), 0xC000);
+                let flags = match instruction.operands[0] {
+                    Some(Operand::Condition(Condition::Nz | Condition::Nc)) => 0xF0,
+                    _ => 0x00,
+                };
+                if matches!(
+                    instruction.operation,
+                    Operation::Invalid
+                        | Operation::JpNn
+                        | Operation::JpHl
+                        | Operation::JrE
+                        | Operation::CallNn
+                        | Operation::Ret
+                        | Operation::Reti
+                        | Operation::Rst
+                ) {
+                    continue;
+                }
+                let mut cpu = CPU::with_bus(bus_with(&program));
+                cpu.pc = 0xC000;
+                cpu.sp = 0xDFF0;
+                cpu.f = flags;
+                cpu.tick().unwrap();
+                assert_eq!(
+                    cpu.pc.wrapping_sub(0xC000),
+                    instruction.length as u16,
+                    "{} ({:02x})",
+                    instruction,
+                    opcode
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_cycles_match_exec() {
+        // every opcode executed on a flat bus takes the decoded cycles, both branch outcomes
+        for prefixed in [false, true] {
+            for opcode in 0..=255u8 {
+                if !prefixed && opcode == 0xCB {
+                    continue;
+                }
+                let instruction = if prefixed {
+                    decode(&bus_with(&[0xCB, opcode]), 0xC000)
+                } else {
+                    decode(&bus_with(&[opcode, 0x00, 0xD0]), 0xC000)
+                };
+                if matches!(
+                    instruction.operation,
+                    Operation::Invalid | Operation::Halt | Operation::Stop
+                ) {
+                    continue;
+                }
+                for flags in [0x00, 0xF0] {
+                    let mut cpu = CPU::with_bus(FlatBus::new());
+                    cpu.pc = 0xC000 + 1 + prefixed as u16;
+                    cpu.sp = 0xDFF0;
+                    cpu.f = flags;
+                    let taken = match instruction.operands[0] {
+                        Some(Operand::Condition(Condition::Nz)) => !cpu.z(),
+                        Some(Operand::Condition(Condition::Z)) => cpu.z(),
+                        Some(Operand::Condition(Condition::Nc)) => !cpu.c(),
+                        Some(Operand::Condition(Condition::C)) => cpu.c(),
+                        _ => false,
+                    };
+                    let expected = if taken {
+                        instruction.cycles_taken
+                    } else {
+                        instruction.cycles
+                    };
+                    let cycles = OPCode::exec(&mut cpu, opcode, prefixed);
+                    assert_eq!(cycles, expected, "{} ({:02x})", instruction, opcode);
+                }
+            }
+        }
+    }
+}
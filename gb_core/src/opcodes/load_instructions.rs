@@ -1,147 +1,144 @@
-use crate::core::CPU;
+use crate::core::{Bus, CPU};
+use crate::opcodes::decoder::{Register, RegisterPair};
 use crate::opcodes::opcode::OPCode;
 
 impl OPCode {
     // 8 bit loads
 
     // LD r8, r8 0b01xxxyyy
-    pub(super) fn op_01xxxyyy(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        // release the mutable borrow of cpu
-        let src_value = {
-            let src = OPCode::get_register_by_index(OPCode::concat_bits(&bits[5..8]), cpu).unwrap();
-            *src
-        };
-
-        let dst = OPCode::get_register_by_index(OPCode::concat_bits(&bits[2..5]), cpu).unwrap();
-        *dst = src_value;
+    pub(super) fn op_01xxxyyy<B: Bus>(cpu: &mut CPU<B>, dst: Register, src: Register) -> u8 {
+        let src_value = *OPCode::get_register(src, cpu);
+        *OPCode::get_register(dst, cpu) = src_value;
+        1
+    }
+
+    // LD B, B 0b01000000
+    pub(super) fn op_01000000<B: Bus>(cpu: &mut CPU<B>) -> u8 {
+        // does nothing, but test roms use it as a breakpoint
+        cpu.breakpoint = true;
         1
     }
 
     // LD r8, n8 0b00xxx110
-    pub(super) fn op_00xxx110(cpu: &mut CPU, bits: &[u8]) -> u8 {
+    pub(super) fn op_00xxx110<B: Bus>(cpu: &mut CPU<B>, dst: Register) -> u8 {
         // load immediate from PC
-        let immediate = cpu.memory_bus.read_byte(cpu.pc);
+        let immediate = cpu.read_cycle(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(1);
-        let dst = OPCode::get_register_by_index(OPCode::concat_bits(&bits[2..5]), cpu).unwrap();
-        *dst = immediate;
+        *OPCode::get_register(dst, cpu) = immediate;
         2
     }
 
     // LD r8, (HL) 0b01xxx110
-    pub(super) fn op_01xxx110(cpu: &mut CPU, bits: &[u8]) -> u8 {
+    pub(super) fn op_01xxx110<B: Bus>(cpu: &mut CPU<B>, dst: Register) -> u8 {
         let address = cpu.hl();
-        let value = cpu.memory_bus.read_byte(address);
-        let dst = OPCode::get_register_by_index(OPCode::concat_bits(&bits[2..5]), cpu).unwrap();
-        *dst = value;
+        let value = cpu.read_cycle(address);
+        *OPCode::get_register(dst, cpu) = value;
         2
     }
 
     // LD (HL), r8 0b01110xxx
-    pub(super) fn op_01110xxx(cpu: &mut CPU, bits: &[u8]) -> u8 {
+    pub(super) fn op_01110xxx<B: Bus>(cpu: &mut CPU<B>, src: Register) -> u8 {
         let address = cpu.hl();
-        let src_val = {
-            let src = OPCode::get_register_by_index(OPCode::concat_bits(&bits[5..8]), cpu).unwrap();
-            *src
-        };
-        cpu.memory_bus.write_byte(address, src_val);
+        let src_val = *OPCode::get_register(src, cpu);
+        cpu.write_cycle(address, src_val);
         2
     }
 
     // LD (HL), n8 0b00110110
-    pub(super) fn op_00110110(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_00110110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.hl();
-        let immediate = cpu.memory_bus.read_byte(cpu.pc);
+        let immediate = cpu.read_cycle(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(1);
-        cpu.memory_bus.write_byte(address, immediate);
+        cpu.write_cycle(address, immediate);
         3
     }
 
     // LD A, (BC) 0b00001010
-    pub(super) fn op_00001010(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_00001010<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.bc();
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         cpu.a = value;
         2
     }
 
     // LD A, (DE) 0b00011010
-    pub(super) fn op_00011010(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_00011010<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.de();
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         cpu.a = value;
         2
     }
 
     // LD (BC), A 0b00000010
-    pub(super) fn op_00000010(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_00000010<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.bc();
-        cpu.memory_bus.write_byte(address, cpu.a);
+        cpu.write_cycle(address, cpu.a);
         2
     }
 
     // LD (DE), A 0b00010010
-    pub(super) fn op_00010010(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_00010010<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.de();
-        cpu.memory_bus.write_byte(address, cpu.a);
+        cpu.write_cycle(address, cpu.a);
         2
     }
 
     // LD A, (nn) 0b11111010
-    pub(super) fn op_11111010(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_11111010<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         // load address from PC
-        let address = cpu.memory_bus.read_word(cpu.pc);
+        let address = cpu.read_word_cycles(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(2);
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         cpu.a = value;
         4
     }
 
     // LD (nn), A 0b11101010
-    pub(super) fn op_11101010(cpu: &mut CPU) -> u8 {
-        let address = cpu.memory_bus.read_word(cpu.pc);
+    pub(super) fn op_11101010<B: Bus>(cpu: &mut CPU<B>) -> u8 {
+        let address = cpu.read_word_cycles(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(2);
-        cpu.memory_bus.write_byte(address, cpu.a);
+        cpu.write_cycle(address, cpu.a);
         4
     }
 
     // LDH A, (C) 0b11110010
-    pub(super) fn op_11110010(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_11110010<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = 0xFF00 + cpu.c as u16;
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         cpu.a = value;
         2
     }
 
     // LDH (C), A 0b11100010
-    pub(super) fn op_11100010(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_11100010<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = 0xFF00 + cpu.c as u16;
-        cpu.memory_bus.write_byte(address, cpu.a);
+        cpu.write_cycle(address, cpu.a);
         2
     }
 
     // LDH A, (n) 0b11110000
-    pub(super) fn op_11110000(cpu: &mut CPU) -> u8 {
-        let address = cpu.memory_bus.read_byte(cpu.pc);
+    pub(super) fn op_11110000<B: Bus>(cpu: &mut CPU<B>) -> u8 {
+        let address = cpu.read_cycle(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(1);
         let address = 0xFF00 + address as u16;
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         cpu.a = value;
         3
     }
 
     // LDH (n), A 0b11100000
-    pub(super) fn op_11100000(cpu: &mut CPU) -> u8 {
-        let address = cpu.memory_bus.read_byte(cpu.pc);
+    pub(super) fn op_11100000<B: Bus>(cpu: &mut CPU<B>) -> u8 {
+        let address = cpu.read_cycle(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(1);
         let address = 0xFF00 + address as u16;
-        cpu.memory_bus.write_byte(address, cpu.a);
+        cpu.write_cycle(address, cpu.a);
         3
     }
 
     // LD A, (HL-) 0b00111010
-    pub(super) fn op_00111010(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_00111010<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.hl();
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         // decrement HL
         cpu.set_hl(address.wrapping_sub(1));
         cpu.a = value;
@@ -149,19 +146,19 @@ impl OPCode {
     }
 
     // LD (HL-), A 0b00110010
-    pub(super) fn op_00110010(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_00110010<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.hl();
         let value = cpu.a;
         // decrement HL
         cpu.set_hl(address.wrapping_sub(1));
-        cpu.memory_bus.write_byte(address, value);
+        cpu.write_cycle(address, value);
         2
     }
 
     // LD A, (HL+) 0b00101010
-    pub(super) fn op_00101010(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_00101010<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.hl();
-        let value = cpu.memory_bus.read_byte(address);
+        let value = cpu.read_cycle(address);
         // increment HL
         cpu.set_hl(address.wrapping_add(1));
         cpu.a = value;
@@ -169,12 +166,12 @@ impl OPCode {
     }
 
     // LD (HL+), A 0b00100010
-    pub(super) fn op_00100010(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_00100010<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         let address = cpu.hl();
         let value = cpu.a;
         // increment HL
         cpu.set_hl(address.wrapping_add(1));
-        cpu.memory_bus.write_byte(address, value);
+        cpu.write_cycle(address, value);
         2
     }
 }
@@ -183,54 +180,50 @@ impl OPCode {
     // 16 bit loads
 
     // LD rr, nn    0b00xx0001
-    pub(super) fn op_00xx0001(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[2..4]);
-        let value = cpu.memory_bus.read_word(cpu.pc);
+    pub(super) fn op_00xx0001<B: Bus>(cpu: &mut CPU<B>, pair: RegisterPair) -> u8 {
+        let value = cpu.read_word_cycles(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(2);
-        OPCode::set_16b_register_by_index(index, cpu, value);
+        OPCode::set_register_pair(pair, cpu, value);
         3
     }
 
     // LD (nn), SP    0b00001000
-    pub(super) fn op_00001000(cpu: &mut CPU) -> u8 {
-        let address = cpu.memory_bus.read_word(cpu.pc);
+    pub(super) fn op_00001000<B: Bus>(cpu: &mut CPU<B>) -> u8 {
+        let address = cpu.read_word_cycles(cpu.pc);
         cpu.pc = cpu.pc.wrapping_add(2);
-        cpu.memory_bus.write_word(address, cpu.sp);
+        cpu.write_word_cycles(address, cpu.sp);
         5
     }
 
     // LD SP, HL    0b11111001
-    pub(super) fn op_11111001(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_11111001<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         cpu.sp = cpu.hl();
         2
     }
 
     // PUSH rr: Push to stack  0b11xx0101
-    pub(super) fn op_11xx0101(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[2..4]);
-        let value = OPCode::get_16b_register_by_index(index, cpu);
-        cpu.sp = cpu.sp.wrapping_sub(2);
-        cpu.memory_bus.write_word(cpu.sp, value);
+    pub(super) fn op_11xx0101<B: Bus>(cpu: &mut CPU<B>, pair: RegisterPair) -> u8 {
+        let value = OPCode::get_register_pair(pair, cpu);
+        cpu.internal_cycle();
+        cpu.push_word(value);
         4
     }
 
     // POP rr 0b11xx0001
-    pub(super) fn op_11xx0001(cpu: &mut CPU, bits: &[u8]) -> u8 {
-        let index = OPCode::concat_bits(&bits[2..4]);
-        let value = cpu.memory_bus.read_word(cpu.sp);
-        cpu.sp = cpu.sp.wrapping_add(2);
-        OPCode::set_16b_register_by_index(index, cpu, value);
+    pub(super) fn op_11xx0001<B: Bus>(cpu: &mut CPU<B>, pair: RegisterPair) -> u8 {
+        let value = cpu.pop_word();
+        OPCode::set_register_pair(pair, cpu, value);
         3
     }
 
     // LD HL, SP+e: Load HL from adjusted stack pointer, 0b11111000, set flag
-    pub(super) fn op_11111000(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_11111000<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         // read immediate from PC
-        let e = cpu.memory_bus.read_byte(cpu.pc) as i8;
+        let e = cpu.read_cycle(cpu.pc) as i8;
         cpu.pc = cpu.pc.wrapping_add(1);
-        // compute SP+e and set flags
-        let half_carry = ((cpu.sp & 0x0F) as u8).wrapping_add((e & 0x0F) as u8) > 0x0F;
-        let carry = ((cpu.sp & 0xFF) as u16).wrapping_add(e as u16) > 0xFF;
+        // the flags come from the unsigned addition of the low byte
+        let half_carry = (cpu.sp & 0x0F) + (e as u8 as u16 & 0x0F) > 0x0F;
+        let carry = (cpu.sp & 0xFF) + e as u8 as u16 > 0xFF;
         cpu.set_z(false);
         cpu.set_n(false);
         cpu.set_h(half_carry);
@@ -1,48 +1,64 @@
-use crate::core::CPU;
+use crate::core::{Bus, CPU};
+use crate::io_registers::speed::SPEED_SWITCH_CYCLES;
+use crate::io_registers::timer::DIV;
 use crate::opcodes::opcode::OPCode;
 
 impl OPCode {
     // Miscellaneous instructions
 
     // HALT 01110110
-    pub(super) fn op_01110110(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_01110110<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         // check ime, ie, if
         if cpu.ime() {
-            // if ime enabled
+            // if ime enabled, the interrupt handler is called once an interrupt is pending
             cpu.is_halted = true;
         } else {
             // ime disabled
             // check if any interrupts pending
-            if (cpu.ie() == 0) && (cpu.r#if() == 0) {
+            if cpu.memory_bus.pending_interrupts() == 0 {
                 // no interrupts pending, As soon as an interrupt becomes pending, the CPU resumes execution. This is like the above, except that the handler is not called.
                 cpu.is_halted = true;
+            } else if cpu.ime_scheduled {
+                // EI right before HALT, the interrupt is serviced after HALT and returns to the HALT,
+                // which is executed again
+                cpu.pc = cpu.pc.wrapping_sub(1);
             } else {
-                // do nothing, next byte read twice in real machine due to hardware bug
+                // halt bug, the cpu does not halt and the next byte is read twice.
+                // a RST after HALT pushes its own address and runs again after the handler returns
+                cpu.halt_bug = true;
             }
         }
-        0
+        1
     }
 
     // STOP 00010000 00000000
-    pub(super) fn op_00010000_00000000(cpu: &mut CPU) -> u8 {
-        // TODO: implement STOP instruction
-
-        // STOP can trigger frequency change in GBC
-
-        // in GB, STOP enters deeper sleep state, and waken up by joypad.
+    pub(super) fn op_00010000_00000000<B: Bus>(cpu: &mut CPU<B>) -> u8 {
+        // the second byte is skipped, whatever its value
+        cpu.pc = cpu.pc.wrapping_add(1);
+        // STOP resets DIV
+        cpu.memory_bus.write_byte(DIV, 0);
 
-        0
+        if cpu.memory_bus.speed_switch() {
+            // STOP can trigger frequency change in GBC, the cpu stalls until the clock settles
+            cpu.speed_switch_stall = SPEED_SWITCH_CYCLES;
+        } else {
+            // in GB, STOP enters deeper sleep state, and waken up by joypad.
+            cpu.is_stopped = true;
+        }
+        1
     }
 
     //DI 11110011
-    pub(super) fn op_11110011(cpu: &mut CPU) -> u8 {
+    pub(super) fn op_11110011<B: Bus>(cpu: &mut CPU<B>) -> u8 {
         cpu.set_ime(false);
+        cpu.ime_scheduled = false;
         1
     }
 
     // EI 11111011
-    pub(super) fn op_11111011(cpu: &mut CPU) -> u8 {
-        cpu.set_ime(true);
+    pub(super) fn op_11111011<B: Bus>(cpu: &mut CPU<B>) -> u8 {
+        // IME is set after the next instruction
+        cpu.schedule_ime();
         1
     }
 
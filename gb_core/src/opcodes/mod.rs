@@ -1,8 +1,12 @@
 mod alu_instructions;
 mod bit_instructions;
 mod control_flow_instructions;
+pub mod decoder;
+pub mod instruction;
 mod load_instructions;
 mod miscellaneous_instructions;
 pub mod opcode;
 
+pub use decoder::{Condition, Operation, Register, RegisterPair};
+pub use instruction::{decode, Instruction, Operand};
 pub use opcode::OPCode;
@@ -1,467 +1,293 @@
 use crate::core::Error;
-use crate::core::CPU;
+use crate::core::{Bus, CPU};
+use crate::opcodes::decoder::{decode_opcode, Condition, Operation, Register, RegisterPair};
 
 pub struct OPCode;
 
 impl OPCode {
-    pub fn get_register_by_index<'a>(index: u8, cpu: &'a mut CPU) -> Result<&'a mut u8, Error> {
-        match index {
-            0b111 => Ok(&mut cpu.a),
-            0b000 => Ok(&mut cpu.b),
-            0b001 => Ok(&mut cpu.c),
-            0b010 => Ok(&mut cpu.d),
-            0b011 => Ok(&mut cpu.e),
-            0b100 => Ok(&mut cpu.h),
-            0b101 => Ok(&mut cpu.l),
-            _ => Err(Error::OPCodeParseError),
+    pub fn get_register<B: Bus>(register: Register, cpu: &mut CPU<B>) -> &mut u8 {
+        match register {
+            Register::A => &mut cpu.a,
+            Register::B => &mut cpu.b,
+            Register::C => &mut cpu.c,
+            Register::D => &mut cpu.d,
+            Register::E => &mut cpu.e,
+            Register::H => &mut cpu.h,
+            Register::L => &mut cpu.l,
         }
     }
 
-    pub fn set_16b_register_by_index(index: u8, cpu: &mut CPU, value: u16) {
-        match index {
-            0b00 => cpu.set_bc(value),
-            0b01 => cpu.set_de(value),
-            0b10 => cpu.set_hl(value),
-            0b11 => cpu.set_sp(value),
-            _ => panic!("Invalid 16-bit register index"),
+    pub fn get_register_pair<B: Bus>(pair: RegisterPair, cpu: &CPU<B>) -> u16 {
+        match pair {
+            RegisterPair::Bc => cpu.bc(),
+            RegisterPair::De => cpu.de(),
+            RegisterPair::Hl => cpu.hl(),
+            RegisterPair::Sp => cpu.sp,
+            RegisterPair::Af => (cpu.a as u16) << 8 | cpu.f as u16,
         }
     }
 
-    pub fn get_16b_register_by_index(index: u8, cpu: &CPU) -> u16 {
-        match index {
-            0b00 => cpu.bc(),
-            0b01 => cpu.de(),
-            0b10 => cpu.hl(),
-            0b11 => cpu.sp,
-            _ => panic!("Invalid 16-bit register index"),
+    pub fn set_register_pair<B: Bus>(pair: RegisterPair, cpu: &mut CPU<B>, value: u16) {
+        match pair {
+            RegisterPair::Bc => cpu.set_bc(value),
+            RegisterPair::De => cpu.set_de(value),
+            RegisterPair::Hl => cpu.set_hl(value),
+            RegisterPair::Sp => cpu.set_sp(value),
+            // the lower 4 bits of F are always 0
+            RegisterPair::Af => {
+                cpu.a = (value >> 8) as u8;
+                cpu.f = value as u8 & 0xF0;
+            }
         }
     }
 
-    pub fn fetch_opcode_u8(cpu: &mut CPU) -> Result<u8, Error> {
+    pub fn condition_met<B: Bus>(condition: Condition, cpu: &CPU<B>) -> bool {
+        match condition {
+            Condition::Nz => !cpu.z(),
+            Condition::Z => cpu.z(),
+            Condition::Nc => !cpu.c(),
+            Condition::C => cpu.c(),
+        }
+    }
+
+    pub fn fetch_opcode_u8<B: Bus>(cpu: &mut CPU<B>) -> Result<u8, Error> {
         // TODO: Implement address boundary check
-        let opcode = cpu.memory_bus.read_byte(cpu.pc);
+        let opcode = cpu.read_cycle(cpu.pc);
         cpu.pc += 1;
         Ok(opcode)
     }
 
-    pub fn fetch_opcode_u16(cpu: &mut CPU) -> Result<u16, Error> {
+    pub fn fetch_opcode_u16<B: Bus>(cpu: &mut CPU<B>) -> Result<u16, Error> {
         // TODO: Implement address boundary check
-        let opcode = cpu.memory_bus.read_word(cpu.pc);
+        let opcode = cpu.read_word_cycles(cpu.pc);
         cpu.pc += 2;
         Ok(opcode)
     }
 
-    pub fn parse_bits_u8(opcode: u8) -> [u8; 8] {
-        let mut opcode_bits = [0; 8];
-        for i in 0..8 {
-            opcode_bits[i] = (opcode >> (7 - i)) & 1;
-        }
-        opcode_bits
-    }
-
-    pub fn parse_bits_u16(opcode: u16) -> [u8; 16] {
-        let mut opcode_bits = [0u8; 16];
-        for i in 0..16 {
-            opcode_bits[i] = ((opcode >> (15 - i)) & 1) as u8;
-        }
-        opcode_bits
-    }
-
-    pub fn concat_bits(bits: &[u8]) -> u8 {
-        let mut result = 0;
-        let bit_len = bits.len();
-        for i in 0..bit_len {
-            result |= bits[i] << (bit_len - i - 1);
-        }
-        result
-    }
-
-    pub fn concat_bits_u16(bits: &[u8]) -> u16 {
-        let mut result = 0u16;
-        let bit_len = bits.len();
-        for i in 0..bit_len {
-            result |= (bits[i] << (bit_len - i - 1)) as u16;
-        }
-        result
-    }
-
-    // execute the opcode
-    pub fn exec(cpu: &mut CPU, opcode: u8, is_cb: bool) -> u8 {
-        let opcode_bits = OPCode::parse_bits_u8(opcode);
-        if !is_cb {
-            match opcode_bits {
-                // TODO: move specified opcodes first
-
-                // --- UnReachable Opcodes ---
-
-                // CP (HL) 0b10011110
-                [1, 0, 1, 1, 1, 1, 1, 0] => OPCode::op_10111110(cpu),
-
-                // CP n 0b11111110
-                [1, 1, 1, 1, 1, 1, 1, 0] => OPCode::op_11111110(cpu),
-
-                // SBC (HL) 0b10011110
-                [1, 0, 0, 1, 1, 1, 1, 0] => OPCode::op_10011110(cpu),
-
-                // SBC n 0b11011110
-                [1, 1, 0, 1, 1, 1, 1, 0] => OPCode::op_11011110(cpu),
-
-                // HALT 01110110
-                [0, 1, 1, 1, 0, 1, 1, 0] => OPCode::op_01110110(cpu),
-
-                // --- 8-bit loads ---
-
-                // LD (HL), n8: 0b00110110 (0x36)
-                [0, 0, 1, 1, 0, 1, 1, 0] => OPCode::op_00110110(cpu),
-
-                // LD r8, n8: 0b00xxx110
-                [0, 0, _, _, _, 1, 1, 0] => OPCode::op_00xxx110(cpu, &opcode_bits),
-
-                // LD r8, (HL): 0b01xxx110
-                [0, 1, _, _, _, 1, 1, 0] => OPCode::op_01xxx110(cpu, &opcode_bits),
-
-                // LD (HL), r8: 0b01110xxx
-                [0, 1, 1, 1, 0, _, _, _] => OPCode::op_01110xxx(cpu, &opcode_bits),
-
-                // LD r8, r8: 0b01xxxyyy
-                [0, 1, _, _, _, _, _, _] => OPCode::op_01xxxyyy(cpu, &opcode_bits),
-
-                // LD A, (BC): 0b00001010 (0x0A)
-                [0, 0, 0, 0, 1, 0, 1, 0] => OPCode::op_00001010(cpu),
-
-                // LD A, (DE): 0b00011010 (0x1A)
-                [0, 0, 0, 1, 1, 0, 1, 0] => OPCode::op_00011010(cpu),
-
-                // LD (BC), A: 0b00000010 (0x02)
-                [0, 0, 0, 0, 0, 0, 1, 0] => OPCode::op_00000010(cpu),
-
-                // LD (DE), A: 0b00010010 (0x12)
-                [0, 0, 0, 1, 0, 0, 1, 0] => OPCode::op_00010010(cpu),
-
-                // LD A, (nn): 0b11111010 (0xFA)
-                [1, 1, 1, 1, 1, 0, 1, 0] => OPCode::op_11111010(cpu),
-
-                // LD (nn), A: 0b11101010 (0xEA)
-                [1, 1, 1, 0, 1, 0, 1, 0] => OPCode::op_11101010(cpu),
-
-                // LDH A, (C): 0b11110010 (0xF2) (LD A, ($FF00+C))
-                [1, 1, 1, 1, 0, 0, 1, 0] => OPCode::op_11110010(cpu),
-
-                // LDH (C), A: 0b11100010 (0xE2) (LD ($FF00+C), A)
-                [1, 1, 1, 0, 0, 0, 1, 0] => OPCode::op_11100010(cpu),
-
-                // LDH A, (n): 0b11110000 (0xF0) (LD A, ($FF00+n))
-                [1, 1, 1, 1, 0, 0, 0, 0] => OPCode::op_11110000(cpu),
-
-                // LDH (n), A: 0b11100000 (0xE0) (LD ($FF00+n), A)
-                [1, 1, 1, 0, 0, 0, 0, 0] => OPCode::op_11100000(cpu),
-
-                // LD A, (HL-): 0b00111010 (0x3A)
-                [0, 0, 1, 1, 1, 0, 1, 0] => OPCode::op_00111010(cpu),
-
-                // LD (HL-), A: 0b00110010 (0x32)
-                [0, 0, 1, 1, 0, 0, 1, 0] => OPCode::op_00110010(cpu),
-
-                // LD A, (HL+): 0b00101010 (0x2A)
-                [0, 0, 1, 0, 1, 0, 1, 0] => OPCode::op_00101010(cpu),
-
-                // LD (HL+), A: 0b00100010 (0x22)
-                [0, 0, 1, 0, 0, 0, 1, 0] => OPCode::op_00100010(cpu),
-
-                // --- 16-bit loads ---
-
-                // LD rr, nn: 0b00xx0001
-                [0, 0, _, _, 0, 0, 0, 1] => OPCode::op_00xx0001(cpu, &opcode_bits),
-
-                // LD (nn), SP: 0b00001000 (0x08)
-                [0, 0, 0, 0, 1, 0, 0, 0] => OPCode::op_00001000(cpu),
-
-                // LD SP, HL: 0b11111001 (0xF9)
-                [1, 1, 1, 1, 1, 0, 0, 1] => OPCode::op_11111001(cpu),
-
-                // PUSH rr: 0b11xx0101
-                [1, 1, _, _, 0, 1, 0, 1] => OPCode::op_11xx0101(cpu, &opcode_bits),
-
-                // POP rr: 0b11xx0001
-                [1, 1, _, _, 0, 0, 0, 1] => OPCode::op_11xx0001(cpu, &opcode_bits),
-
-                // LD HL, SP+e: 0b11111000 (0xF8) (LDHL SP, e)
-                [1, 1, 1, 1, 1, 0, 0, 0] => OPCode::op_11111000(cpu),
-
-                // --- add ---
-
-                // ADD (HL) 0b10000110
-                [1, 0, 0, 0, 0, 1, 1, 0] => OPCode::op_10000110(cpu),
-
-                // ADD n: 0b11000110
-                [1, 1, 0, 0, 0, 1, 1, 0] => OPCode::op_11000110(cpu),
-
-                // ADC (HL) 0b10001110
-                [1, 0, 0, 0, 1, 1, 1, 0] => OPCode::op_10001110(cpu),
-
-                // ADC n 0b11001110
-                [1, 1, 0, 0, 1, 1, 1, 0] => OPCode::op_11001110(cpu),
-
-                //ADD r: 0b10000xxx
-                [1, 0, 0, 0, 0, _, _, _] => OPCode::op_10000xxx(cpu, &opcode_bits),
-
-                // ADC r 0b10001xxx
-                [1, 0, 0, 0, 1, _, _, _] => OPCode::op_10001xxx(cpu, &opcode_bits),
-
-                // --- sub ---
-
-                // SUB (HL) 0b10010110
-                [1, 0, 0, 1, 0, 1, 1, 0] => OPCode::op_10010110(cpu),
-
-                // SUB n 0b11010110
-                [1, 1, 0, 1, 0, 1, 1, 0] => OPCode::op_11010110(cpu),
-
-                // SBC r 0b10011xxx
-                [1, 0, 0, 1, 1, _, _, _] => OPCode::op_10011xxx(cpu, &opcode_bits),
-
-                // SUB r 0b10010xxx
-                [1, 0, 0, 1, 0, _, _, _] => OPCode::op_10010xxx(cpu, &opcode_bits),
-
-                // --- logical ---
-
-                // AND (HL) 0b10100110
-                [1, 0, 1, 0, 0, 1, 1, 0] => OPCode::op_10100110(cpu),
-
-                // AND n 0b11100110
-                [1, 1, 1, 0, 0, 1, 1, 0] => OPCode::op_11100110(cpu),
-
-                // OR (HL) 0b10110110
-                [1, 0, 1, 1, 0, 1, 1, 0] => OPCode::op_10110110(cpu),
-
-                // OR n 0b11110110
-                [1, 1, 1, 1, 0, 1, 1, 0] => OPCode::op_11110110(cpu),
-
-                // XOR (HL) 0b10101110
-                [1, 0, 1, 0, 1, 1, 1, 0] => OPCode::op_10101110(cpu),
-                // XOR n 0b11101110
-                [1, 1, 1, 0, 1, 1, 1, 0] => OPCode::op_11101110(cpu),
-
-                // XOR r 0b10101xxx
-                [1, 0, 1, 0, 1, _, _, _] => OPCode::op_10101xxx(cpu, &opcode_bits),
-
-                // OR r  0b10110xxx
-                [1, 0, 1, 1, 0, _, _, _] => OPCode::op_10110xxx(cpu, &opcode_bits),
-
-                // AND r 0b10100xxx
-                [1, 0, 1, 0, 0, _, _, _] => OPCode::op_10100xxx(cpu, &opcode_bits),
-
-                // --- other ALU ---
-
-                // INC (HL) 0b00110100
-                [0, 0, 1, 1, 0, 1, 0, 0] => OPCode::op_00110100(cpu),
-
-                // DEC (HL) 0b00110101
-                [0, 0, 1, 1, 0, 1, 0, 1] => OPCode::op_00110101(cpu),
-
-                // CCF 0b00111111
-                [0, 0, 1, 1, 1, 1, 1, 1] => OPCode::op_00111111(cpu),
-
-                // SCF 0b00110111
-                [0, 0, 1, 1, 0, 1, 1, 1] => OPCode::op_00110111(cpu),
-
-                // DAA 0b00100111
-                [0, 0, 1, 0, 0, 1, 1, 1] => OPCode::op_00100111(cpu),
-
-                // CPL 0b00101111
-                [0, 0, 1, 0, 1, 1, 1, 1] => OPCode::op_00101111(cpu),
-
-                // CP r 0b10111xxx
-                [1, 0, 1, 1, 1, _, _, _] => OPCode::op_10111xxx(cpu, &opcode_bits),
-
-                // INC r 0b00xxx100
-                [0, 0, _, _, _, 1, 0, 0] => OPCode::op_00xxx100(cpu, &opcode_bits),
-
-                // DEC r 0b00xxx101
-                [0, 0, _, _, _, 1, 0, 1] => OPCode::op_00xxx101(cpu, &opcode_bits),
-
-                // --- 16-bit ALU ---
-
-                // ADD SP, e  11101000
-                [1, 1, 1, 0, 1, 0, 0, 0] => OPCode::op_11101000(cpu),
-
-                // INC rr 0b00xx0011
-                [0, 0, _, _, 0, 0, 1, 1] => OPCode::op_00xx0011(cpu, &opcode_bits),
-
-                // DEC rr 0b00xx1011
-                [0, 0, _, _, 1, 0, 1, 1] => OPCode::op_00xx1011(cpu, &opcode_bits),
-
-                // ADD HL, rr 0b00xx1001
-                [0, 0, _, _, 1, 0, 0, 1] => OPCode::op_00xx1001(cpu, &opcode_bits),
-
-                // --- Single Byte Rotations ---
-                // RLCA 00000111
-                [0, 0, 0, 0, 0, 1, 1, 1] => OPCode::cb_op_00000111(cpu),
-
-                // RRCA 00001111
-                [0, 0, 0, 0, 1, 1, 1, 1] => OPCode::cb_op_00001111(cpu),
-
-                // RLA 00010111
-                [0, 0, 0, 1, 0, 1, 1, 1] => OPCode::cb_op_00010111(cpu),
-
-                // RRA 00011111
-                [0, 0, 0, 1, 1, 1, 1, 1] => OPCode::cb_op_00011111(cpu),
-
-                // --- control flow ---
-                // JP nn 11000011
-                [1, 1, 0, 0, 0, 0, 1, 1] => OPCode::op_11000011(cpu),
-
-                // JP HL 11101001
-                [1, 1, 1, 0, 1, 0, 0, 1] => OPCode::op_11101001(cpu),
-
-                // JP cc, nn 110xx010
-                [1, 1, 0, _, _, 0, 1, 0] => OPCode::op_110xx010(cpu, &opcode_bits),
-
-                // JR e 00011000
-                [0, 0, 0, 1, 1, 0, 0, 0] => OPCode::op_00011000(cpu),
-
-                // JR cc, e 001xx000
-                [0, 0, 1, _, _, 0, 0, 0] => OPCode::op_001xx000(cpu, &opcode_bits),
-
-                // CALL nn 11001101
-                [1, 1, 0, 0, 1, 1, 0, 1] => OPCode::op_11001101(cpu),
-
-                // CALL cc, nn 110xx100
-                [1, 1, 0, _, _, 1, 0, 0] => OPCode::op_110xx100(cpu, &opcode_bits),
-
-                // RET 11001001
-                [1, 1, 0, 0, 1, 0, 0, 1] => OPCode::op_11001001(cpu),
-
-                // RET cc 110xx000
-                [1, 1, 0, _, _, 0, 0, 0] => OPCode::op_110xx000(cpu, &opcode_bits),
-
-                // RETI 11011001
-                [1, 1, 0, 1, 1, 0, 0, 1] => OPCode::op_11011001(cpu),
-
-                // RST n 11xxx111
-                [1, 1, _, _, _, 1, 1, 1] => OPCode::op_11xxx111(cpu, &opcode_bits),
-
-                // --- Miscellaneous instructions ---
-
-                // STOP 00010000 00000000
-                // STOP needs a special handler
-
-                // DI 11110011
-                [1, 1, 1, 1, 0, 0, 1, 1] => OPCode::op_11110011(cpu),
-
-                // EI 11111011
-                [1, 1, 1, 1, 1, 0, 1, 1] => OPCode::op_11111011(cpu),
-
-                // NOP 00000000
-                [0, 0, 0, 0, 0, 0, 0, 0] => OPCode::op_00000000(),
-
-                _ => panic!("Invalid opcode"),
-            }
-        } else {
-            match opcode_bits {
-                // TODO: add bit opcodes
-
-                // --- Bit Operations ---
-
-                // RLC (HL) 00000110
-                [0, 0, 0, 0, 0, 1, 1, 0] => OPCode::cb_op_00000110(cpu),
-
-                //RRC (HL) 00001110
-                [0, 0, 0, 0, 1, 1, 1, 0] => OPCode::cb_op_00001110(cpu),
-
-                // RL (HL) 00010110
-                [0, 0, 0, 1, 0, 1, 1, 0] => OPCode::cb_op_00010110(cpu),
-
-                // RR (HL) 00011110
-                [0, 0, 0, 1, 1, 1, 1, 0] => OPCode::cb_op_00011110(cpu),
-
-                // SLA (HL) 00100110
-                [0, 0, 1, 0, 0, 1, 1, 0] => OPCode::cb_op_00100110(cpu),
-
-                // SRA (HL) 00101110
-                [0, 0, 1, 0, 1, 1, 1, 0] => OPCode::cb_op_00101110(cpu),
-
-                // SWAP (HL) 00110110
-                [0, 0, 1, 1, 0, 1, 1, 0] => OPCode::cb_op_00110110(cpu),
-
-                // SRL (HL) 00111110
-                [0, 0, 1, 1, 1, 1, 1, 0] => OPCode::cb_op_00111110(cpu),
-
-                // SET b,(HL) 11xxx110
-                [1, 1, _, _, _, 1, 1, 0] => OPCode::cb_op_11xxx110(cpu, &opcode_bits),
-
-                // RES b, (HL) 10xxx110
-                [1, 0, _, _, _, 1, 1, 0] => OPCode::cb_op_10xxx110(cpu, &opcode_bits),
-
-                // BIT b, (HL) 01xxx110
-                [0, 1, _, _, _, 1, 1, 0] => OPCode::cb_op_01xxx110(cpu, &opcode_bits),
-
-                // RLC r 00000xxx
-                [0, 0, 0, 0, 0, _, _, _] => OPCode::cb_op_00000xxx(cpu, &opcode_bits),
-
-                // RRC r 00001xxx
-                [0, 0, 0, 0, 1, _, _, _] => OPCode::cb_op_00001xxx(cpu, &opcode_bits),
-
-                // RL r 00010xxx
-                [0, 0, 0, 1, 0, _, _, _] => OPCode::cb_op_00010xxx(cpu, &opcode_bits),
-
-                // RR r8 00011xxx
-                [0, 0, 0, 1, 1, _, _, _] => OPCode::cb_op_00011xxx(cpu, &opcode_bits),
-
-                // SLA r8 00100xxx
-                [0, 0, 1, 0, 0, _, _, _] => OPCode::cb_op_00100xxx(cpu, &opcode_bits),
-
-                // SRA r 00101xxx
-                [0, 0, 1, 0, 1, _, _, _] => OPCode::cb_op_00101xxx(cpu, &opcode_bits),
-
-                // SWAP r 00110xxx
-                [0, 0, 1, 1, 0, _, _, _] => OPCode::cb_op_00110xxx(cpu, &opcode_bits),
-
-                // SRL r 00111xxx
-                [0, 0, 1, 1, 1, _, _, _] => OPCode::cb_op_00111xxx(cpu, &opcode_bits),
-
-                // BIT b, r 01xxxxxx
-                [0, 1, _, _, _, _, _, _] => OPCode::cb_op_01xxxxxx(cpu, &opcode_bits),
-
-                // RES b, r 10xxxxxx
-                [1, 0, _, _, _, _, _, _] => OPCode::cb_op_10xxxxxx(cpu, &opcode_bits),
-
-                // SET b, r 11xxxxxx
-                [1, 1, _, _, _, _, _, _] => OPCode::cb_op_11xxxxxx(cpu, &opcode_bits),
-
-                _ => panic!("Invalid opcode"),
+    // execute the opcode through the decode table
+    pub fn exec<B: Bus>(cpu: &mut CPU<B>, opcode: u8, is_cb: bool) -> u8 {
+        let decoded = decode_opcode(opcode, is_cb);
+        let (dst, src) = (decoded.dst, decoded.src);
+        let (pair, condition, bit) = (decoded.pair, decoded.condition, decoded.bit);
+        match decoded.operation {
+            // LD r8, r8: 0b01xxxyyy
+            Operation::LdRR => OPCode::op_01xxxyyy(cpu, register(dst), register(src)),
+            // LD r8, n8: 0b00xxx110
+            Operation::LdRN => OPCode::op_00xxx110(cpu, register(dst)),
+            // LD r8, (HL): 0b01xxx110
+            Operation::LdRHl => OPCode::op_01xxx110(cpu, register(dst)),
+            // LD (HL), r8: 0b01110xxx
+            Operation::LdHlR => OPCode::op_01110xxx(cpu, register(src)),
+            // LD (HL), n8: 0b00110110 (0x36)
+            Operation::LdHlN => OPCode::op_00110110(cpu),
+            // LD A, (BC): 0b00001010 (0x0A)
+            Operation::LdABc => OPCode::op_00001010(cpu),
+            // LD A, (DE): 0b00011010 (0x1A)
+            Operation::LdADe => OPCode::op_00011010(cpu),
+            // LD (BC), A: 0b00000010 (0x02)
+            Operation::LdBcA => OPCode::op_00000010(cpu),
+            // LD (DE), A: 0b00010010 (0x12)
+            Operation::LdDeA => OPCode::op_00010010(cpu),
+            // LD A, (nn): 0b11111010 (0xFA)
+            Operation::LdANn => OPCode::op_11111010(cpu),
+            // LD (nn), A: 0b11101010 (0xEA)
+            Operation::LdNnA => OPCode::op_11101010(cpu),
+            // LDH A, (C): 0b11110010 (0xF2) (LD A, ($FF00+C))
+            Operation::LdhAC => OPCode::op_11110010(cpu),
+            // LDH (C), A: 0b11100010 (0xE2) (LD ($FF00+C), A)
+            Operation::LdhCA => OPCode::op_11100010(cpu),
+            // LDH A, (n): 0b11110000 (0xF0) (LD A, ($FF00+n))
+            Operation::LdhAN => OPCode::op_11110000(cpu),
+            // LDH (n), A: 0b11100000 (0xE0) (LD ($FF00+n), A)
+            Operation::LdhNA => OPCode::op_11100000(cpu),
+            // LD A, (HL-): 0b00111010 (0x3A)
+            Operation::LdAHlDec => OPCode::op_00111010(cpu),
+            // LD (HL-), A: 0b00110010 (0x32)
+            Operation::LdHlDecA => OPCode::op_00110010(cpu),
+            // LD A, (HL+): 0b00101010 (0x2A)
+            Operation::LdAHlInc => OPCode::op_00101010(cpu),
+            // LD (HL+), A: 0b00100010 (0x22)
+            Operation::LdHlIncA => OPCode::op_00100010(cpu),
+            // LD rr, nn: 0b00xx0001
+            Operation::LdRrNn => OPCode::op_00xx0001(cpu, pair),
+            // LD (nn), SP: 0b00001000 (0x08)
+            Operation::LdNnSp => OPCode::op_00001000(cpu),
+            // LD SP, HL: 0b11111001 (0xF9)
+            Operation::LdSpHl => OPCode::op_11111001(cpu),
+            // PUSH rr: 0b11xx0101
+            Operation::Push => OPCode::op_11xx0101(cpu, pair),
+            // POP rr: 0b11xx0001
+            Operation::Pop => OPCode::op_11xx0001(cpu, pair),
+            // LD HL, SP+e: 0b11111000 (0xF8) (LDHL SP, e)
+            Operation::LdHlSpE => OPCode::op_11111000(cpu),
+            //ADD r: 0b10000xxx
+            Operation::AddR => OPCode::op_10000xxx(cpu, register(src)),
+            // ADD (HL) 0b10000110
+            Operation::AddHl => OPCode::op_10000110(cpu),
+            // ADD n: 0b11000110
+            Operation::AddN => OPCode::op_11000110(cpu),
+            // ADC r 0b10001xxx
+            Operation::AdcR => OPCode::op_10001xxx(cpu, register(src)),
+            // ADC (HL) 0b10001110
+            Operation::AdcHl => OPCode::op_10001110(cpu),
+            // ADC n 0b11001110
+            Operation::AdcN => OPCode::op_11001110(cpu),
+            // SUB r 0b10010xxx
+            Operation::SubR => OPCode::op_10010xxx(cpu, register(src)),
+            // SUB (HL) 0b10010110
+            Operation::SubHl => OPCode::op_10010110(cpu),
+            // SUB n 0b11010110
+            Operation::SubN => OPCode::op_11010110(cpu),
+            // SBC r 0b10011xxx
+            Operation::SbcR => OPCode::op_10011xxx(cpu, register(src)),
+            // SBC (HL) 0b10011110
+            Operation::SbcHl => OPCode::op_10011110(cpu),
+            // SBC n 0b11011110
+            Operation::SbcN => OPCode::op_11011110(cpu),
+            // AND r 0b10100xxx
+            Operation::AndR => OPCode::op_10100xxx(cpu, register(src)),
+            // AND (HL) 0b10100110
+            Operation::AndHl => OPCode::op_10100110(cpu),
+            // AND n 0b11100110
+            Operation::AndN => OPCode::op_11100110(cpu),
+            // XOR r 0b10101xxx
+            Operation::XorR => OPCode::op_10101xxx(cpu, register(src)),
+            // XOR (HL) 0b10101110
+            Operation::XorHl => OPCode::op_10101110(cpu),
+            // XOR n 0b11101110
+            Operation::XorN => OPCode::op_11101110(cpu),
+            // OR r  0b10110xxx
+            Operation::OrR => OPCode::op_10110xxx(cpu, register(src)),
+            // OR (HL) 0b10110110
+            Operation::OrHl => OPCode::op_10110110(cpu),
+            // OR n 0b11110110
+            Operation::OrN => OPCode::op_11110110(cpu),
+            // CP r 0b10111xxx
+            Operation::CpR => OPCode::op_10111xxx(cpu, register(src)),
+            // CP (HL) 0b10011110
+            Operation::CpHl => OPCode::op_10111110(cpu),
+            // CP n 0b11111110
+            Operation::CpN => OPCode::op_11111110(cpu),
+            // INC r 0b00xxx100
+            Operation::IncR => OPCode::op_00xxx100(cpu, register(dst)),
+            // INC (HL) 0b00110100
+            Operation::IncHl => OPCode::op_00110100(cpu),
+            // DEC r 0b00xxx101
+            Operation::DecR => OPCode::op_00xxx101(cpu, register(dst)),
+            // DEC (HL) 0b00110101
+            Operation::DecHl => OPCode::op_00110101(cpu),
+            // CCF 0b00111111
+            Operation::Ccf => OPCode::op_00111111(cpu),
+            // SCF 0b00110111
+            Operation::Scf => OPCode::op_00110111(cpu),
+            // DAA 0b00100111
+            Operation::Daa => OPCode::op_00100111(cpu),
+            // CPL 0b00101111
+            Operation::Cpl => OPCode::op_00101111(cpu),
+            // ADD SP, e  11101000
+            Operation::AddSpE => OPCode::op_11101000(cpu),
+            // INC rr 0b00xx0011
+            Operation::IncRr => OPCode::op_00xx0011(cpu, pair),
+            // DEC rr 0b00xx1011
+            Operation::DecRr => OPCode::op_00xx1011(cpu, pair),
+            // ADD HL, rr 0b00xx1001
+            Operation::AddHlRr => OPCode::op_00xx1001(cpu, pair),
+            // RLCA 00000111
+            Operation::Rlca => OPCode::cb_op_00000111(cpu),
+            // RRCA 00001111
+            Operation::Rrca => OPCode::cb_op_00001111(cpu),
+            // RLA 00010111
+            Operation::Rla => OPCode::cb_op_00010111(cpu),
+            // RRA 00011111
+            Operation::Rra => OPCode::cb_op_00011111(cpu),
+            // JP nn 11000011
+            Operation::JpNn => OPCode::op_11000011(cpu),
+            // JP HL 11101001
+            Operation::JpHl => OPCode::op_11101001(cpu),
+            // JP cc, nn 110xx010
+            Operation::JpCcNn => OPCode::op_110xx010(cpu, condition),
+            // JR e 00011000
+            Operation::JrE => OPCode::op_00011000(cpu),
+            // JR cc, e 001xx000
+            Operation::JrCcE => OPCode::op_001xx000(cpu, condition),
+            // CALL nn 11001101
+            Operation::CallNn => OPCode::op_11001101(cpu),
+            // CALL cc, nn 110xx100
+            Operation::CallCcNn => OPCode::op_110xx100(cpu, condition),
+            // RET 11001001
+            Operation::Ret => OPCode::op_11001001(cpu),
+            // RET cc 110xx000
+            Operation::RetCc => OPCode::op_110xx000(cpu, condition),
+            // RETI 11011001
+            Operation::Reti => OPCode::op_11011001(cpu),
+            // RST n 11xxx111
+            Operation::Rst => OPCode::op_11xxx111(cpu, decoded.vector),
+            // HALT 01110110
+            Operation::Halt => OPCode::op_01110110(cpu),
+            // STOP 00010000 00000000
+            Operation::Stop => OPCode::op_00010000_00000000(cpu),
+            // DI 11110011
+            Operation::Di => OPCode::op_11110011(cpu),
+            // EI 11111011
+            Operation::Ei => OPCode::op_11111011(cpu),
+            // NOP 00000000
+            Operation::Nop => OPCode::op_00000000(),
+            // LD B, B: 0b01000000 (0x40)
+            Operation::LdBB => OPCode::op_01000000(cpu),
+            // RLC r 00000xxx
+            Operation::RlcR => OPCode::cb_op_00000xxx(cpu, register(src)),
+            // RLC (HL) 00000110
+            Operation::RlcHl => OPCode::cb_op_00000110(cpu),
+            // RRC r 00001xxx
+            Operation::RrcR => OPCode::cb_op_00001xxx(cpu, register(src)),
+            //RRC (HL) 00001110
+            Operation::RrcHl => OPCode::cb_op_00001110(cpu),
+            // RL r 00010xxx
+            Operation::RlR => OPCode::cb_op_00010xxx(cpu, register(src)),
+            // RL (HL) 00010110
+            Operation::RlHl => OPCode::cb_op_00010110(cpu),
+            // RR r8 00011xxx
+            Operation::RrR => OPCode::cb_op_00011xxx(cpu, register(src)),
+            // RR (HL) 00011110
+            Operation::RrHl => OPCode::cb_op_00011110(cpu),
+            // SLA r8 00100xxx
+            Operation::SlaR => OPCode::cb_op_00100xxx(cpu, register(src)),
+            // SLA (HL) 00100110
+            Operation::SlaHl => OPCode::cb_op_00100110(cpu),
+            // SRA r 00101xxx
+            Operation::SraR => OPCode::cb_op_00101xxx(cpu, register(src)),
+            // SRA (HL) 00101110
+            Operation::SraHl => OPCode::cb_op_00101110(cpu),
+            // SWAP r 00110xxx
+            Operation::SwapR => OPCode::cb_op_00110xxx(cpu, register(src)),
+            // SWAP (HL) 00110110
+            Operation::SwapHl => OPCode::cb_op_00110110(cpu),
+            // SRL r 00111xxx
+            Operation::SrlR => OPCode::cb_op_00111xxx(cpu, register(src)),
+            // SRL (HL) 00111110
+            Operation::SrlHl => OPCode::cb_op_00111110(cpu),
+            // BIT b, r 01xxxxxx
+            Operation::BitR => OPCode::cb_op_01xxxxxx(cpu, bit, register(src)),
+            // BIT b, (HL) 01xxx110
+            Operation::BitHl => OPCode::cb_op_01xxx110(cpu, bit),
+            // RES b, r 10xxxxxx
+            Operation::ResR => OPCode::cb_op_10xxxxxx(cpu, bit, register(src)),
+            // RES b, (HL) 10xxx110
+            Operation::ResHl => OPCode::cb_op_10xxx110(cpu, bit),
+            // SET b, r 11xxxxxx
+            Operation::SetR => OPCode::cb_op_11xxxxxx(cpu, bit, register(src)),
+            // SET b,(HL) 11xxx110
+            Operation::SetHl => OPCode::cb_op_11xxx110(cpu, bit),
+            // the unused opcodes lock up the cpu, CPU::tick reports it
+            Operation::Invalid => {
+                cpu.illegal_opcode = Some(opcode);
+                1
             }
         }
     }
-
-    // TODO: execute the stop instruction
-    pub fn exec_stop(cpu: &mut CPU) -> u8 {
-        unimplemented!()
-    }
 }
 
-// macro_rules! set_16b_register_by_index {
-//     ($index:expr, $cpu:expr, $value:expr) => {
-//         match $index {
-//             0b00 => $cpu.set_BC($value),
-//             0b01 => $cpu.set_DE($value),
-//             0b10 => $cpu.set_HL($value),
-//             0b11 => $cpu.set_SP($value),
-//             _ => panic!("Invalid 16-bit register index"),
-//         }
-//     };
-// }
-
-// macro_rules! get_16b_register_by_index {
-//     ($index: expr, $cpu: expr) => {
-//         match $index {
-//             0b00 => $cpu.BC(),
-//             0b01 => $cpu.DE(),
-//             0b10 => $cpu.HL(),
-//             0b11 => $cpu.SP,
-//             _ => panic!("Invalid 16-bit register index"),
-//         }
-//     };
-// }
-
-// pub(crate) use get_16b_register_by_index;
-// pub(crate) use set_16b_register_by_index;
+// the register forms, (HL) decodes to its own operations
+fn register(register: Option<Register>) -> Register {
+    register.expect("(HL) operand in a register form")
+}
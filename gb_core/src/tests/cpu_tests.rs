@@ -1,42 +1,78 @@
-use crate::core::CPU;
+use crate::core::{Bus, Error, FaultPolicy, FlatBus, Interrupt, CPU, IE};
+use crate::io_registers::speed::{KEY1, SPEED_SWITCH_CYCLES};
+use crate::io_registers::timer::{DIV, TAC, TIMA};
+use crate::io_registers::Button;
+use crate::opcodes::decoder::decode_opcode;
 use crate::opcodes::opcode::OPCode;
 
 #[test]
-fn test_set_register_macro() {
+fn test_set_register_pair() {
     let mut cpu = CPU::new();
-    OPCode::set_16b_register_by_index(0b00, &mut cpu, 0x1234);
+    // LD BC, nn
+    OPCode::set_register_pair(decode_opcode(0x01, false).pair, &mut cpu, 0x1234);
     assert_eq!(cpu.b, 0x12);
     assert_eq!(cpu.c, 0x34);
 
-    OPCode::set_16b_register_by_index(0b01, &mut cpu, 0x5678);
+    // LD DE, nn
+    OPCode::set_register_pair(decode_opcode(0x11, false).pair, &mut cpu, 0x5678);
     assert_eq!(cpu.d, 0x56);
     assert_eq!(cpu.e, 0x78);
 
-    OPCode::set_16b_register_by_index(0b10, &mut cpu, 0x9ABC);
+    // LD HL, nn
+    OPCode::set_register_pair(decode_opcode(0x21, false).pair, &mut cpu, 0x9ABC);
     assert_eq!(cpu.h, 0x9A);
     assert_eq!(cpu.l, 0xBC);
 
-    OPCode::set_16b_register_by_index(0b11, &mut cpu, 0xDEF0);
+    // LD SP, nn
+    OPCode::set_register_pair(decode_opcode(0x31, false).pair, &mut cpu, 0xDEF0);
     assert_eq!(cpu.sp, 0xDEF0);
+
+    // POP AF, the lower 4 bits of F are dropped
+    OPCode::set_register_pair(decode_opcode(0xF1, false).pair, &mut cpu, 0x12FF);
+    assert_eq!((cpu.a, cpu.f), (0x12, 0xF0));
 }
 
 #[test]
-fn test_get_register_macro() {
+fn test_get_register_pair() {
     let mut cpu = CPU::new();
+    // PUSH BC
     cpu.b = 0x12;
     cpu.c = 0x34;
-    assert_eq!(OPCode::get_16b_register_by_index(0b00, &cpu), 0x1234);
+    assert_eq!(
+        OPCode::get_register_pair(decode_opcode(0xC5, false).pair, &cpu),
+        0x1234
+    );
 
+    // PUSH DE
     cpu.d = 0x56;
     cpu.e = 0x78;
-    assert_eq!(OPCode::get_16b_register_by_index(0b01, &cpu), 0x5678);
+    assert_eq!(
+        OPCode::get_register_pair(decode_opcode(0xD5, false).pair, &cpu),
+        0x5678
+    );
 
+    // PUSH HL
     cpu.h = 0x9A;
     cpu.l = 0xBC;
-    assert_eq!(OPCode::get_16b_register_by_index(0b10, &cpu), 0x9ABC);
+    assert_eq!(
+        OPCode::get_register_pair(decode_opcode(0xE5, false).pair, &cpu),
+        0x9ABC
+    );
+
+    // PUSH AF
+    cpu.a = 0xDE;
+    cpu.f = 0xF0;
+    assert_eq!(
+        OPCode::get_register_pair(decode_opcode(0xF5, false).pair, &cpu),
+        0xDEF0
+    );
 
+    // INC SP
     cpu.sp = 0xDEF0;
-    assert_eq!(OPCode::get_16b_register_by_index(0b11, &cpu), 0xDEF0);
+    assert_eq!(
+        OPCode::get_register_pair(decode_opcode(0x33, false).pair, &cpu),
+        0xDEF0
+    );
 }
 
 #[test]
@@ -60,3 +96,369 @@ fn test_flags() {
     cpu.set_c(false);
     assert!(!cpu.c());
 }
+
+#[test]
+fn test_interrupt_dispatch_priority() {
+    let mut cpu = CPU::new();
+    cpu.pc = 0xC000;
+    cpu.sp = 0xD000;
+    cpu.ime = true;
+    cpu.memory_bus.write_byte(IE, 0x1F);
+    cpu.memory_bus.request_interrupt(Interrupt::Joypad);
+    cpu.memory_bus.request_interrupt(Interrupt::Timer);
+
+    // timer has higher priority than joypad, servicing takes 5 machine cycles
+    assert_eq!(cpu.tick().unwrap(), 20);
+    assert_eq!(cpu.pc, 0x0050);
+    assert!(!cpu.ime);
+    assert_eq!(cpu.sp, 0xCFFE);
+    assert_eq!(cpu.memory_bus.read_word(cpu.sp), 0xC000);
+    assert_eq!(cpu.r#if() & 0x1F, Interrupt::Joypad.bit());
+}
+
+#[test]
+fn test_ei_delay() {
+    let mut cpu = CPU::new();
+    cpu.pc = 0xC000;
+    cpu.sp = 0xD000;
+    // EI, NOP, NOP
+    cpu.memory_bus.write_byte(0xC000, 0xFB);
+    cpu.memory_bus.write_byte(0xC001, 0x00);
+    cpu.memory_bus.write_byte(0xC002, 0x00);
+    cpu.memory_bus.write_byte(IE, Interrupt::VBlank.bit());
+    cpu.memory_bus.request_interrupt(Interrupt::VBlank);
+
+    cpu.tick().unwrap();
+    assert!(!cpu.ime);
+    // the instruction after EI is executed before the interrupt is serviced
+    cpu.tick().unwrap();
+    assert!(cpu.ime);
+    assert_eq!(cpu.pc, 0xC002);
+    cpu.tick().unwrap();
+    assert_eq!(cpu.pc, 0x0040);
+}
+
+#[test]
+fn test_di_cancels_ei() {
+    let mut cpu = CPU::new();
+    cpu.pc = 0xC000;
+    // EI, DI, NOP
+    cpu.memory_bus.write_byte(0xC000, 0xFB);
+    cpu.memory_bus.write_byte(0xC001, 0xF3);
+    cpu.memory_bus.write_byte(0xC002, 0x00);
+    cpu.memory_bus.write_byte(IE, Interrupt::VBlank.bit());
+    cpu.memory_bus.request_interrupt(Interrupt::VBlank);
+
+    cpu.tick().unwrap();
+    cpu.tick().unwrap();
+    cpu.tick().unwrap();
+    assert!(!cpu.ime);
+    assert_eq!(cpu.pc, 0xC003);
+}
+
+#[test]
+fn test_halt_wakes_without_ime() {
+    let mut cpu = CPU::new();
+    cpu.pc = 0xC000;
+    // HALT, NOP
+    cpu.memory_bus.write_byte(0xC000, 0x76);
+    cpu.memory_bus.write_byte(0xC001, 0x00);
+    cpu.memory_bus.write_byte(IE, Interrupt::Timer.bit());
+
+    cpu.tick().unwrap();
+    assert!(cpu.is_halted);
+    assert_eq!(cpu.tick().unwrap(), 4);
+    assert!(cpu.is_halted);
+
+    // the cpu resumes without calling the handler
+    cpu.memory_bus.request_interrupt(Interrupt::Timer);
+    cpu.tick().unwrap();
+    assert!(!cpu.is_halted);
+    assert_eq!(cpu.pc, 0xC002);
+    assert_eq!(cpu.r#if() & 0x1F, Interrupt::Timer.bit());
+}
+
+#[test]
+fn test_halt_bug() {
+    let mut cpu = CPU::new();
+    cpu.pc = 0xC000;
+    // HALT, INC A, NOP
+    cpu.memory_bus.write_byte(0xC000, 0x76);
+    cpu.memory_bus.write_byte(0xC001, 0x3C);
+    cpu.memory_bus.write_byte(0xC002, 0x00);
+    cpu.memory_bus.write_byte(IE, Interrupt::Timer.bit());
+    cpu.memory_bus.request_interrupt(Interrupt::Timer);
+
+    // IME = 0 with an interrupt pending, the cpu does not halt and INC A is read twice
+    cpu.tick().unwrap();
+    assert!(!cpu.is_halted);
+    cpu.tick().unwrap();
+    assert_eq!(cpu.pc, 0xC001);
+    cpu.tick().unwrap();
+    assert_eq!(cpu.pc, 0xC002);
+    assert_eq!(cpu.a, 2);
+}
+
+#[test]
+fn test_ei_halt() {
+    let mut cpu = CPU::new();
+    cpu.pc = 0xC000;
+    cpu.sp = 0xD000;
+    // EI, HALT
+    cpu.memory_bus.write_byte(0xC000, 0xFB);
+    cpu.memory_bus.write_byte(0xC001, 0x76);
+    cpu.memory_bus.write_byte(IE, Interrupt::Timer.bit());
+    cpu.memory_bus.request_interrupt(Interrupt::Timer);
+
+    cpu.tick().unwrap();
+    cpu.tick().unwrap();
+    assert!(cpu.ime);
+    // the handler returns to the HALT
+    cpu.tick().unwrap();
+    assert_eq!(cpu.pc, 0x0050);
+    assert_eq!(cpu.memory_bus.read_word(cpu.sp), 0xC001);
+}
+
+#[test]
+fn test_halt_rst() {
+    let mut cpu = CPU::new();
+    cpu.pc = 0xC000;
+    cpu.sp = 0xD000;
+    // HALT, RST 0x38
+    cpu.memory_bus.write_byte(0xC000, 0x76);
+    cpu.memory_bus.write_byte(0xC001, 0xFF);
+    cpu.memory_bus.write_byte(IE, Interrupt::Timer.bit());
+    cpu.memory_bus.request_interrupt(Interrupt::Timer);
+
+    cpu.tick().unwrap();
+    cpu.tick().unwrap();
+    // RST pushes its own address, it runs again once the handler returns
+    assert_eq!(cpu.pc, 0x0038);
+    assert_eq!(cpu.memory_bus.read_word(cpu.sp), 0xC001);
+}
+
+#[test]
+fn test_oam_dma() {
+    let mut cpu = CPU::new();
+    for i in 0..0xA0 {
+        cpu.memory_bus.write_byte(0xC100 + i, i as u8);
+    }
+    cpu.memory_bus.write_byte(0xFF80, 0x42);
+    cpu.memory_bus.write_byte(0xFF46, 0xC1);
+
+    // only HRAM is accessible while the transfer runs
+    cpu.memory_bus.step(8);
+    assert_eq!(cpu.memory_bus.read_byte(0xC105), 0xFF);
+    assert_eq!(cpu.memory_bus.read_byte(0xFF80), 0x42);
+    cpu.memory_bus.write_byte(0xC105, 0x00);
+
+    cpu.memory_bus.step(159 * 4);
+    for i in 0..0xA0 {
+        assert_eq!(cpu.memory_bus.read_byte(0xFE00 + i), i as u8);
+    }
+    assert_eq!(cpu.memory_bus.read_byte(0xC105), 0x05);
+}
+
+#[test]
+fn test_joypad_wakes_stop() {
+    let mut cpu = CPU::new();
+    cpu.pc = 0xC000;
+    // STOP, NOP
+    cpu.memory_bus.write_byte(0xC000, 0x10);
+    cpu.memory_bus.write_byte(0xC001, 0x00);
+    cpu.memory_bus.write_byte(0xC002, 0x00);
+    // select the action buttons
+    cpu.memory_bus.write_byte(0xFF00, 0x10);
+
+    // the timer at its fastest rate
+    cpu.memory_bus.write_byte(TAC, 0x05);
+
+    cpu.tick().unwrap();
+    assert!(cpu.is_stopped);
+    assert_eq!(cpu.tick().unwrap(), 4);
+    assert_eq!(cpu.pc, 0xC002);
+
+    // the system clock is stopped, DIV and TIMA do not count
+    for _ in 0..0x1000 {
+        cpu.tick().unwrap();
+    }
+    assert_eq!(cpu.memory_bus.read_byte(DIV), 0);
+    assert_eq!(cpu.memory_bus.read_byte(TIMA), 0);
+
+    cpu.memory_bus.set_button(Button::Start, true);
+    assert_eq!(cpu.r#if() & 0x1F, Interrupt::Joypad.bit());
+    cpu.tick().unwrap();
+    assert!(!cpu.is_stopped);
+    assert_eq!(cpu.pc, 0xC003);
+}
+
+#[test]
+fn test_stop_resets_div() {
+    let mut cpu = CPU::new();
+    cpu.pc = 0xC000;
+    // STOP with a non-zero second byte, it is skipped all the same
+    cpu.memory_bus.write_byte(0xC000, 0x10);
+    cpu.memory_bus.write_byte(0xC001, 0x3C);
+    cpu.memory_bus.step(0x400);
+    assert_ne!(cpu.memory_bus.read_byte(DIV), 0);
+
+    cpu.tick().unwrap();
+    assert!(cpu.is_stopped);
+    assert_eq!(cpu.pc, 0xC002);
+    assert_eq!(cpu.memory_bus.read_byte(DIV), 0);
+}
+
+#[test]
+fn test_cgb_speed_switch() {
+    let mut cpu = CPU::new();
+    cpu.memory_bus.set_cgb_mode(true);
+    cpu.pc = 0xC000;
+    // STOP, INC A
+    cpu.memory_bus.write_byte(0xC000, 0x10);
+    cpu.memory_bus.write_byte(0xC001, 0x00);
+    cpu.memory_bus.write_byte(0xC002, 0x3C);
+    // arm the switch
+    cpu.memory_bus.write_byte(KEY1, 0x01);
+
+    cpu.tick().unwrap();
+    assert!(!cpu.is_stopped);
+    assert_eq!(cpu.memory_bus.read_byte(KEY1), 0xFE);
+
+    // the cpu stalls while the clock switches, no button is needed to continue
+    for _ in 0..SPEED_SWITCH_CYCLES {
+        cpu.tick().unwrap();
+    }
+    assert_eq!(cpu.a, 0);
+    // DIV stands still during the switch too
+    assert_eq!(cpu.memory_bus.read_byte(DIV), 0);
+    cpu.tick().unwrap();
+    assert_eq!(cpu.a, 1);
+}
+
+// flat memory that counts the machine cycles the cpu ticks it, and records when writes happen
+struct CountingBus {
+    memory: FlatBus,
+    ticks: u32,
+    writes: Vec<(u32, u16)>,
+}
+
+impl Bus for CountingBus {
+    fn read_byte(&self, address: u16) -> u8 {
+        self.memory.read_byte(address)
+    }
+
+    fn write_byte(&mut self, address: u16, value: u8) {
+        self.memory.write_byte(address, value);
+        self.writes.push((self.ticks, address));
+    }
+
+    fn tick(&mut self) {
+        self.ticks += 1;
+    }
+
+    fn reset(&mut self) {
+        self.memory.reset();
+        self.ticks = 0;
+        self.writes.clear();
+    }
+}
+
+#[test]
+fn test_cpu_on_custom_bus() {
+    let mut cpu = CPU::with_bus(CountingBus {
+        memory: FlatBus::new(),
+        ticks: 0,
+        writes: Vec::new(),
+    });
+    cpu.pc = 0x0100;
+    cpu.sp = 0xFFFE;
+    // LD B, 0x42; PUSH BC; NOP
+    for (i, byte) in [0x06, 0x42, 0xC5, 0x00].iter().enumerate() {
+        cpu.memory_bus.write_byte(0x0100 + i as u16, *byte);
+    }
+
+    assert_eq!(cpu.tick().unwrap(), 8);
+    assert_eq!(cpu.tick().unwrap(), 16);
+    assert_eq!(cpu.memory_bus.read_byte(0xFFFD), 0x42);
+    assert_eq!(cpu.tick().unwrap(), 4);
+    assert_eq!(cpu.memory_bus.ticks, 7);
+}
+
+#[test]
+fn test_access_timing() {
+    let mut cpu = CPU::with_bus(CountingBus {
+        memory: FlatBus::new(),
+        ticks: 0,
+        writes: Vec::new(),
+    });
+    cpu.sp = 0xFFFE;
+    cpu.set_hl(0xC000);
+    // LD (HL), 0x12; PUSH HL; CALL 0x0000
+    for (i, byte) in [0x36, 0x12, 0xE5, 0xCD, 0x00, 0x00].iter().enumerate() {
+        cpu.memory_bus.memory.write_byte(i as u16, *byte);
+    }
+
+    // the write lands in the third machine cycle
+    assert_eq!(cpu.tick().unwrap(), 12);
+    assert_eq!(cpu.memory_bus.writes, vec![(2, 0xC000)]);
+
+    // an internal cycle, then the high byte is pushed first
+    cpu.memory_bus.writes.clear();
+    assert_eq!(cpu.tick().unwrap(), 16);
+    assert_eq!(cpu.memory_bus.writes, vec![(5, 0xFFFD), (6, 0xFFFC)]);
+
+    cpu.memory_bus.writes.clear();
+    assert_eq!(cpu.tick().unwrap(), 24);
+    assert_eq!(cpu.memory_bus.writes, vec![(11, 0xFFFB), (12, 0xFFFA)]);
+    assert_eq!(cpu.memory_bus.read_word(0xFFFA), 0x0006);
+    assert_eq!(cpu.pc, 0x0000);
+}
+
+#[test]
+fn test_bus_fault() {
+    let mut cpu = CPU::new();
+    cpu.pc = 0xC000;
+    // LD A, (0x0150) without a cartridge
+    cpu.memory_bus.write_byte(0xC000, 0xFA);
+    cpu.memory_bus.write_byte(0xC001, 0x50);
+    cpu.memory_bus.write_byte(0xC002, 0x01);
+
+    // the instruction completes, then the fault is reported with the pc and address
+    match cpu.tick() {
+        Err(Error::BusFault { pc, address, cause }) => {
+            assert_eq!((pc, address), (0xC000, 0x0150));
+            assert!(matches!(*cause, Error::CartridgeNotLoaded));
+        }
+        result => panic!("expected a bus fault, got {:?}", result),
+    }
+    assert_eq!(cpu.pc, 0xC003);
+
+    // open bus reads 0xFF and keeps running
+    cpu.pc = 0xC000;
+    cpu.memory_bus.set_fault_policy(FaultPolicy::OpenBus);
+    assert_eq!(cpu.tick().unwrap(), 16);
+    assert_eq!(cpu.a, 0xFF);
+}
+
+#[test]
+fn test_illegal_opcode() {
+    let mut cpu = CPU::new();
+    cpu.pc = 0xC000;
+    cpu.memory_bus.write_byte(0xC000, 0xD3);
+    cpu.memory_bus.write_byte(0xC001, 0x00);
+
+    match cpu.tick() {
+        Err(Error::IllegalOpcode { pc, opcode }) => assert_eq!((pc, opcode), (0xC000, 0xD3)),
+        result => panic!("expected an illegal opcode, got {:?}", result),
+    }
+
+    // the cpu stays locked up, interrupts included
+    cpu.set_ime(true);
+    cpu.memory_bus.write_byte(IE, 0x1F);
+    cpu.memory_bus.write_byte(0xFF0F, 0x1F);
+    assert_eq!(cpu.tick().unwrap(), 4);
+    assert_eq!(cpu.pc, 0xC001);
+
+    cpu.reset();
+    assert_eq!(cpu.illegal_opcode, None);
+}
@@ -1,2 +1,4 @@
 mod cpu_tests;
+mod mooneye_tests;
 mod opcode_tests;
+mod sm83_tests;
@@ -0,0 +1,16 @@
+use crate::core::DEFAULT_TIMER_FREQUENCY;
+use crate::harness::{run_mooneye, MooneyeResult};
+
+// mooneye roms finish within a few emulated seconds
+// unused when no roms are found
+#[allow(dead_code)]
+const MAX_CYCLES: u64 = 20 * DEFAULT_TIMER_FREQUENCY;
+
+#[allow(dead_code)]
+fn assert_mooneye_passed(path: &str) {
+    let result = run_mooneye(path, MAX_CYCLES).unwrap();
+    assert_eq!(result, MooneyeResult::Passed, "{}: {}", path, result);
+}
+
+// one test per rom found by build.rs
+include!(concat!(env!("OUT_DIR"), "/mooneye_tests.rs"));
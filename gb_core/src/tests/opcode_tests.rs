@@ -1,4 +1,4 @@
-use crate::core::CPU;
+use crate::core::{Bus, CPU};
 use crate::opcodes::opcode::OPCode;
 
 #[test]
@@ -36,3 +36,87 @@ fn test_op_0b11111000() {
     assert_eq!(cpu.hl(), 0b00000001_00000000);
     assert_eq!(cpu.f, 0b00010000);
 }
+
+#[test]
+fn test_op_0b11111000_negative() {
+    let mut cpu = CPU::new();
+    cpu.sp = 0x0000;
+    cpu.pc = 0xC000;
+    cpu.memory_bus.write_byte(cpu.pc, 0xFF);
+    // LD HL, SP-1 borrows, but no carry out of the low byte
+    OPCode::exec(&mut cpu, 0b11111000, false);
+    assert_eq!(cpu.hl(), 0xFFFF);
+    assert_eq!(cpu.f, 0b00000000);
+
+    cpu.sp = 0x00FF;
+    cpu.pc = 0xC000;
+    cpu.memory_bus.write_byte(cpu.pc, 0xFF);
+    // LD HL, SP-1, both flags set
+    OPCode::exec(&mut cpu, 0b11111000, false);
+    assert_eq!(cpu.hl(), 0x00FE);
+    assert_eq!(cpu.f, 0b00110000);
+}
+
+#[test]
+fn test_push_pop_af() {
+    let mut cpu = CPU::new();
+    cpu.sp = 0xD000;
+    cpu.a = 0x12;
+    cpu.f = 0xB0;
+    // PUSH AF
+    OPCode::exec(&mut cpu, 0b11110101, false);
+    assert_eq!(cpu.sp, 0xCFFE);
+    assert_eq!(cpu.memory_bus.read_word(0xCFFE), 0x12B0);
+
+    // POP AF, the lower 4 bits of F are dropped
+    cpu.memory_bus.write_word(0xCFFE, 0x34FF);
+    OPCode::exec(&mut cpu, 0b11110001, false);
+    assert_eq!(cpu.sp, 0xD000);
+    assert_eq!((cpu.a, cpu.f), (0x34, 0xF0));
+
+    // SP is left alone
+    cpu.set_hl(0x5678);
+    OPCode::exec(&mut cpu, 0b11100101, false);
+    OPCode::exec(&mut cpu, 0b11110001, false);
+    assert_eq!((cpu.a, cpu.f, cpu.sp), (0x56, 0x70, 0xD000));
+}
+
+#[test]
+fn test_register_operands() {
+    let mut cpu = CPU::new();
+    cpu.a = 0x0F;
+    cpu.b = 0x01;
+    // ADD A, B adds the value of B
+    OPCode::exec(&mut cpu, 0b10000000, false);
+    assert_eq!(cpu.a, 0x10);
+    assert_eq!(cpu.f, 0b00100000);
+
+    // LD D, A
+    OPCode::exec(&mut cpu, 0b01010111, false);
+    assert_eq!(cpu.d, 0x10);
+
+    // SET 7, D and RES 4, D
+    OPCode::exec(&mut cpu, 0b11111010, true);
+    OPCode::exec(&mut cpu, 0b10100010, true);
+    assert_eq!(cpu.d, 0x80);
+
+    // INC DE
+    cpu.e = 0xFF;
+    OPCode::exec(&mut cpu, 0b00010011, false);
+    assert_eq!(cpu.de(), 0x8100);
+
+    // JR NC, +2 taken, JR C not taken
+    cpu.pc = 0xC000;
+    cpu.memory_bus.write_byte(0xC000, 0x02);
+    assert_eq!(OPCode::exec(&mut cpu, 0b00110000, false), 3);
+    assert_eq!(cpu.pc, 0xC003);
+    cpu.memory_bus.write_byte(0xC003, 0x02);
+    assert_eq!(OPCode::exec(&mut cpu, 0b00111000, false), 2);
+    assert_eq!(cpu.pc, 0xC004);
+
+    // RST 0x28
+    cpu.sp = 0xD000;
+    OPCode::exec(&mut cpu, 0b11101111, false);
+    assert_eq!(cpu.pc, 0x0028);
+    assert_eq!(cpu.memory_bus.read_word(0xCFFE), 0xC004);
+}
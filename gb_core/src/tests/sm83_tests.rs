@@ -0,0 +1,254 @@
+// single step tests in the SM83 json format, one file per opcode ("00.json", "cb 00.json")
+// every case has the initial and final registers and RAM, and the bus activity of each machine cycle,
+// the bus activity is compared cycle by cycle, idle cycles only by their kind.
+// the tests are read from SM83_TESTS, or ../sm83/v1 next to this crate
+use crate::core::{Bus, BusCycle, FlatBus, RecordingBus, CPU, IE};
+use crate::opcodes::opcode::OPCode;
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+
+fn tests_dir() -> PathBuf {
+    match env::var("SM83_TESTS") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../sm83/v1"),
+    }
+}
+
+fn field(state: &Value, name: &str) -> u16 {
+    state[name].as_u64().unwrap() as u16
+}
+
+// cpu on a flat 64 KiB bus in the initial state
+fn load_state(state: &Value) -> CPU<RecordingBus> {
+    let mut cpu = CPU::with_bus(RecordingBus::new(FlatBus::new()));
+    cpu.pc = field(state, "pc");
+    cpu.sp = field(state, "sp");
+    cpu.a = field(state, "a") as u8;
+    cpu.f = field(state, "f") as u8;
+    cpu.b = field(state, "b") as u8;
+    cpu.c = field(state, "c") as u8;
+    cpu.d = field(state, "d") as u8;
+    cpu.e = field(state, "e") as u8;
+    cpu.h = field(state, "h") as u8;
+    cpu.l = field(state, "l") as u8;
+    cpu.set_ime(field(state, "ime") == 1);
+    // the setup is not recorded
+    let bus = cpu.memory_bus.bus_mut();
+    bus.write_byte(IE, field(state, "ie") as u8);
+    for entry in state["ram"].as_array().unwrap() {
+        let address = entry[0].as_u64().unwrap() as u16;
+        let value = entry[1].as_u64().unwrap() as u8;
+        bus.write_byte(address, value);
+    }
+    cpu
+}
+
+// execute one instruction through OPCode::exec, return machine cycles taken
+fn step(cpu: &mut CPU<RecordingBus>) -> u8 {
+    let opcode = cpu.read_cycle(cpu.pc);
+    cpu.pc = cpu.pc.wrapping_add(1);
+    let cycles = match opcode {
+        0xCB => {
+            let opcode = cpu.read_cycle(cpu.pc);
+            cpu.pc = cpu.pc.wrapping_add(1);
+            OPCode::exec(cpu, opcode, true)
+        }
+        _ => OPCode::exec(cpu, opcode, false),
+    };
+    // the remaining machine cycles are internal, like CPU::tick
+    while cpu.memory_bus.cycles().len() < cycles as usize {
+        cpu.internal_cycle();
+    }
+    cycles
+}
+
+// [address, value, kind] of a machine cycle, the value of an idle cycle may be null
+fn expected_cycle(entry: &Value) -> BusCycle {
+    let address = entry[0].as_u64().unwrap_or(0) as u16;
+    let value = entry[1].as_u64().unwrap_or(0) as u8;
+    match entry[2].as_str() {
+        Some("r-m") => BusCycle::Read { address, value },
+        Some("-wm") => BusCycle::Write { address, value },
+        _ => BusCycle::Idle,
+    }
+}
+
+// compare the cpu with the final state and the bus activity, return the differences
+fn compare_state(cpu: &CPU<RecordingBus>, state: &Value, cycles: &[Value]) -> Vec<String> {
+    let mut errors = Vec::new();
+    let registers = [
+        ("pc", cpu.pc),
+        ("sp", cpu.sp),
+        ("a", cpu.a as u16),
+        ("f", cpu.f as u16),
+        ("b", cpu.b as u16),
+        ("c", cpu.c as u16),
+        ("d", cpu.d as u16),
+        ("e", cpu.e as u16),
+        ("h", cpu.h as u16),
+        ("l", cpu.l as u16),
+        ("ie", cpu.memory_bus.bus().read_byte(IE) as u16),
+    ];
+    for (name, value) in registers {
+        let expected = field(state, name);
+        if value != expected {
+            errors.push(format!(
+                "{}: {:04x}, expected {:04x}",
+                name, value, expected
+            ));
+        }
+    }
+    // EI enables IME after the next instruction, count it as enabled
+    let ime = cpu.ime || cpu.ime_scheduled;
+    if ime != (field(state, "ime") == 1) {
+        errors.push(format!("ime: {}, expected {}", ime, !ime));
+    }
+    for entry in state["ram"].as_array().unwrap() {
+        let address = entry[0].as_u64().unwrap() as u16;
+        let expected = entry[1].as_u64().unwrap() as u8;
+        let value = cpu.memory_bus.bus().read_byte(address);
+        if value != expected {
+            errors.push(format!(
+                "ram {:04x}: {:02x}, expected {:02x}",
+                address, value, expected
+            ));
+        }
+    }
+    let expected: Vec<BusCycle> = cycles.iter().map(expected_cycle).collect();
+    if cpu.memory_bus.cycles() != expected {
+        errors.push(format!(
+            "cycles: {:?}, expected {:?}",
+            cpu.memory_bus.cycles(),
+            expected
+        ));
+    }
+    errors
+}
+
+// run all cases of a test file, return every failure
+fn run_test_file(tests: &[Value]) -> Vec<String> {
+    let mut failures = Vec::new();
+    for test in tests {
+        let name = test["name"].as_str().unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut cpu = load_state(&test["initial"]);
+            step(&mut cpu);
+            compare_state(&cpu, &test["final"], test["cycles"].as_array().unwrap())
+        }));
+        match result {
+            Ok(errors) if errors.is_empty() => (),
+            Ok(errors) => failures.push(format!("{}: {}", name, errors.join(", "))),
+            Err(_) => failures.push(format!("{}: panicked", name)),
+        }
+    }
+    failures
+}
+
+#[test]
+#[ignore = "needs the SM83 tests in ../sm83/v1 or SM83_TESTS"]
+fn test_sm83_single_step() {
+    let dir = tests_dir();
+    let entries = fs::read_dir(&dir)
+        .unwrap_or_else(|error| panic!("no SM83 tests in {}: {}", dir.display(), error));
+    let mut files: Vec<PathBuf> = entries
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "no SM83 tests in {}", dir.display());
+
+    // every failing case is counted, only the first of each file is shown
+    let mut failed_cases = 0;
+    let mut cases = 0;
+    let mut failures = Vec::new();
+    for file in &files {
+        let tests: Vec<Value> = serde_json::from_str(&fs::read_to_string(file).unwrap()).unwrap();
+        let file_failures = run_test_file(&tests);
+        cases += tests.len();
+        if let Some(first) = file_failures.first() {
+            failed_cases += file_failures.len();
+            failures.push(format!(
+                "{} ({} failed): {}",
+                file.display(),
+                file_failures.len(),
+                first
+            ));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} of {} cases failed in {} of {} opcodes:\n{}",
+        failed_cases,
+        cases,
+        failures.len(),
+        files.len(),
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn test_sm83_case() {
+    // LD B, C
+    let test: Value = serde_json::from_str(
+        r#"{
+            "name": "41 0000",
+            "initial": {"pc": 49152, "sp": 65534, "a": 1, "b": 2, "c": 3, "d": 4, "e": 5,
+                        "f": 176, "h": 6, "l": 7, "ime": 0, "ie": 0, "ram": [[49152, 65]]},
+            "final": {"pc": 49153, "sp": 65534, "a": 1, "b": 3, "c": 3, "d": 4, "e": 5,
+                      "f": 176, "h": 6, "l": 7, "ime": 0, "ie": 0, "ram": [[49152, 65]]},
+            "cycles": [[49152, 65, "r-m"]]
+        }"#,
+    )
+    .unwrap();
+    assert!(run_test_file(std::slice::from_ref(&test)).is_empty());
+
+    // every failing case is returned
+    let mut broken = test;
+    broken["final"]["b"] = Value::from(2);
+    let failures = run_test_file(&[broken.clone(), broken.clone()]);
+    assert_eq!(failures.len(), 2);
+    assert!(failures[0].contains("b: 0003, expected 0002"));
+
+    // the bus activity of each cycle
+    broken["final"]["b"] = Value::from(3);
+    broken["cycles"][0][1] = Value::from(64);
+    assert!(run_test_file(&[broken.clone()])[0].contains("cycles"));
+    broken["cycles"] =
+        serde_json::from_str(r#"[[49152, 65, "r-m"], [49153, null, "---"]]"#).unwrap();
+    assert!(run_test_file(&[broken])[0].contains("cycles"));
+}
+
+#[test]
+fn test_sm83_case_cycles() {
+    // LD (HL), A, then INC BC, its idle cycle is only compared by the kind
+    let test: Value = serde_json::from_str(
+        r#"[{
+            "name": "77 0000",
+            "initial": {"pc": 49152, "sp": 65534, "a": 18, "b": 0, "c": 0, "d": 0, "e": 0,
+                        "f": 0, "h": 208, "l": 0, "ime": 0, "ie": 5, "ram": [[49152, 119]]},
+            "final": {"pc": 49153, "sp": 65534, "a": 18, "b": 0, "c": 0, "d": 0, "e": 0,
+                      "f": 0, "h": 208, "l": 0, "ime": 0, "ie": 5,
+                      "ram": [[49152, 119], [53248, 18]]},
+            "cycles": [[49152, 119, "r-m"], [53248, 18, "-wm"]]
+        }, {
+            "name": "03 0000",
+            "initial": {"pc": 49152, "sp": 65534, "a": 0, "b": 0, "c": 0, "d": 0, "e": 0,
+                        "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0, "ram": [[49152, 3]]},
+            "final": {"pc": 49153, "sp": 65534, "a": 0, "b": 0, "c": 1, "d": 0, "e": 0,
+                      "f": 0, "h": 0, "l": 0, "ime": 0, "ie": 0, "ram": [[49152, 3]]},
+            "cycles": [[49152, 3, "r-m"], [0, null, "---"]]
+        }]"#,
+    )
+    .unwrap();
+    assert!(run_test_file(test.as_array().unwrap()).is_empty());
+
+    let mut broken = test[0].clone();
+    broken["final"]["ie"] = Value::from(4);
+    assert!(run_test_file(&[broken])[0].contains("ie: 0005, expected 0004"));
+}
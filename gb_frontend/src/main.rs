@@ -1,3 +1,38 @@
-fn main() {
-    println!("Hello, world!");
+use gb_core::app::GameBoyApp;
+use std::env;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: gb_frontend record <rom> <wav> <frames> [--channels]";
+
+// record the audio of a headless run, --channels also writes every channel alone
+fn record(args: &[String]) -> Result<(), String> {
+    let [rom, wav, frames, options @ ..] = args else {
+        return Err(USAGE.to_string());
+    };
+    let frames: u32 = frames.parse().map_err(|_| USAGE.to_string())?;
+    let channel_tracks = match options {
+        [] => false,
+        [option] if option == "--channels" => true,
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let mut app = GameBoyApp::new(rom).map_err(|error| error.to_string())?;
+    app.boot();
+    app.record_audio(wav, frames, channel_tracks)
+        .map_err(|error| error.to_string())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.split_first() {
+        Some((command, args)) if command == "record" => record(args),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", error);
+            ExitCode::FAILURE
+        }
+    }
 }